@@ -10,3 +10,6 @@
 
 (print! "fact(10)= ")
 (println! (fact 10))
+
+(print! "fact(25)= ")
+(println! (fact 25))
@@ -0,0 +1,101 @@
+//! `#[derive(RispValue)]`, enabled in risp with the `derive` feature.
+//!
+//! For a struct with named fields, it implements `FromExp`, `IntoExp` and
+//! `RispRecord`, so the struct converts to and from a risp record. The
+//! record type and its fields are named in kebab case: `struct ServerConfig
+//! { max_conns: i64 }` becomes a `server-config` record, with the accessor
+//! `server-config-max-conns` once `Interpreter::define_record` is called.
+
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::{parse_macro_input, Data, DeriveInput, Fields};
+
+#[proc_macro_derive(RispValue)]
+pub fn derive_risp_value(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+fn expand(input: &DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(syn::Error::new_spanned(
+                    &input.ident,
+                    "RispValue can only be derived for structs with named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(syn::Error::new_spanned(
+                &input.ident,
+                "RispValue can only be derived for structs",
+            ))
+        }
+    };
+
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let name = kebab_case(&ident.to_string());
+    let idents: Vec<_> = fields.iter().map(|f| f.ident.as_ref().unwrap()).collect();
+    let names: Vec<String> = idents
+        .iter()
+        .map(|i| {
+            let i = i.to_string();
+            i.trim_start_matches("r#").replace('_', "-")
+        })
+        .collect();
+
+    Ok(quote! {
+        impl #impl_generics ::risp::types::FromExp for #ident #ty_generics #where_clause {
+            fn from_exp(
+                exp: &::risp::types::Exp,
+            ) -> ::std::result::Result<Self, ::risp::types::Exceptions> {
+                let record = ::risp::types::Record::of(exp, #name)?;
+                ::std::result::Result::Ok(#ident {
+                    #(#idents: ::risp::types::FromExp::from_exp(record.field(#names)?)?,)*
+                })
+            }
+        }
+
+        impl #impl_generics ::risp::types::IntoExp for #ident #ty_generics #where_clause {
+            fn into_exp(self) -> ::risp::types::Exp {
+                let fields = ::std::vec![
+                    #((
+                        ::std::string::String::from(#names),
+                        ::risp::types::IntoExp::into_exp(self.#idents),
+                    ),)*
+                ];
+                ::risp::types::Exp::Record(::std::rc::Rc::new(
+                    ::risp::types::Record::new(#name, fields),
+                ))
+            }
+        }
+
+        impl #impl_generics ::risp::types::RispRecord for #ident #ty_generics #where_clause {
+            const NAME: &'static str = #name;
+            const FIELDS: &'static [&'static str] = &[#(#names),*];
+        }
+    })
+}
+
+/// `ServerConfig` becomes `server-config` and `HTTPServer` `http-server`.
+fn kebab_case(name: &str) -> String {
+    let chars: Vec<char> = name.chars().collect();
+    let mut out = String::new();
+    for (i, &c) in chars.iter().enumerate() {
+        if c.is_uppercase() && i > 0 {
+            let prev = chars[i - 1];
+            let next_lower = chars.get(i + 1).is_some_and(|n| n.is_lowercase());
+            if prev.is_lowercase() || prev.is_numeric() || (prev.is_uppercase() && next_lower) {
+                out.push('-');
+            }
+        }
+        out.extend(c.to_lowercase());
+    }
+    out.replace('_', "-")
+}
@@ -0,0 +1,84 @@
+use risp::types::{FromExp, IntoExp, RispRecord};
+use risp::{Interpreter, RispValue};
+
+#[derive(RispValue, Debug, Clone, PartialEq)]
+struct ServerConfig {
+    host: String,
+    max_conns: i64,
+    timeout: Option<f64>,
+    tags: Vec<String>,
+}
+
+#[derive(RispValue, Debug, PartialEq)]
+struct HTTPPair {
+    left: ServerConfig,
+    right: ServerConfig,
+}
+
+fn config() -> ServerConfig {
+    ServerConfig {
+        host: "localhost".to_string(),
+        max_conns: 8,
+        timeout: None,
+        tags: vec!["a".to_string()],
+    }
+}
+
+#[test]
+fn records() {
+    assert_eq!(ServerConfig::NAME, "server-config");
+    assert_eq!(
+        ServerConfig::FIELDS,
+        &["host", "max-conns", "timeout", "tags"]
+    );
+    assert_eq!(HTTPPair::NAME, "http-pair");
+
+    let exp = config().into_exp();
+    assert_eq!(
+        format!("{}", exp),
+        "#<server-config host=localhost max-conns=8 timeout=#f tags=(a)>"
+    );
+    assert_eq!(ServerConfig::from_exp(&exp).unwrap(), config());
+
+    let mut interp = Interpreter::new();
+    interp.define_record::<ServerConfig>();
+    interp.set_global("config", config());
+    let n: i64 = interp.call("server-config-max-conns", (config(),)).unwrap();
+    assert_eq!(n, 8);
+    let updated: ServerConfig = interp
+        .eval_str(
+            "(make-server-config (server-config-host config)
+                                 (* 2 (server-config-max-conns config))
+                                 2.5
+                                 '(\"a\" \"b\"))",
+        )
+        .and_then(|x| ServerConfig::from_exp(&x))
+        .unwrap();
+    assert_eq!(updated.max_conns, 16);
+    assert_eq!(updated.timeout, Some(2.5));
+    assert_eq!(updated.tags, vec!["a", "b"]);
+    let ans = interp
+        .eval_str("(list (server-config? config) (server-config? 1))")
+        .unwrap();
+    assert_eq!(format!("{}", ans), "(#t #f)");
+
+    let err = interp
+        .eval_str("(make-server-config \"h\" 1.5 #f '())")
+        .unwrap_err();
+    assert_eq!(
+        format!("{}", err.root()),
+        "type error: expected an integer, got number 1.5"
+    );
+    let err = interp.eval_str("(server-config-host 1)").unwrap_err();
+    assert_eq!(
+        format!("{}", err.root()),
+        "type error: expected a server-config record, got number 1"
+    );
+
+    let pair = HTTPPair {
+        left: config(),
+        right: updated.clone(),
+    };
+    let back = HTTPPair::from_exp(&pair.into_exp()).unwrap();
+    assert_eq!(back.right, updated);
+}
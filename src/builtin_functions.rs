@@ -1,242 +1,408 @@
+use super::eval;
+use super::expander;
+use super::number::parse_number;
+use super::parser::read_prefix;
 use super::types::*;
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use std::cell::{Cell, RefCell, RefMut};
+use std::cmp::Ordering;
+use std::convert::TryFrom;
 use std::rc::Rc;
-use std::{f64, i64};
-pub fn logical_bin_ops(args: &[Exp], f: fn(bool, bool) -> bool) -> Result<Exp, Exceptions> {
-    let evaluated: Result<Vec<bool>, Exceptions> = args
-        .into_iter()
-        .map(|x| -> Result<bool, Exceptions> { get_bool(x) })
-        .collect();
-    let ans = evaluated?.iter().fold(true, |acc, x| f(acc, *x));
-    return Ok(Exp::Atom(Atom::Bool(ans)));
+/// Whether `f` holds for every pair of neighbouring arguments, e.g.
+/// `(< a b c)` is `(and (< a b) (< b c))`. Numbers are compared exactly and
+/// comparisons with NaN are false.
+pub fn compare_chain(args: &[Exp], name: &str, f: fn(Ordering) -> bool) -> Result<Exp, Exceptions> {
+    expect_atleast_x_args(1, name, args)?;
+    let numbers = args.iter().map(get_number).collect::<Result<Vec<_>, _>>()?;
+    let ans = numbers
+        .windows(2)
+        .all(|pair| pair[0].compare(pair[1]).is_some_and(f));
+    Ok(Exp::Atom(Atom::Bool(ans)))
+}
+
+pub fn add(args: &[Exp], _env: &mut Environment) -> Result<Exp, Exceptions> {
+    let mut sum = Number::Int(0);
+    for x in args {
+        sum = sum.add(get_number(x)?)?;
+    }
+    Ok(sum.into_exp())
+}
+
+pub fn multiply(args: &[Exp], _env: &mut Environment) -> Result<Exp, Exceptions> {
+    let mut product = Number::Int(1);
+    for x in args {
+        product = product.mul(get_number(x)?)?;
+    }
+    Ok(product.into_exp())
+}
+
+pub fn minus(args: &[Exp], _env: &mut Environment) -> Result<Exp, Exceptions> {
+    expect_atleast_x_args(1, "-", args)?;
+    let (head, tail) = args.split_first().unwrap();
+    let first = get_number(head)?;
+    if tail.is_empty() {
+        return Ok(first.neg()?.into_exp());
+    }
+    let mut ans = first.clone();
+    for x in tail {
+        ans = ans.sub(get_number(x)?)?;
+    }
+    Ok(ans.into_exp())
+}
+
+pub fn divide(args: &[Exp], _env: &mut Environment) -> Result<Exp, Exceptions> {
+    expect_atleast_x_args(1, "/", args)?;
+    let (head, tail) = args.split_first().unwrap();
+    let first = get_number(head)?;
+    if tail.is_empty() {
+        return Ok(Number::Int(1).div(first)?.into_exp());
+    }
+    let mut ans = first.clone();
+    for x in tail {
+        ans = ans.div(get_number(x)?)?;
+    }
+    Ok(ans.into_exp())
+}
+
+pub fn fmod(args: &[Exp], _env: &mut Environment) -> Result<Exp, Exceptions> {
+    let _ = expect_x_args(2, "fmod", args)?;
+    Ok(Exp::Atom(Atom::Number(Number::Float(
+        get_float(&args[0])? % get_float(&args[1])?,
+    ))))
+}
+
+/// `(mod a b)` is the remainder of `a` and `b`, like `remainder`.
+pub fn mod_int(args: &[Exp], _env: &mut Environment) -> Result<Exp, Exceptions> {
+    Ok(divide_integers(args, "mod", false)?.1.into_exp())
+}
+
+/// The quotient and remainder of dividing two integers, see
+/// `Number::div_rem`.
+pub fn divide_integers(
+    args: &[Exp],
+    name: &str,
+    floor: bool,
+) -> Result<(Number, Number), Exceptions> {
+    expect_x_args(2, name, args)?;
+    get_integer(&args[0])?.div_rem(get_integer(&args[1])?, floor)
+}
+
+pub fn gcd(args: &[Exp], _env: &mut Environment) -> Result<Exp, Exceptions> {
+    let mut ans = Number::Int(0);
+    for x in args {
+        ans = ans.gcd(get_integer(x)?);
+    }
+    Ok(ans.into_exp())
+}
+
+pub fn lcm(args: &[Exp], _env: &mut Environment) -> Result<Exp, Exceptions> {
+    let mut ans = Number::Int(1);
+    for x in args {
+        ans = ans.lcm(get_integer(x)?);
+    }
+    Ok(ans.into_exp())
+}
+
+pub fn exact_integer_sqrt(args: &[Exp], _env: &mut Environment) -> Result<Exp, Exceptions> {
+    expect_x_args(1, "exact-integer-sqrt", args)?;
+    match &args[0] {
+        Exp::Atom(Atom::Number(n))
+            if n.is_exact()
+                && n.is_integer()
+                && n.compare(&Number::Int(0)) != Some(Ordering::Less) =>
+        {
+            Ok(n.exact_integer_sqrt().into_exp())
+        }
+        x => Err(Exceptions::type_error("an exact non-negative integer", x)),
+    }
+}
+
+pub fn parity(args: &[Exp], name: &str, even: bool) -> Result<Exp, Exceptions> {
+    expect_x_args(1, name, args)?;
+    Ok(Exp::Atom(Atom::Bool(
+        get_integer(&args[0])?.is_even() == even,
+    )))
+}
+
+/// Compares a number against zero. Comparisons with NaN are false.
+pub fn sign_test(args: &[Exp], name: &str, f: fn(Ordering) -> bool) -> Result<Exp, Exceptions> {
+    expect_x_args(1, name, args)?;
+    let sign = get_number(&args[0])?.compare(&Number::Int(0));
+    Ok(Exp::Atom(Atom::Bool(sign.is_some_and(f))))
 }
 
-pub fn binary_cmp(args: &[Exp], f: fn(f64, f64) -> bool) -> Result<Exp, Exceptions> {
-    if args.len() != 2 {
-        return Err(Exceptions::ValueError(
-            format!("expected two arguments for comparision got {}", args.len()).to_string(),
-        ));
+/// Applies `f` to a number as a float. The result is inexact, except that
+/// `exact_at.0` maps to the exact `exact_at.1`, e.g. `(exp 0)` is `1`.
+pub fn float_fn(
+    args: &[Exp],
+    name: &str,
+    f: fn(f64) -> f64,
+    exact_at: (i64, i64),
+) -> Result<Exp, Exceptions> {
+    expect_x_args(1, name, args)?;
+    let x = get_number(&args[0])?;
+    if *x == Number::Int(exact_at.0) {
+        Ok(Number::Int(exact_at.1).into_exp())
     } else {
-        let (a, b) = (get_float(&args[0])?, get_float(&args[1])?);
-        return Ok(Exp::Atom(Atom::Bool(f(a, b))));
+        Ok(Number::Float(f(x.to_f64())).into_exp())
     }
 }
 
-pub fn binary_op_arith(args: &[Exp], init: f64, f: fn(f64, f64) -> f64) -> Result<Exp, Exceptions> {
-    let evaluated: Result<Vec<f64>, Exceptions> = args
-        .into_iter()
-        .map(|x| -> Result<f64, Exceptions> { get_float(x) })
-        .collect();
-    let ans = evaluated?.iter().fold(init as f64, |sum, x| f(sum, *x));
-    let rounded_down = ans.floor();
-    if ans == rounded_down {
-        return Ok(Exp::Atom(Atom::Number(Number::Int(rounded_down as i64))));
+fn expect_one_or_two_args(name: &str, args: &[Exp]) -> Result<(), Exceptions> {
+    if args.is_empty() || args.len() > 2 {
+        Err(Exceptions::ArityMismatch {
+            name: name.to_string(),
+            expected: "1 or 2".to_string(),
+            got: args.len(),
+        })
     } else {
-        return Ok(Exp::Atom(Atom::Number(Number::Float(ans))));
-    }
-}
-
-pub fn minus(args: &[Exp]) -> Result<Exp, Exceptions> {
-    let (head, tail) = args
-        .split_first()
-        .ok_or(Exceptions::ValueError(
-            "- must have one argument at least".to_string(),
-        ))
-        .unwrap();
-    let first = get_float(head)?;
-    let rem_sum = binary_op_arith(tail, 0_f64, |x, y| x + y)?;
-    let ans = first - (get_float(&rem_sum)?);
-    let rounded_down = ans.floor();
-    if ans == rounded_down {
-        if tail.is_empty() {
-            return Ok(Exp::Atom(Atom::Number(Number::Int(-rounded_down as i64))));
+        Ok(())
+    }
+}
+
+/// Checks that there are between `min` and `max` arguments.
+fn expect_args_between(min: usize, max: usize, name: &str, args: &[Exp]) -> Result<(), Exceptions> {
+    if args.len() < min || args.len() > max {
+        let expected = if max == min + 1 {
+            format!("{} or {}", min, max)
         } else {
-            return Ok(Exp::Atom(Atom::Number(Number::Int(rounded_down as i64))));
-        }
+            format!("{} to {}", min, max)
+        };
+        Err(Exceptions::ArityMismatch {
+            name: name.to_string(),
+            expected,
+            got: args.len(),
+        })
     } else {
-        if tail.is_empty() {
-            return Ok(Exp::Atom(Atom::Number(Number::Float(-ans))));
-        } else {
-            return Ok(Exp::Atom(Atom::Number(Number::Float(ans))));
+        Ok(())
+    }
+}
+
+/// `(log x)` is the natural logarithm and `(log x b)` the logarithm in
+/// base `b`.
+pub fn log(args: &[Exp], _env: &mut Environment) -> Result<Exp, Exceptions> {
+    expect_one_or_two_args("log", args)?;
+    if args.len() == 1 {
+        return float_fn(args, "log", f64::ln, (1, 0));
+    }
+    let (x, base) = (get_number(&args[0])?.to_f64(), get_number(&args[1])?);
+    let ans = match base {
+        Number::Int(2) => x.log2(),
+        Number::Int(10) => x.log10(),
+        _ => x.ln() / base.to_f64().ln(),
+    };
+    Ok(Number::Float(ans).into_exp())
+}
+
+/// `(atan y x)` is the angle of the point `(x, y)`.
+pub fn atan(args: &[Exp], _env: &mut Environment) -> Result<Exp, Exceptions> {
+    expect_one_or_two_args("atan", args)?;
+    if args.len() == 1 {
+        return float_fn(args, "atan", f64::atan, (0, 0));
+    }
+    let (y, x) = (
+        get_number(&args[0])?.to_f64(),
+        get_number(&args[1])?.to_f64(),
+    );
+    Ok(Number::Float(y.atan2(x)).into_exp())
+}
+
+fn get_radix(name: &str, args: &[Exp]) -> Result<u32, Exceptions> {
+    match args.get(1) {
+        None => Ok(10),
+        Some(Exp::Atom(Atom::Number(Number::Int(x)))) if [2, 8, 10, 16].contains(x) => {
+            Ok(*x as u32)
         }
+        Some(x) => Err(Exceptions::ValueError(format!(
+            "{}: radix must be 2, 8, 10 or 16, got {}",
+            name, x
+        ))),
     }
 }
 
-pub fn divide(args: &[Exp]) -> Result<Exp, Exceptions> {
-    let (head, tail) = args
-        .split_first()
-        .ok_or(Exceptions::ValueError(
-            "/ must have one argument at least".to_string(),
-        ))
-        .unwrap();
-    let first = get_float(head)?;
-    let rem_sum = binary_op_arith(tail, 1_f64, |x, y| x * y)?;
-    let ans = first * 1_f64 / (get_float(&rem_sum)?);
-    if tail.is_empty() {
-        return Ok(Exp::Atom(Atom::Number(Number::Float(1_f64 / ans))));
-    } else {
-        return Ok(Exp::Atom(Atom::Number(Number::Float(ans))));
+/// `(number->string z radix)` writes `z` in base `radix`, 10 by default.
+pub fn number_to_string(args: &[Exp], _env: &mut Environment) -> Result<Exp, Exceptions> {
+    expect_one_or_two_args("number->string", args)?;
+    let n = get_number(&args[0])?;
+    let radix = get_radix("number->string", args)?;
+    match n.to_string_radix(radix) {
+        Some(s) => Ok(s.into_exp()),
+        None => Err(Exceptions::ValueError(format!(
+            "number->string: {} is inexact and can only be written in radix 10",
+            n
+        ))),
     }
 }
 
-pub fn fmod(args: &[Exp]) -> Result<Exp, Exceptions> {
-    let _ = expect_x_args(2, "fmod", args)?;
-    return Ok(Exp::Atom(Atom::Number(Number::Float(
-        get_float(&args[0])? % get_float(&args[1])?,
-    ))));
+/// `(string->number s radix)` reads a number written in base `radix`, 10 by
+/// default, or returns `#f` if `s` isn't one.
+pub fn string_to_number(args: &[Exp], _env: &mut Environment) -> Result<Exp, Exceptions> {
+    expect_one_or_two_args("string->number", args)?;
+    let radix = get_radix("string->number", args)?;
+    match &args[0] {
+        Exp::Str(s) => Ok(match parse_number(&s.borrow(), radix) {
+            Some(n) => n.into_exp(),
+            None => Exp::Atom(Atom::Bool(false)),
+        }),
+        x => Err(Exceptions::type_error("a string", x)),
+    }
 }
 
-pub fn mod_int(args: &[Exp]) -> Result<Exp, Exceptions> {
-    let _ = expect_x_args(2, "mod", args)?;
-    return Ok(Exp::Atom(Atom::Number(Number::Int(
-        get_int(&args[0])? % get_int(&args[1])?,
-    ))));
+pub fn sqrt(args: &[Exp], _env: &mut Environment) -> Result<Exp, Exceptions> {
+    expect_x_args(1, "sqrt", args)?;
+    Ok(get_number(&args[0])?.sqrt().into_exp())
 }
 
-pub fn logical_not(args: &[Exp]) -> Result<Exp, Exceptions> {
+pub fn square(args: &[Exp], _env: &mut Environment) -> Result<Exp, Exceptions> {
+    expect_x_args(1, "square", args)?;
+    let x = get_number(&args[0])?;
+    Ok(x.mul(x)?.into_exp())
+}
+
+pub fn rounding(args: &[Exp], name: &str, f: fn(&Number) -> Number) -> Result<Exp, Exceptions> {
+    expect_x_args(1, name, args)?;
+    Ok(f(get_number(&args[0])?).into_exp())
+}
+
+pub fn is_exact_integer(args: &[Exp], _env: &mut Environment) -> Result<Exp, Exceptions> {
+    expect_x_args(1, "exact-integer?", args)?;
+    let ans = matches!(&args[0], Exp::Atom(Atom::Number(n)) if n.is_exact() && n.is_integer());
+    Ok(Exp::Atom(Atom::Bool(ans)))
+}
+
+/// Applies `f` to a float. Exact numbers are all finite, so they are
+/// tested as `0.0`.
+pub fn float_test(args: &[Exp], name: &str, f: fn(f64) -> bool) -> Result<Exp, Exceptions> {
+    expect_x_args(1, name, args)?;
+    let ans = match get_number(&args[0])? {
+        Number::Float(x) => f(*x),
+        _ => f(0.0),
+    };
+    Ok(Exp::Atom(Atom::Bool(ans)))
+}
+
+pub fn logical_not(args: &[Exp], _env: &mut Environment) -> Result<Exp, Exceptions> {
     let _ = expect_x_args(1, "not", args)?;
-    let operand = get_bool(&args[0])?;
-    return Ok(Exp::Atom(Atom::Bool(!operand)));
+    Ok(Exp::Atom(Atom::Bool(!args[0].is_truthy())))
 }
 
-pub fn absolute_val(args: &[Exp]) -> Result<Exp, Exceptions> {
+pub fn absolute_val(args: &[Exp], _env: &mut Environment) -> Result<Exp, Exceptions> {
     let _ = expect_x_args(1, "abs", args)?;
-    match &args[0] {
-        Exp::Atom(Atom::Number(x)) => match x {
-            Number::Int(y) => Ok(Exp::Atom(Atom::Number(Number::Int(y.abs())))),
-            Number::Float(y) => Ok(Exp::Atom(Atom::Number(Number::Float(y.abs())))),
-        },
-        _ => Err(Exceptions::ValueError(
-            "Number not provided to abs".to_string(),
-        )),
-    }
+    Ok(get_number(&args[0])?.abs()?.into_exp())
 }
 
-pub fn power(args: &[Exp]) -> Result<Exp, Exceptions> {
+pub fn power(args: &[Exp], _env: &mut Environment) -> Result<Exp, Exceptions> {
     let _ = expect_x_args(2, "expt", args)?;
-    return Ok(Exp::Atom(Atom::Number(Number::Float(
-        get_float(&args[0])?.powf(get_float(&args[1])?),
-    ))));
+    let (base, exponent) = (get_number(&args[0])?, get_number(&args[1])?);
+    Ok(base.pow(exponent)?.into_exp())
 }
 
-pub fn begin(args: &[Exp]) -> Result<Exp, Exceptions> {
-    if args.is_empty() {
-        return Err(Exceptions::ValueError(
-            "Expected atleast one expression after begin".to_string(),
-        ));
-    } else {
-        return Ok(args.last().unwrap().clone());
-    }
+pub fn list(args: &[Exp], _env: &mut Environment) -> Result<Exp, Exceptions> {
+    Ok(Exp::List(Rc::new(args.to_vec().into())))
 }
 
-pub fn append(args: &[Exp]) -> Result<Exp, Exceptions> {
+pub fn append(args: &[Exp], _env: &mut Environment) -> Result<Exp, Exceptions> {
     let mut ret_list = vec![];
     for exps in args {
         match exps {
             Exp::List(vals) => {
-                for e in vals.as_ref() {
+                for e in vals.iter() {
                     ret_list.push(e.clone());
                 }
             }
             _ => ret_list.push(exps.clone()),
         }
     }
-    Ok(Exp::List(Rc::new(ret_list)))
+    Ok(Exp::List(Rc::new(ret_list.into())))
 }
 
-pub fn apply(args: &[Exp]) -> Result<Exp, Exceptions> {
-    expect_x_args(2, "apply", args)?;
-    if let Exp::Func(f) = &args[0] {
-        if let Exp::List(params) = &args[1] {
-            return f(params);
-        } else {
-            return Err(Exceptions::ValueError(
-                "Expected a list as second argument to apply. Got something else".to_string(),
-            ));
-        }
-    } else {
-        return Err(Exceptions::ValueError(
-            "Expected the first argument for apply to be a function".to_string(),
-        ));
+/// (apply f arg ... list) calls `f` with the `arg`s followed by the
+/// elements of `list`.
+pub fn apply(args: &[Exp], env: &mut Environment) -> Result<Exp, Exceptions> {
+    expect_atleast_x_args(2, "apply", args)?;
+    let (last, init) = args[1..].split_last().unwrap();
+    let mut call_args = init.to_vec();
+    match last {
+        Exp::List(lst) => call_args.extend(lst.iter().cloned()),
+        _ => return Err(Exceptions::type_error("a list", last)),
     }
+    eval::apply(&args[0], call_args, env)
 }
 
-pub fn cons(args: &[Exp]) -> Result<Exp, Exceptions> {
+pub fn cons(args: &[Exp], _env: &mut Environment) -> Result<Exp, Exceptions> {
     expect_x_args(2, "cons", args)?;
     if let Exp::List(lst) = &args[1] {
-        let ret_list = [[args[0].clone()].to_vec(), lst.as_ref().clone()].concat();
-        Ok(Exp::List(Rc::new(ret_list)))
+        let ret_list = [[args[0].clone()].to_vec(), lst.to_vec()].concat();
+        Ok(Exp::List(Rc::new(ret_list.into())))
     } else {
-        return Err(Exceptions::ValueError(
-            "Expected a list as second argument to cons. Got something else".to_string(),
-        ));
+        Err(Exceptions::type_error("a list", &args[1]))
     }
 }
 
-fn head_tails<'a>(args: &'a [Exp]) -> Result<(&'a Exp, &'a [Exp]), Exceptions> {
-    let _ = expect_x_args(1, "car", args)?;
+fn head_tails<'a>(args: &'a [Exp], func_name: &str) -> Result<(&'a Exp, &'a [Exp]), Exceptions> {
+    let _ = expect_x_args(1, func_name, args)?;
     if let Exp::List(lst) = &args[0] {
-        let (head, tails) = lst
-            .split_first()
-            .ok_or(Exceptions::ValueError(
-                "The list should have length atleast 1".to_string(),
+        lst.split_first().ok_or_else(|| {
+            Exceptions::ValueError(format!(
+                "{}: the list should have length atleast 1",
+                func_name
             ))
-            .unwrap();
-        return Ok((head, tails));
+        })
     } else {
-        Err(Exceptions::ValueError(
-            "Expected a list after car".to_string(),
-        ))
+        Err(Exceptions::type_error("a list", &args[0]))
     }
 }
 fn same_object(a: &Exp, b: &Exp) -> bool {
     match a {
         Exp::List(lst1) => {
             if let Exp::List(lst2) = b {
-                lst1.as_ref() as *const Vec<Exp> == lst2.as_ref() as *const Vec<Exp>
+                std::ptr::eq(lst1.as_ref(), lst2.as_ref())
             } else {
                 false
             }
         }
+        Exp::Str(s1) => matches!(b, Exp::Str(s2) if Rc::ptr_eq(s1, s2)),
         _ => a == b,
     }
     // a as *const Exp == b as *const Exp
 }
-pub fn same_obj(args: &[Exp]) -> Result<Exp, Exceptions> {
+pub fn same_obj(args: &[Exp], _env: &mut Environment) -> Result<Exp, Exceptions> {
     expect_x_args(2, "equal?", args)?;
-    return Ok(Exp::Atom(Atom::Bool(same_object(&args[0], &args[1]))));
+    Ok(Exp::Atom(Atom::Bool(same_object(&args[0], &args[1]))))
 }
-pub fn equal(args: &[Exp]) -> Result<Exp, Exceptions> {
+pub fn equal(args: &[Exp], _env: &mut Environment) -> Result<Exp, Exceptions> {
     expect_x_args(2, "equal?", args)?;
-    return Ok(Exp::Atom(Atom::Bool(&args[0] == &args[1])));
+    Ok(Exp::Atom(Atom::Bool(args[0] == args[1])))
 }
 
-pub fn length(args: &[Exp]) -> Result<Exp, Exceptions> {
+pub fn length(args: &[Exp], _env: &mut Environment) -> Result<Exp, Exceptions> {
     expect_x_args(1, "length", args)?;
     if let Exp::List(lst) = &args[0] {
         Ok(Exp::Atom(Atom::Number(Number::Int(lst.len() as i64))))
     } else {
-        Err(Exceptions::ValueError(
-            "non list type passed to length function".to_string(),
-        ))
+        Err(Exceptions::type_error("a list", &args[0]))
     }
 }
 
-pub fn is_list(args: &[Exp]) -> Result<Exp, Exceptions> {
+pub fn is_list(args: &[Exp], _env: &mut Environment) -> Result<Exp, Exceptions> {
     expect_x_args(1, "list?", args)?;
     if let Exp::List(_) = &args[0] {
-        return Ok(Exp::Atom(Atom::Bool(true)));
+        Ok(Exp::Atom(Atom::Bool(true)))
     } else {
-        return Ok(Exp::Atom(Atom::Bool(false)));
+        Ok(Exp::Atom(Atom::Bool(false)))
     }
 }
 
-pub fn car(args: &[Exp]) -> Result<Exp, Exceptions> {
-    return Ok(head_tails(args)?.0.clone());
+pub fn car(args: &[Exp], _env: &mut Environment) -> Result<Exp, Exceptions> {
+    Ok(head_tails(args, "car")?.0.clone())
 }
-pub fn cdr(args: &[Exp]) -> Result<Exp, Exceptions> {
-    return Ok(Exp::List(Rc::new(head_tails(args)?.1.to_vec())));
+pub fn cdr(args: &[Exp], _env: &mut Environment) -> Result<Exp, Exceptions> {
+    Ok(Exp::List(Rc::new(
+        head_tails(args, "cdr")?.1.to_vec().into(),
+    )))
 }
-pub fn is_null(args: &[Exp]) -> Result<Exp, Exceptions> {
+pub fn is_null(args: &[Exp], _env: &mut Environment) -> Result<Exp, Exceptions> {
     expect_x_args(1, "null?", args)?;
     if let Exp::List(lst) = &args[0] {
         Ok(Exp::Atom(Atom::Bool(lst.is_empty())))
@@ -245,26 +411,27 @@ pub fn is_null(args: &[Exp]) -> Result<Exp, Exceptions> {
     }
 }
 
-pub fn min_max(args: &[Exp], funcname: &str, f: fn(f64, f64) -> f64) -> Result<Exp, Exceptions> {
+/// The argument that compares as `keep` against all the others, made
+/// inexact if any argument is.
+pub fn min_max(args: &[Exp], funcname: &str, keep: Ordering) -> Result<Exp, Exceptions> {
     expect_atleast_x_args(1, funcname, args)?;
-    let evaluated: Result<Vec<f64>, Exceptions> = args
-        .into_iter()
-        .map(|x| -> Result<f64, Exceptions> { get_float(x) })
-        .collect();
-    let evaluated = evaluated?;
-    let mut ans = evaluated[0].clone();
-    for elem in evaluated {
-        ans = f(ans, elem);
-    }
-    let rounded_down = ans.floor();
-    if ans == rounded_down {
-        return Ok(Exp::Atom(Atom::Number(Number::Int(rounded_down as i64))));
+    let mut ans = get_number(&args[0])?;
+    let mut exact = true;
+    for x in args {
+        let x = get_number(x)?;
+        exact &= x.is_exact();
+        if x.compare(ans) == Some(keep) {
+            ans = x;
+        }
+    }
+    if exact {
+        Ok(ans.clone().into_exp())
     } else {
-        return Ok(Exp::Atom(Atom::Number(Number::Float(ans))));
+        Ok(ans.to_inexact().into_exp())
     }
 }
 
-pub fn is_number(args: &[Exp]) -> Result<Exp, Exceptions> {
+pub fn is_number(args: &[Exp], _env: &mut Environment) -> Result<Exp, Exceptions> {
     expect_x_args(1, "number?", args)?;
     if let Exp::Atom(Atom::Number(_)) = &args[0] {
         Ok(Exp::Atom(Atom::Bool(true)))
@@ -273,15 +440,57 @@ pub fn is_number(args: &[Exp]) -> Result<Exp, Exceptions> {
     }
 }
 
-pub fn is_proc(args: &[Exp]) -> Result<Exp, Exceptions> {
-    expect_x_args(1, "procedure?", args)?;
-    match &args[0] {
-        Exp::Func(_) => Ok(Exp::Atom(Atom::Bool(true))),
-        Exp::Procedure(_) => Ok(Exp::Atom(Atom::Bool(true))),
-        _ => Ok(Exp::Atom(Atom::Bool(false))),
+pub fn is_exact(args: &[Exp], _env: &mut Environment) -> Result<Exp, Exceptions> {
+    expect_x_args(1, "exact?", args)?;
+    Ok(Exp::Atom(Atom::Bool(get_number(&args[0])?.is_exact())))
+}
+
+pub fn is_inexact(args: &[Exp], _env: &mut Environment) -> Result<Exp, Exceptions> {
+    expect_x_args(1, "inexact?", args)?;
+    Ok(Exp::Atom(Atom::Bool(!get_number(&args[0])?.is_exact())))
+}
+
+pub fn exact_to_inexact(args: &[Exp], _env: &mut Environment) -> Result<Exp, Exceptions> {
+    expect_x_args(1, "exact->inexact", args)?;
+    Ok(get_number(&args[0])?.to_inexact().into_exp())
+}
+
+pub fn inexact_to_exact(args: &[Exp], _env: &mut Environment) -> Result<Exp, Exceptions> {
+    expect_x_args(1, "inexact->exact", args)?;
+    Ok(get_number(&args[0])?.to_exact()?.into_exp())
+}
+
+fn fraction_part(x: &Exp, part: fn(&BigRational) -> &BigInt) -> Result<Exp, Exceptions> {
+    let n = get_number(x)?;
+    let part = Number::from_bigint(part(&n.to_exact()?.to_rational().unwrap()).clone());
+    if n.is_exact() {
+        Ok(part.into_exp())
+    } else {
+        Ok(part.to_inexact().into_exp())
     }
 }
-pub fn is_bool(args: &[Exp]) -> Result<Exp, Exceptions> {
+
+pub fn numerator(args: &[Exp], _env: &mut Environment) -> Result<Exp, Exceptions> {
+    expect_x_args(1, "numerator", args)?;
+    fraction_part(&args[0], BigRational::numer)
+}
+
+pub fn denominator(args: &[Exp], _env: &mut Environment) -> Result<Exp, Exceptions> {
+    expect_x_args(1, "denominator", args)?;
+    fraction_part(&args[0], BigRational::denom)
+}
+
+pub fn rationalize(args: &[Exp], _env: &mut Environment) -> Result<Exp, Exceptions> {
+    expect_x_args(2, "rationalize", args)?;
+    let (x, y) = (get_number(&args[0])?, get_number(&args[1])?);
+    Ok(x.rationalize(y)?.into_exp())
+}
+
+pub fn is_proc(args: &[Exp], _env: &mut Environment) -> Result<Exp, Exceptions> {
+    expect_x_args(1, "procedure?", args)?;
+    Ok(Exp::Atom(Atom::Bool(args[0].is_procedure())))
+}
+pub fn is_bool(args: &[Exp], _env: &mut Environment) -> Result<Exp, Exceptions> {
     expect_x_args(1, "bool?", args)?;
     if let Exp::Atom(Atom::Bool(_)) = &args[0] {
         Ok(Exp::Atom(Atom::Bool(true)))
@@ -290,55 +499,757 @@ pub fn is_bool(args: &[Exp]) -> Result<Exp, Exceptions> {
     }
 }
 
-pub fn map(args: &[Exp]) -> Result<Exp, Exceptions> {
-    expect_x_args(2, "map", args)?;
-    println!("hi");
-    let is_callable = get_bool(&is_proc(&args[..1])?)?;
-    if is_callable {
-        if let Exp::List(lst) = &args[1] {
-            let mut mapped_list = vec![Exp::Atom(Atom::Symbol("list".to_string()))];
-            for x in lst.iter() {
-                mapped_list.push(Exp::List(Rc::new(vec![args[0].clone(), x.clone()])));
-            }
-            Ok(Exp::List(Rc::new(mapped_list)))
+/// The procedure argument of `func_name` followed by the elements of its
+/// list arguments taken one from each list at a time, up to the length of
+/// the shortest list.
+fn proc_and_rows<'a>(
+    args: &'a [Exp],
+    func_name: &str,
+) -> Result<(&'a Exp, Vec<Vec<Exp>>), Exceptions> {
+    expect_atleast_x_args(2, func_name, args)?;
+    let f = &args[0];
+    if !f.is_procedure() {
+        return Err(Exceptions::type_error("a procedure", f));
+    }
+    let lists = args[1..]
+        .iter()
+        .map(|x| match x {
+            Exp::List(lst) => Ok(lst),
+            _ => Err(Exceptions::type_error("a list", x)),
+        })
+        .collect::<Result<Vec<_>, Exceptions>>()?;
+    let len = lists.iter().map(|lst| lst.len()).min().unwrap_or(0);
+    let rows = (0..len)
+        .map(|i| lists.iter().map(|lst| lst[i].clone()).collect())
+        .collect();
+    Ok((f, rows))
+}
+
+pub fn map(args: &[Exp], env: &mut Environment) -> Result<Exp, Exceptions> {
+    let (f, rows) = proc_and_rows(args, "map")?;
+    let mapped = rows
+        .into_iter()
+        .map(|row| eval::apply(f, row, env))
+        .collect::<Result<Vec<Exp>, Exceptions>>()?;
+    Ok(Exp::List(Rc::new(mapped.into())))
+}
+
+pub fn for_each(args: &[Exp], env: &mut Environment) -> Result<Exp, Exceptions> {
+    let (f, rows) = proc_and_rows(args, "for-each")?;
+    for row in rows {
+        eval::apply(f, row, env)?;
+    }
+    Ok(Exp::Atom(Atom::Bool(false)))
+}
+
+pub fn filter(args: &[Exp], env: &mut Environment) -> Result<Exp, Exceptions> {
+    expect_x_args(2, "filter", args)?;
+    let (pred, rows) = proc_and_rows(args, "filter")?;
+    let mut kept = vec![];
+    for mut row in rows {
+        if eval::apply(pred, row.clone(), env)?.is_truthy() {
+            kept.push(row.remove(0));
+        }
+    }
+    Ok(Exp::List(Rc::new(kept.into())))
+}
+
+/// (fold kons knil list ...) calls `(kons elem ... acc)` for the elements
+/// of the lists from left to right, starting with `knil` as `acc`.
+pub fn fold(args: &[Exp], env: &mut Environment) -> Result<Exp, Exceptions> {
+    expect_atleast_x_args(3, "fold", args)?;
+    let mut lists = vec![args[0].clone()];
+    lists.extend(args[2..].iter().cloned());
+    let (kons, rows) = proc_and_rows(&lists, "fold")?;
+    let mut acc = args[1].clone();
+    for mut row in rows {
+        row.push(acc);
+        acc = eval::apply(kons, row, env)?;
+    }
+    Ok(acc)
+}
+
+/// (sort list less?) is a stable merge sort of `list`.
+pub fn sort(args: &[Exp], env: &mut Environment) -> Result<Exp, Exceptions> {
+    expect_x_args(2, "sort", args)?;
+    let (less, items) = match &args[0] {
+        Exp::List(lst) => (&args[1], lst.to_vec()),
+        x => return Err(Exceptions::type_error("a list", x)),
+    };
+    if !less.is_procedure() {
+        return Err(Exceptions::type_error("a procedure", less));
+    }
+    let sorted = merge_sort(items, less, env)?;
+    Ok(Exp::List(Rc::new(sorted.into())))
+}
+
+fn merge_sort(
+    mut items: Vec<Exp>,
+    less: &Exp,
+    env: &mut Environment,
+) -> Result<Vec<Exp>, Exceptions> {
+    if items.len() <= 1 {
+        return Ok(items);
+    }
+    let right = items.split_off(items.len() / 2);
+    let mut left = merge_sort(items, less, env)?.into_iter().peekable();
+    let mut right = merge_sort(right, less, env)?.into_iter().peekable();
+    let mut merged = Vec::with_capacity(left.len() + right.len());
+    while let (Some(l), Some(r)) = (left.peek(), right.peek()) {
+        // only take from the right when it is strictly less, to keep equal
+        // elements in order
+        if eval::apply(less, vec![r.clone(), l.clone()], env)?.is_truthy() {
+            merged.extend(right.next());
         } else {
+            merged.extend(left.next());
+        }
+    }
+    merged.extend(left);
+    merged.extend(right);
+    Ok(merged)
+}
+
+/// (with-exception-handler handler thunk)
+///
+/// Exceptions raised with `raise-continuable` call `handler` right away
+/// and get its result back. Any other exception unwinds out of `thunk`
+/// first; `handler` is still called with it, but since such exceptions
+/// can't be continued, returning from `handler` is itself an error.
+pub fn with_exception_handler(args: &[Exp], env: &mut Environment) -> Result<Exp, Exceptions> {
+    expect_x_args(2, "with-exception-handler", args)?;
+    let (handler, thunk) = (&args[0], &args[1]);
+    env.push_handler(handler.clone());
+    let result = eval::apply(thunk, vec![], env);
+    env.pop_handler();
+    match result {
+        Ok(value) => Ok(value),
+        Err(e) => {
+            eval::apply(handler, vec![eval::condition(e)], env)?;
             Err(Exceptions::ValueError(
-                "Expected a list as second argument to map".to_string(),
+                "exception handler returned from a non-continuable exception".to_string(),
             ))
         }
+    }
+}
+
+/// (raise-continuable obj)
+///
+/// Calls the innermost handler with `obj` and returns whatever it returns.
+/// The handler itself runs with the outer handlers installed.
+pub fn raise_continuable(args: &[Exp], env: &mut Environment) -> Result<Exp, Exceptions> {
+    expect_x_args(1, "raise-continuable", args)?;
+    match env.pop_handler() {
+        None => Err(Exceptions::Raised(args[0].clone())),
+        Some(handler) => {
+            let result = eval::apply(&handler, vec![args[0].clone()], env);
+            env.push_handler(handler);
+            result
+        }
+    }
+}
+
+pub fn macroexpand(args: &[Exp], env: &mut Environment) -> Result<Exp, Exceptions> {
+    expect_x_args(1, "macroexpand", args)?;
+    expander::macroexpand(&args[0], env, false)
+}
+
+pub fn macroexpand_1(args: &[Exp], env: &mut Environment) -> Result<Exp, Exceptions> {
+    expect_x_args(1, "macroexpand-1", args)?;
+    expander::macroexpand(&args[0], env, true)
+}
+
+pub fn error(args: &[Exp], _env: &mut Environment) -> Result<Exp, Exceptions> {
+    expect_atleast_x_args(1, "error", args)?;
+    match &args[0] {
+        Exp::Str(message) => Err(Exceptions::UserError {
+            message: message.borrow().clone(),
+            irritants: args[1..].to_vec(),
+        }),
+        x => Err(Exceptions::type_error("a string", x)),
+    }
+}
+
+pub fn raise(args: &[Exp], _env: &mut Environment) -> Result<Exp, Exceptions> {
+    expect_x_args(1, "raise", args)?;
+    match &args[0] {
+        // re-raising a caught exception keeps where it originally came from
+        Exp::Condition(e) => Err(e.as_ref().clone()),
+        x => Err(Exceptions::Raised(x.clone())),
+    }
+}
+
+pub fn is_error_object(args: &[Exp], _env: &mut Environment) -> Result<Exp, Exceptions> {
+    expect_x_args(1, "error-object?", args)?;
+    Ok(Exp::Atom(Atom::Bool(matches!(&args[0], Exp::Condition(_)))))
+}
+
+/// The message and irritants of an error object, in the shape `error`
+/// would have been called with.
+fn message_and_irritants(args: &[Exp], func_name: &str) -> Result<(String, Vec<Exp>), Exceptions> {
+    expect_x_args(1, func_name, args)?;
+    let e = match &args[0] {
+        Exp::Condition(e) => e.root(),
+        x => return Err(Exceptions::type_error("an error object", x)),
+    };
+    Ok(match e {
+        Exceptions::UserError { message, irritants } => (message.clone(), irritants.clone()),
+        Exceptions::UnboundVariable(name) => (
+            "unbound variable".to_string(),
+            vec![Exp::Atom(Atom::Symbol(name.clone()))],
+        ),
+        Exceptions::TypeError { expected, actual } => (
+            format!("type error: expected {}", expected),
+            vec![actual.clone()],
+        ),
+        e => (e.to_string(), vec![]),
+    })
+}
+
+pub fn error_object_message(args: &[Exp], _env: &mut Environment) -> Result<Exp, Exceptions> {
+    Ok(message_and_irritants(args, "error-object-message")?
+        .0
+        .into_exp())
+}
+
+pub fn error_object_irritants(args: &[Exp], _env: &mut Environment) -> Result<Exp, Exceptions> {
+    let irritants = message_and_irritants(args, "error-object-irritants")?.1;
+    Ok(Exp::List(Rc::new(irritants.into())))
+}
+
+fn printer(args: &[Exp], new_line: bool) -> Result<Exp, Exceptions> {
+    let printable_form = args
+        .iter()
+        .map(|x| format!("{}", x))
+        .collect::<Vec<String>>()
+        .join(" ");
+    if !new_line {
+        print!("{}", printable_form);
     } else {
-        Err(Exceptions::ValueError(
-            "Expected a callable as first argument to map".to_string(),
-        ))
+        println!("{}", printable_form);
+    }
+    Ok(printable_form.into_exp())
+}
+
+pub fn print(args: &[Exp], _env: &mut Environment) -> Result<Exp, Exceptions> {
+    printer(args, false)
+}
+
+pub fn println(args: &[Exp], _env: &mut Environment) -> Result<Exp, Exceptions> {
+    printer(args, true)
+}
+
+/// Writes `text` to the output port `port`, or to standard output when
+/// there is none.
+fn write_to(port: Option<&Exp>, text: &str) -> Result<Exp, Exceptions> {
+    match port {
+        None => print!("{}", text),
+        Some(x) => output_port(x)?.borrow_mut().push_str(text),
+    }
+    Ok(Exp::Atom(Atom::Bool(false)))
+}
+
+fn output_port(x: &Exp) -> Result<&RefCell<String>, Exceptions> {
+    match x {
+        Exp::Port(p) => match &**p {
+            Port::Output(buffer) => Ok(buffer),
+            Port::Input(..) => Err(Exceptions::type_error("an output port", x)),
+        },
+        _ => Err(Exceptions::type_error("an output port", x)),
+    }
+}
+
+/// `(display x port)` writes `x` with strings as their bare contents, like
+/// `print!` does.
+pub fn display(args: &[Exp], _env: &mut Environment) -> Result<Exp, Exceptions> {
+    expect_one_or_two_args("display", args)?;
+    write_to(args.get(1), &args[0].to_string())
+}
+
+/// `(write x port)` writes `x` the way it would be read back in, with
+/// strings in double quotes.
+pub fn write(args: &[Exp], _env: &mut Environment) -> Result<Exp, Exceptions> {
+    expect_one_or_two_args("write", args)?;
+    write_to(args.get(1), &args[0].written().to_string())
+}
+
+pub fn newline(args: &[Exp], _env: &mut Environment) -> Result<Exp, Exceptions> {
+    expect_args_between(0, 1, "newline", args)?;
+    write_to(args.first(), "\n")
+}
+
+pub fn write_string(args: &[Exp], _env: &mut Environment) -> Result<Exp, Exceptions> {
+    expect_one_or_two_args("write-string", args)?;
+    write_to(args.get(1), &get_string(&args[0])?)
+}
+
+pub fn write_char(args: &[Exp], _env: &mut Environment) -> Result<Exp, Exceptions> {
+    expect_one_or_two_args("write-char", args)?;
+    write_to(args.get(1), &get_char(&args[0])?.to_string())
+}
+
+pub fn open_output_string(args: &[Exp], _env: &mut Environment) -> Result<Exp, Exceptions> {
+    expect_x_args(0, "open-output-string", args)?;
+    Ok(Exp::Port(Rc::new(Port::Output(
+        RefCell::new(String::new()),
+    ))))
+}
+
+/// Everything written so far to a port from `open-output-string`.
+pub fn get_output_string(args: &[Exp], _env: &mut Environment) -> Result<Exp, Exceptions> {
+    expect_x_args(1, "get-output-string", args)?;
+    Ok(output_port(&args[0])?.borrow().clone().into_exp())
+}
+
+/// A port that reads the characters of a copy of the given string.
+pub fn open_input_string(args: &[Exp], _env: &mut Environment) -> Result<Exp, Exceptions> {
+    expect_x_args(1, "open-input-string", args)?;
+    let chars = get_string(&args[0])?.chars().collect();
+    Ok(Exp::Port(Rc::new(Port::Input(chars, Cell::new(0)))))
+}
+
+fn input_port(x: &Exp) -> Result<(&[char], &Cell<usize>), Exceptions> {
+    match x {
+        Exp::Port(p) => match &**p {
+            Port::Input(chars, pos) => Ok((chars, pos)),
+            Port::Output(_) => Err(Exceptions::type_error("an input port", x)),
+        },
+        _ => Err(Exceptions::type_error("an input port", x)),
+    }
+}
+
+pub fn eof(args: &[Exp], _env: &mut Environment) -> Result<Exp, Exceptions> {
+    expect_x_args(0, "eof-object", args)?;
+    Ok(Exp::Eof)
+}
+
+pub fn is_eof(args: &[Exp], _env: &mut Environment) -> Result<Exp, Exceptions> {
+    expect_x_args(1, "eof-object?", args)?;
+    Ok(Exp::Atom(Atom::Bool(matches!(args[0], Exp::Eof))))
+}
+
+/// `(read-char port)` reads the next character, or gives the eof object
+/// at the end. `(peek-char port)` does the same without consuming it.
+pub fn read_char(args: &[Exp], name: &str, consume: bool) -> Result<Exp, Exceptions> {
+    expect_x_args(1, name, args)?;
+    let (chars, pos) = input_port(&args[0])?;
+    match chars.get(pos.get()) {
+        Some(c) => {
+            if consume {
+                pos.set(pos.get() + 1);
+            }
+            Ok(c.into_exp())
+        }
+        None => Ok(Exp::Eof),
     }
 }
 
+/// `(read port)` reads the next expression, without evaluating it, or
+/// gives the eof object if only whitespace is left.
+pub fn read(args: &[Exp], _env: &mut Environment) -> Result<Exp, Exceptions> {
+    expect_x_args(1, "read", args)?;
+    let (chars, pos) = input_port(&args[0])?;
+    let rest: String = chars[pos.get()..].iter().collect();
+    match read_prefix(&rest)? {
+        Some((exp, taken)) => {
+            pos.set(pos.get() + taken);
+            Ok(exp)
+        }
+        None => {
+            pos.set(chars.len());
+            Ok(Exp::Eof)
+        }
+    }
+}
+
+/// `(read-line port)` reads up to the next line break, which it consumes
+/// but leaves out of the result.
+pub fn read_line(args: &[Exp], _env: &mut Environment) -> Result<Exp, Exceptions> {
+    expect_x_args(1, "read-line", args)?;
+    let (chars, pos) = input_port(&args[0])?;
+    let rest = &chars[pos.get()..];
+    if rest.is_empty() {
+        return Ok(Exp::Eof);
+    }
+    let line: String = rest.iter().take_while(|c| **c != '\n').collect();
+    let taken = line.chars().count();
+    pos.set(pos.get() + (taken + 1).min(rest.len()));
+    Ok(line
+        .strip_suffix('\r')
+        .unwrap_or(&line)
+        .to_string()
+        .into_exp())
+}
+
+/// `(read-string k port)` reads up to `k` characters.
+pub fn read_string(args: &[Exp], _env: &mut Environment) -> Result<Exp, Exceptions> {
+    expect_x_args(2, "read-string", args)?;
+    let k = get_index(&args[0])?;
+    let (chars, pos) = input_port(&args[1])?;
+    let rest = &chars[pos.get()..];
+    if rest.is_empty() && k > 0 {
+        return Ok(Exp::Eof);
+    }
+    let taken = k.min(rest.len());
+    pos.set(pos.get() + taken);
+    Ok(rest[..taken].iter().collect::<String>().into_exp())
+}
+
+pub fn is_string(args: &[Exp], _env: &mut Environment) -> Result<Exp, Exceptions> {
+    expect_x_args(1, "string?", args)?;
+    Ok(Exp::Atom(Atom::Bool(matches!(&args[0], Exp::Str(_)))))
+}
+
+pub fn is_symbol(args: &[Exp], _env: &mut Environment) -> Result<Exp, Exceptions> {
+    expect_x_args(1, "symbol?", args)?;
+    Ok(Exp::Atom(Atom::Bool(matches!(
+        &args[0],
+        Exp::Atom(Atom::Symbol(_))
+    ))))
+}
+
+/// Strings are indexed by character, not by byte.
+pub fn string_length(args: &[Exp], _env: &mut Environment) -> Result<Exp, Exceptions> {
+    expect_x_args(1, "string-length", args)?;
+    Ok((get_string(&args[0])?.chars().count() as i64).into_exp())
+}
+
+pub fn string_append(args: &[Exp], _env: &mut Environment) -> Result<Exp, Exceptions> {
+    let mut ans = String::new();
+    for x in args {
+        ans.push_str(&get_string(x)?);
+    }
+    Ok(ans.into_exp())
+}
+
+/// The range of characters given by the optional `start` and `end`
+/// arguments of `substring` and friends, the whole string by default.
+fn char_range(
+    name: &str,
+    len: usize,
+    start: Option<&Exp>,
+    end: Option<&Exp>,
+) -> Result<(usize, usize), Exceptions> {
+    let start = start.map_or(Ok(0), get_index)?;
+    let end = end.map_or(Ok(len), get_index)?;
+    if start > end || end > len {
+        return Err(Exceptions::ValueError(format!(
+            "{}: range {} to {} is out of bounds for a string of length {}",
+            name, start, end, len
+        )));
+    }
+    Ok((start, end))
+}
+
+/// `(substring s start end)` is the characters of `s` from `start` up to
+/// but not including `end`, which defaults to the end of `s`.
+pub fn substring(args: &[Exp], _env: &mut Environment) -> Result<Exp, Exceptions> {
+    expect_args_between(2, 3, "substring", args)?;
+    string_copy_range(args, "substring")
+}
+
+/// `(string-copy s start end)` is a new string with the characters of `s`
+/// from `start` to `end`, all of them by default.
+pub fn string_copy(args: &[Exp], _env: &mut Environment) -> Result<Exp, Exceptions> {
+    expect_args_between(1, 3, "string-copy", args)?;
+    string_copy_range(args, "string-copy")
+}
+
+fn string_copy_range(args: &[Exp], name: &str) -> Result<Exp, Exceptions> {
+    let s = get_string(&args[0])?;
+    let (start, end) = char_range(name, s.chars().count(), args.get(1), args.get(2))?;
+    Ok(s.chars()
+        .skip(start)
+        .take(end - start)
+        .collect::<String>()
+        .into_exp())
+}
+
+/// `(make-string k c)` is a string of `k` copies of `c`, a space by
+/// default.
+pub fn make_string(args: &[Exp], _env: &mut Environment) -> Result<Exp, Exceptions> {
+    expect_one_or_two_args("make-string", args)?;
+    let k = get_index(&args[0])?;
+    let c = args.get(1).map_or(Ok(' '), get_char)?;
+    Ok(c.to_string().repeat(k).into_exp())
+}
+
+fn get_string_mut<'a>(name: &str, x: &'a Exp) -> Result<RefMut<'a, String>, Exceptions> {
+    match x {
+        Exp::Str(s) if s.literal => Err(Exceptions::ValueError(format!(
+            "{}: cannot change the string literal {}",
+            name,
+            x.written()
+        ))),
+        Exp::Str(s) => Ok(s.borrow_mut()),
+        _ => Err(Exceptions::type_error("a string", x)),
+    }
+}
+
+/// `(string-set! s k c)` replaces the `k`th character of `s` with `c`.
+pub fn string_set(args: &[Exp], _env: &mut Environment) -> Result<Exp, Exceptions> {
+    expect_x_args(3, "string-set!", args)?;
+    let k = get_index(&args[1])?;
+    let c = get_char(&args[2])?;
+    let mut s = get_string_mut("string-set!", &args[0])?;
+    match s.char_indices().nth(k) {
+        Some((at, old)) => {
+            s.replace_range(at..at + old.len_utf8(), c.encode_utf8(&mut [0; 4]));
+            Ok(Exp::Atom(Atom::Bool(false)))
+        }
+        None => Err(Exceptions::ValueError(format!(
+            "string-set!: index {} is out of bounds for a string of length {}",
+            k,
+            s.chars().count()
+        ))),
+    }
+}
+
+/// `(string-fill! s c start end)` sets the characters of `s` from `start`
+/// to `end`, all of them by default, to `c`.
+pub fn string_fill(args: &[Exp], _env: &mut Environment) -> Result<Exp, Exceptions> {
+    expect_args_between(2, 4, "string-fill!", args)?;
+    let c = get_char(&args[1])?;
+    let mut s = get_string_mut("string-fill!", &args[0])?;
+    let (start, end) = char_range("string-fill!", s.chars().count(), args.get(2), args.get(3))?;
+    *s = s
+        .chars()
+        .enumerate()
+        .map(|(i, old)| if i >= start && i < end { c } else { old })
+        .collect();
+    Ok(Exp::Atom(Atom::Bool(false)))
+}
+
+/// `(string-ref s k)` is the `k`th character of `s`.
+pub fn string_ref(args: &[Exp], _env: &mut Environment) -> Result<Exp, Exceptions> {
+    expect_x_args(2, "string-ref", args)?;
+    let s = get_string(&args[0])?;
+    let k = get_index(&args[1])?;
+    match s.chars().nth(k) {
+        Some(c) => Ok(c.into_exp()),
+        None => Err(Exceptions::ValueError(format!(
+            "string-ref: index {} is out of bounds for a string of length {}",
+            k,
+            s.chars().count()
+        ))),
+    }
+}
+
+/// Like `compare_chain` for strings, which are compared character by
+/// character. With `fold_case` the comparison ignores case.
+pub fn string_compare_chain(
+    args: &[Exp],
+    name: &str,
+    fold_case: bool,
+    f: fn(Ordering) -> bool,
+) -> Result<Exp, Exceptions> {
+    expect_atleast_x_args(1, name, args)?;
+    let strings = args
+        .iter()
+        .map(|x| {
+            let s = get_string(x)?;
+            Ok(if fold_case {
+                s.to_lowercase()
+            } else {
+                s.to_string()
+            })
+        })
+        .collect::<Result<Vec<_>, Exceptions>>()?;
+    let ans = strings.windows(2).all(|pair| f(pair[0].cmp(&pair[1])));
+    Ok(Exp::Atom(Atom::Bool(ans)))
+}
+
+pub fn string_upcase(args: &[Exp], _env: &mut Environment) -> Result<Exp, Exceptions> {
+    expect_x_args(1, "string-upcase", args)?;
+    Ok(get_string(&args[0])?.to_uppercase().into_exp())
+}
+
+pub fn string_downcase(args: &[Exp], _env: &mut Environment) -> Result<Exp, Exceptions> {
+    expect_x_args(1, "string-downcase", args)?;
+    Ok(get_string(&args[0])?.to_lowercase().into_exp())
+}
+
+/// `(string-split s sep)` splits `s` at every occurrence of `sep`, a string
+/// or a character. Without `sep` it splits at runs of whitespace and drops
+/// empty pieces.
+pub fn string_split(args: &[Exp], _env: &mut Environment) -> Result<Exp, Exceptions> {
+    expect_one_or_two_args("string-split", args)?;
+    let s = get_string(&args[0])?;
+    let pieces: Vec<String> = match args.get(1) {
+        None => s.split_whitespace().map(String::from).collect(),
+        Some(Exp::Atom(Atom::Char(c))) => s.split(*c).map(String::from).collect(),
+        Some(sep) => match get_string(sep)?.as_str() {
+            "" => {
+                return Err(Exceptions::ValueError(
+                    "string-split: the separator is empty".to_string(),
+                ))
+            }
+            sep => s.split(sep).map(String::from).collect(),
+        },
+    };
+    Ok(pieces.into_exp())
+}
+
+/// `(string-join list sep)` joins a list of strings, putting `sep`
+/// (a single space by default) between them.
+pub fn string_join(args: &[Exp], _env: &mut Environment) -> Result<Exp, Exceptions> {
+    expect_one_or_two_args("string-join", args)?;
+    let pieces = match &args[0] {
+        Exp::List(items) => items
+            .iter()
+            .map(get_string)
+            .collect::<Result<Vec<_>, _>>()?,
+        x => return Err(Exceptions::type_error("a list", x)),
+    };
+    let pieces: Vec<&str> = pieces.iter().map(|s| s.as_str()).collect();
+    let sep = match args.get(1) {
+        Some(x) => get_string(x)?.clone(),
+        None => " ".to_string(),
+    };
+    Ok(pieces.join(&sep).into_exp())
+}
+
+/// `(string-index s pred)` is the index of the first character of `s` for
+/// which `pred` is true, or `#f` if there is none. `pred` may also be a
+/// character to look for.
+pub fn string_index(args: &[Exp], env: &mut Environment) -> Result<Exp, Exceptions> {
+    expect_x_args(2, "string-index", args)?;
+    // Copied, since `pred` may change the string.
+    let s: Vec<char> = get_string(&args[0])?.chars().collect();
+    let pred = &args[1];
+    if !pred.is_procedure() && get_char(pred).is_err() {
+        return Err(Exceptions::type_error("a procedure or a character", pred));
+    }
+    for (i, &c) in s.iter().enumerate() {
+        let found = match pred {
+            Exp::Atom(Atom::Char(wanted)) => c == *wanted,
+            _ => eval::apply(pred, vec![c.into_exp()], env)?.is_truthy(),
+        };
+        if found {
+            return Ok((i as i64).into_exp());
+        }
+    }
+    Ok(Exp::Atom(Atom::Bool(false)))
+}
+
+/// `(string-contains s pattern)` is the index in `s` where the first
+/// occurrence of `pattern` starts, or `#f` if there is none.
+pub fn string_contains(args: &[Exp], _env: &mut Environment) -> Result<Exp, Exceptions> {
+    expect_x_args(2, "string-contains", args)?;
+    let s = get_string(&args[0])?;
+    let pattern = get_string(&args[1])?;
+    Ok(match s.find(pattern.as_str()) {
+        Some(byte) => (s[..byte].chars().count() as i64).into_exp(),
+        None => Exp::Atom(Atom::Bool(false)),
+    })
+}
+
+pub fn string_to_list(args: &[Exp], _env: &mut Environment) -> Result<Exp, Exceptions> {
+    expect_x_args(1, "string->list", args)?;
+    let chars: Vec<char> = get_string(&args[0])?.chars().collect();
+    Ok(chars.into_exp())
+}
+
+pub fn list_to_string(args: &[Exp], _env: &mut Environment) -> Result<Exp, Exceptions> {
+    expect_x_args(1, "list->string", args)?;
+    match &args[0] {
+        Exp::List(items) => Ok(items
+            .iter()
+            .map(get_char)
+            .collect::<Result<String, _>>()?
+            .into_exp()),
+        x => Err(Exceptions::type_error("a list", x)),
+    }
+}
+
+pub fn string_to_symbol(args: &[Exp], _env: &mut Environment) -> Result<Exp, Exceptions> {
+    expect_x_args(1, "string->symbol", args)?;
+    Ok(Exp::Atom(Atom::Symbol(get_string(&args[0])?.to_string())))
+}
+
+pub fn symbol_to_string(args: &[Exp], _env: &mut Environment) -> Result<Exp, Exceptions> {
+    expect_x_args(1, "symbol->string", args)?;
+    match &args[0] {
+        Exp::Atom(Atom::Symbol(s)) => Ok(s.clone().into_exp()),
+        x => Err(Exceptions::type_error("a symbol", x)),
+    }
+}
+
+pub fn is_char(args: &[Exp], _env: &mut Environment) -> Result<Exp, Exceptions> {
+    expect_x_args(1, "char?", args)?;
+    Ok(Exp::Atom(Atom::Bool(matches!(
+        &args[0],
+        Exp::Atom(Atom::Char(_))
+    ))))
+}
+
+/// The Unicode scalar value of a character.
+pub fn char_to_integer(args: &[Exp], _env: &mut Environment) -> Result<Exp, Exceptions> {
+    expect_x_args(1, "char->integer", args)?;
+    Ok((get_char(&args[0])? as i64).into_exp())
+}
+
+pub fn integer_to_char(args: &[Exp], _env: &mut Environment) -> Result<Exp, Exceptions> {
+    expect_x_args(1, "integer->char", args)?;
+    let n = get_index(&args[0])?;
+    u32::try_from(n)
+        .ok()
+        .and_then(std::char::from_u32)
+        .map(|c| c.into_exp())
+        .ok_or_else(|| {
+            Exceptions::ValueError(format!(
+                "integer->char: {} is not a Unicode scalar value",
+                n
+            ))
+        })
+}
+
+pub fn char_test(args: &[Exp], name: &str, f: fn(char) -> bool) -> Result<Exp, Exceptions> {
+    expect_x_args(1, name, args)?;
+    Ok(Exp::Atom(Atom::Bool(f(get_char(&args[0])?))))
+}
+
+/// Changes the case of a character, leaving it alone when the other case
+/// is not a single character, as for `ß`.
+pub fn char_case(args: &[Exp], name: &str, f: fn(char) -> Vec<char>) -> Result<Exp, Exceptions> {
+    expect_x_args(1, name, args)?;
+    let c = get_char(&args[0])?;
+    Ok(match f(c)[..] {
+        [mapped] => mapped.into_exp(),
+        _ => c.into_exp(),
+    })
+}
+
+/// Like `compare_chain` for characters, which are ordered by their scalar
+/// values.
+pub fn char_compare_chain(
+    args: &[Exp],
+    name: &str,
+    f: fn(Ordering) -> bool,
+) -> Result<Exp, Exceptions> {
+    expect_atleast_x_args(1, name, args)?;
+    let chars = args.iter().map(get_char).collect::<Result<Vec<_>, _>>()?;
+    let ans = chars.windows(2).all(|pair| f(pair[0].cmp(&pair[1])));
+    Ok(Exp::Atom(Atom::Bool(ans)))
+}
+
 pub fn expect_x_args(x: usize, func_name: &str, args: &[Exp]) -> Result<usize, Exceptions> {
     if args.len() != x {
-        return Err(Exceptions::ValueError(
-            format!(
-                "expected {} arguments for {}, got {}",
-                x,
-                func_name,
-                args.len()
-            )
-            .to_string(),
-        ));
+        Err(Exceptions::ArityMismatch {
+            name: func_name.to_string(),
+            expected: x.to_string(),
+            got: args.len(),
+        })
     } else {
         Ok(x)
     }
 }
 pub fn expect_atleast_x_args(x: usize, func_name: &str, args: &[Exp]) -> Result<usize, Exceptions> {
     if args.len() < x {
-        return Err(Exceptions::ValueError(
-            format!(
-                "expected at least {} arguments for {}, got {}",
-                x,
-                func_name,
-                args.len()
-            )
-            .to_string(),
-        ));
+        Err(Exceptions::ArityMismatch {
+            name: func_name.to_string(),
+            expected: format!("at least {}", x),
+            got: args.len(),
+        })
     } else {
         Ok(x)
     }
@@ -1,91 +1,390 @@
 use super::builtin_functions::*;
 use super::types::*;
+use std::cmp::Ordering;
 use std::f64;
-use std::rc::Rc;
 
 pub fn default_env() -> Environment {
-    let mut env: Environment = Environment::new();
+    let env: Environment = Environment::new();
     env.insert(
         "pi".to_string(),
         Exp::Atom(Atom::Number(Number::Float(f64::consts::PI))),
     );
+    env.insert("+".to_string(), Exp::Func(add));
+    env.insert("*".to_string(), Exp::Func(multiply));
+    env.insert("-".to_string(), Exp::Func(minus));
+    env.insert("/".to_string(), Exp::Func(divide));
+    env.insert("fmod".to_string(), Exp::Func(fmod));
+    env.insert("mod".to_string(), Exp::Func(mod_int));
+    env.insert("abs".to_string(), Exp::Func(absolute_val));
+    env.insert(
+        "quotient".to_string(),
+        Exp::Func(|args, _| Ok(divide_integers(args, "quotient", false)?.0.into_exp())),
+    );
+    env.insert(
+        "remainder".to_string(),
+        Exp::Func(|args, _| Ok(divide_integers(args, "remainder", false)?.1.into_exp())),
+    );
+    env.insert(
+        "modulo".to_string(),
+        Exp::Func(|args, _| Ok(divide_integers(args, "modulo", true)?.1.into_exp())),
+    );
+    env.insert(
+        "floor/".to_string(),
+        Exp::Func(|args, _| Ok(divide_integers(args, "floor/", true)?.into_exp())),
+    );
+    env.insert(
+        "floor-quotient".to_string(),
+        Exp::Func(|args, _| Ok(divide_integers(args, "floor-quotient", true)?.0.into_exp())),
+    );
+    env.insert(
+        "floor-remainder".to_string(),
+        Exp::Func(|args, _| Ok(divide_integers(args, "floor-remainder", true)?.1.into_exp())),
+    );
+    env.insert(
+        "truncate/".to_string(),
+        Exp::Func(|args, _| Ok(divide_integers(args, "truncate/", false)?.into_exp())),
+    );
+    env.insert(
+        "truncate-quotient".to_string(),
+        Exp::Func(|args, _| {
+            Ok(divide_integers(args, "truncate-quotient", false)?
+                .0
+                .into_exp())
+        }),
+    );
+    env.insert(
+        "truncate-remainder".to_string(),
+        Exp::Func(|args, _| {
+            Ok(divide_integers(args, "truncate-remainder", false)?
+                .1
+                .into_exp())
+        }),
+    );
     env.insert(
         "exp".to_string(),
-        Exp::Atom(Atom::Number(Number::Float(f64::consts::E))),
+        Exp::Func(|args, _| float_fn(args, "exp", f64::exp, (0, 1))),
+    );
+    env.insert(
+        "sin".to_string(),
+        Exp::Func(|args, _| float_fn(args, "sin", f64::sin, (0, 0))),
+    );
+    env.insert(
+        "cos".to_string(),
+        Exp::Func(|args, _| float_fn(args, "cos", f64::cos, (0, 1))),
     );
     env.insert(
-        "+".to_string(),
-        Exp::Func(|args| binary_op_arith(&args[..], 0.0, |x, y| x + y)),
+        "tan".to_string(),
+        Exp::Func(|args, _| float_fn(args, "tan", f64::tan, (0, 0))),
     );
     env.insert(
-        "*".to_string(),
-        Exp::Func(|args| binary_op_arith(&args[..], 1_f64, |x, y| x * y)),
+        "asin".to_string(),
+        Exp::Func(|args, _| float_fn(args, "asin", f64::asin, (0, 0))),
     );
-    env.insert("-".to_string(), Exp::Func(|args| minus(args)));
-    env.insert("/".to_string(), Exp::Func(|args| divide(args)));
-    env.insert("fmod".to_string(), Exp::Func(|args| fmod(args)));
-    env.insert("mod".to_string(), Exp::Func(|args| mod_int(args)));
-    env.insert("abs".to_string(), Exp::Func(|args| absolute_val(args)));
-    env.insert("expt".to_string(), Exp::Func(|args| power(args)));
+    env.insert(
+        "acos".to_string(),
+        Exp::Func(|args, _| float_fn(args, "acos", f64::acos, (1, 0))),
+    );
+    env.insert("log".to_string(), Exp::Func(log));
+    env.insert("atan".to_string(), Exp::Func(atan));
+    env.insert("sqrt".to_string(), Exp::Func(sqrt));
+    env.insert("square".to_string(), Exp::Func(square));
+    env.insert(
+        "floor".to_string(),
+        Exp::Func(|args, _| rounding(args, "floor", Number::floor)),
+    );
+    env.insert(
+        "ceiling".to_string(),
+        Exp::Func(|args, _| rounding(args, "ceiling", Number::ceiling)),
+    );
+    env.insert(
+        "round".to_string(),
+        Exp::Func(|args, _| rounding(args, "round", Number::round)),
+    );
+    env.insert(
+        "truncate".to_string(),
+        Exp::Func(|args, _| rounding(args, "truncate", Number::truncate)),
+    );
+    env.insert("exact-integer?".to_string(), Exp::Func(is_exact_integer));
+    env.insert("number->string".to_string(), Exp::Func(number_to_string));
+    env.insert("string->number".to_string(), Exp::Func(string_to_number));
+    env.insert(
+        "nan?".to_string(),
+        Exp::Func(|args, _| float_test(args, "nan?", f64::is_nan)),
+    );
+    env.insert(
+        "infinite?".to_string(),
+        Exp::Func(|args, _| float_test(args, "infinite?", f64::is_infinite)),
+    );
+    env.insert(
+        "finite?".to_string(),
+        Exp::Func(|args, _| float_test(args, "finite?", f64::is_finite)),
+    );
+    env.insert("gcd".to_string(), Exp::Func(gcd));
+    env.insert("lcm".to_string(), Exp::Func(lcm));
+    env.insert(
+        "exact-integer-sqrt".to_string(),
+        Exp::Func(exact_integer_sqrt),
+    );
+    env.insert(
+        "even?".to_string(),
+        Exp::Func(|args, _| parity(args, "even?", true)),
+    );
+    env.insert(
+        "odd?".to_string(),
+        Exp::Func(|args, _| parity(args, "odd?", false)),
+    );
+    env.insert(
+        "zero?".to_string(),
+        Exp::Func(|args, _| sign_test(args, "zero?", |x| x == Ordering::Equal)),
+    );
+    env.insert(
+        "positive?".to_string(),
+        Exp::Func(|args, _| sign_test(args, "positive?", |x| x == Ordering::Greater)),
+    );
+    env.insert(
+        "negative?".to_string(),
+        Exp::Func(|args, _| sign_test(args, "negative?", |x| x == Ordering::Less)),
+    );
+    env.insert("expt".to_string(), Exp::Func(power));
     env.insert(
         ">".to_string(),
-        Exp::Func(|args| binary_cmp(args, |x, y| x > y)),
+        Exp::Func(|args, _| compare_chain(args, ">", |x| x == Ordering::Greater)),
     );
     env.insert(
         "<".to_string(),
-        Exp::Func(|args| binary_cmp(args, |x, y| x < y)),
+        Exp::Func(|args, _| compare_chain(args, "<", |x| x == Ordering::Less)),
     );
     env.insert(
         "=".to_string(),
-        Exp::Func(|args| binary_cmp(args, |x, y| x == y)),
+        Exp::Func(|args, _| compare_chain(args, "=", |x| x == Ordering::Equal)),
     );
     env.insert(
         ">=".to_string(),
-        Exp::Func(|args| binary_cmp(args, |x, y| x >= y)),
+        Exp::Func(|args, _| compare_chain(args, ">=", |x| x != Ordering::Less)),
     );
     env.insert(
         "<=".to_string(),
-        Exp::Func(|args| binary_cmp(args, |x, y| x <= y)),
+        Exp::Func(|args, _| compare_chain(args, "<=", |x| x != Ordering::Greater)),
     );
+    env.insert("not".to_string(), Exp::Func(logical_not));
+    env.insert("append".to_string(), Exp::Func(append));
+    env.insert("car".to_string(), Exp::Func(car));
+    env.insert("cdr".to_string(), Exp::Func(cdr));
+    env.insert("apply".to_string(), Exp::Func(apply));
+    env.insert("cons".to_string(), Exp::Func(cons));
+    env.insert("same_obj?".to_string(), Exp::Func(same_obj));
+    env.insert("equal?".to_string(), Exp::Func(equal));
+    env.insert("length".to_string(), Exp::Func(length));
+    env.insert("list?".to_string(), Exp::Func(is_list));
     env.insert(
-        "and".to_string(),
-        Exp::Func(|args| logical_bin_ops(args, |x, y| x && y)),
+        "max".to_string(),
+        Exp::Func(|args, _| min_max(args, "max", Ordering::Greater)),
     );
     env.insert(
-        "or".to_string(),
-        Exp::Func(|args| logical_bin_ops(args, |x, y| x && y)),
+        "min".to_string(),
+        Exp::Func(|args, _| min_max(args, "min", Ordering::Less)),
     );
-    env.insert("not".to_string(), Exp::Func(|args| logical_not(args)));
-    env.insert("begin".to_string(), Exp::Func(|args| begin(args)));
-    env.insert("append".to_string(), Exp::Func(|args| append(args)));
-    env.insert("car".to_string(), Exp::Func(|args| car(args)));
-    env.insert("cdr".to_string(), Exp::Func(|args| cdr(args)));
-    env.insert("apply".to_string(), Exp::Func(|args| apply(args)));
-    env.insert("cons".to_string(), Exp::Func(|args| cons(args)));
-    env.insert("same_obj?".to_string(), Exp::Func(|args| same_obj(args)));
-    env.insert("equal?".to_string(), Exp::Func(|args| equal(args)));
-    env.insert("length".to_string(), Exp::Func(|args| length(args)));
-    env.insert("list?".to_string(), Exp::Func(|args| is_list(args)));
+    env.insert("null?".to_string(), Exp::Func(is_null));
+    env.insert("number?".to_string(), Exp::Func(is_number));
+    env.insert("exact?".to_string(), Exp::Func(is_exact));
+    env.insert("inexact?".to_string(), Exp::Func(is_inexact));
+    env.insert("exact->inexact".to_string(), Exp::Func(exact_to_inexact));
+    env.insert("inexact->exact".to_string(), Exp::Func(inexact_to_exact));
+    env.insert("inexact".to_string(), Exp::Func(exact_to_inexact));
+    env.insert("exact".to_string(), Exp::Func(inexact_to_exact));
+    env.insert("numerator".to_string(), Exp::Func(numerator));
+    env.insert("denominator".to_string(), Exp::Func(denominator));
+    env.insert("rationalize".to_string(), Exp::Func(rationalize));
+    env.insert("procedure?".to_string(), Exp::Func(is_proc));
+    env.insert("bool?".to_string(), Exp::Func(is_bool));
+    env.insert("map".to_string(), Exp::Func(map));
+    env.insert("for-each".to_string(), Exp::Func(for_each));
+    env.insert("filter".to_string(), Exp::Func(filter));
+    env.insert("fold".to_string(), Exp::Func(fold));
+    env.insert("sort".to_string(), Exp::Func(sort));
+    env.insert("print!".to_string(), Exp::Func(print));
+    env.insert("println!".to_string(), Exp::Func(println));
+    env.insert("display".to_string(), Exp::Func(display));
+    env.insert("write".to_string(), Exp::Func(write));
+    env.insert("newline".to_string(), Exp::Func(newline));
+    env.insert("write-string".to_string(), Exp::Func(write_string));
+    env.insert("write-char".to_string(), Exp::Func(write_char));
     env.insert(
-        "max".to_string(),
-        Exp::Func(|args| min_max(args, "max", |x: f64, y: f64| x.max(y))),
+        "open-output-string".to_string(),
+        Exp::Func(open_output_string),
     );
     env.insert(
-        "min".to_string(),
-        Exp::Func(|args| min_max(args, "min", |x: f64, y: f64| x.min(y))),
-    );
-    env.insert("null?".to_string(), Exp::Func(|args| is_null(args)));
-    env.insert("number?".to_string(), Exp::Func(|args| is_number(args)));
-    env.insert("procedure?".to_string(), Exp::Func(|args| is_proc(args)));
-    env.insert("bool?".to_string(), Exp::Func(|args| is_bool(args)));
-    env.insert("map".to_string(), Exp::Func(|args| map(args)));
-    env.insert(
-        "list".to_string(),
-        Exp::Func(|args| {
-            let x = Exp::List(Rc::new(args.to_vec()));
-            Ok(x)
+        "get-output-string".to_string(),
+        Exp::Func(get_output_string),
+    );
+    env.insert(
+        "open-input-string".to_string(),
+        Exp::Func(open_input_string),
+    );
+    env.insert(
+        "read-char".to_string(),
+        Exp::Func(|args, _| read_char(args, "read-char", true)),
+    );
+    env.insert(
+        "peek-char".to_string(),
+        Exp::Func(|args, _| read_char(args, "peek-char", false)),
+    );
+    env.insert("read-line".to_string(), Exp::Func(read_line));
+    env.insert("read-string".to_string(), Exp::Func(read_string));
+    env.insert("read".to_string(), Exp::Func(read));
+    env.insert("eof-object".to_string(), Exp::Func(eof));
+    env.insert("eof-object?".to_string(), Exp::Func(is_eof));
+    env.insert("string?".to_string(), Exp::Func(is_string));
+    env.insert("symbol?".to_string(), Exp::Func(is_symbol));
+    env.insert("string-length".to_string(), Exp::Func(string_length));
+    env.insert("string-append".to_string(), Exp::Func(string_append));
+    env.insert("substring".to_string(), Exp::Func(substring));
+    env.insert("string-ref".to_string(), Exp::Func(string_ref));
+    env.insert("make-string".to_string(), Exp::Func(make_string));
+    env.insert("string-copy".to_string(), Exp::Func(string_copy));
+    env.insert("string-set!".to_string(), Exp::Func(string_set));
+    env.insert("string-fill!".to_string(), Exp::Func(string_fill));
+    env.insert(
+        "string=?".to_string(),
+        Exp::Func(|args, _| {
+            string_compare_chain(args, "string=?", false, |x| x == Ordering::Equal)
+        }),
+    );
+    env.insert(
+        "string<?".to_string(),
+        Exp::Func(|args, _| string_compare_chain(args, "string<?", false, |x| x == Ordering::Less)),
+    );
+    env.insert(
+        "string>?".to_string(),
+        Exp::Func(|args, _| {
+            string_compare_chain(args, "string>?", false, |x| x == Ordering::Greater)
         }),
     );
+    env.insert(
+        "string<=?".to_string(),
+        Exp::Func(|args, _| {
+            string_compare_chain(args, "string<=?", false, |x| x != Ordering::Greater)
+        }),
+    );
+    env.insert(
+        "string>=?".to_string(),
+        Exp::Func(|args, _| {
+            string_compare_chain(args, "string>=?", false, |x| x != Ordering::Less)
+        }),
+    );
+    env.insert(
+        "string-ci=?".to_string(),
+        Exp::Func(|args, _| {
+            string_compare_chain(args, "string-ci=?", true, |x| x == Ordering::Equal)
+        }),
+    );
+    env.insert(
+        "string-ci<?".to_string(),
+        Exp::Func(|args, _| {
+            string_compare_chain(args, "string-ci<?", true, |x| x == Ordering::Less)
+        }),
+    );
+    env.insert(
+        "string-ci>?".to_string(),
+        Exp::Func(|args, _| {
+            string_compare_chain(args, "string-ci>?", true, |x| x == Ordering::Greater)
+        }),
+    );
+    env.insert(
+        "string-ci<=?".to_string(),
+        Exp::Func(|args, _| {
+            string_compare_chain(args, "string-ci<=?", true, |x| x != Ordering::Greater)
+        }),
+    );
+    env.insert(
+        "string-ci>=?".to_string(),
+        Exp::Func(|args, _| {
+            string_compare_chain(args, "string-ci>=?", true, |x| x != Ordering::Less)
+        }),
+    );
+    env.insert("char?".to_string(), Exp::Func(is_char));
+    env.insert("char->integer".to_string(), Exp::Func(char_to_integer));
+    env.insert("integer->char".to_string(), Exp::Func(integer_to_char));
+    env.insert(
+        "char-alphabetic?".to_string(),
+        Exp::Func(|args, _| char_test(args, "char-alphabetic?", char::is_alphabetic)),
+    );
+    env.insert(
+        "char-numeric?".to_string(),
+        Exp::Func(|args, _| char_test(args, "char-numeric?", char::is_numeric)),
+    );
+    env.insert(
+        "char-whitespace?".to_string(),
+        Exp::Func(|args, _| char_test(args, "char-whitespace?", char::is_whitespace)),
+    );
+    env.insert(
+        "char-upper-case?".to_string(),
+        Exp::Func(|args, _| char_test(args, "char-upper-case?", char::is_uppercase)),
+    );
+    env.insert(
+        "char-lower-case?".to_string(),
+        Exp::Func(|args, _| char_test(args, "char-lower-case?", char::is_lowercase)),
+    );
+    env.insert(
+        "char-upcase".to_string(),
+        Exp::Func(|args, _| char_case(args, "char-upcase", |c| c.to_uppercase().collect())),
+    );
+    env.insert(
+        "char-downcase".to_string(),
+        Exp::Func(|args, _| char_case(args, "char-downcase", |c| c.to_lowercase().collect())),
+    );
+    env.insert(
+        "char=?".to_string(),
+        Exp::Func(|args, _| char_compare_chain(args, "char=?", |x| x == Ordering::Equal)),
+    );
+    env.insert(
+        "char<?".to_string(),
+        Exp::Func(|args, _| char_compare_chain(args, "char<?", |x| x == Ordering::Less)),
+    );
+    env.insert(
+        "char>?".to_string(),
+        Exp::Func(|args, _| char_compare_chain(args, "char>?", |x| x == Ordering::Greater)),
+    );
+    env.insert(
+        "char<=?".to_string(),
+        Exp::Func(|args, _| char_compare_chain(args, "char<=?", |x| x != Ordering::Greater)),
+    );
+    env.insert(
+        "char>=?".to_string(),
+        Exp::Func(|args, _| char_compare_chain(args, "char>=?", |x| x != Ordering::Less)),
+    );
+    env.insert("string-upcase".to_string(), Exp::Func(string_upcase));
+    env.insert("string-downcase".to_string(), Exp::Func(string_downcase));
+    env.insert("string-split".to_string(), Exp::Func(string_split));
+    env.insert("string-join".to_string(), Exp::Func(string_join));
+    env.insert("string-index".to_string(), Exp::Func(string_index));
+    env.insert("string-contains".to_string(), Exp::Func(string_contains));
+    env.insert("string->list".to_string(), Exp::Func(string_to_list));
+    env.insert("list->string".to_string(), Exp::Func(list_to_string));
+    env.insert("string->symbol".to_string(), Exp::Func(string_to_symbol));
+    env.insert("symbol->string".to_string(), Exp::Func(symbol_to_string));
+    env.insert("error".to_string(), Exp::Func(error));
+    env.insert("raise".to_string(), Exp::Func(raise));
+    env.insert(
+        "raise-continuable".to_string(),
+        Exp::Func(raise_continuable),
+    );
+    env.insert(
+        "with-exception-handler".to_string(),
+        Exp::Func(with_exception_handler),
+    );
+    env.insert("error-object?".to_string(), Exp::Func(is_error_object));
+    env.insert(
+        "error-object-message".to_string(),
+        Exp::Func(error_object_message),
+    );
+    env.insert(
+        "error-object-irritants".to_string(),
+        Exp::Func(error_object_irritants),
+    );
+    env.insert("macroexpand".to_string(), Exp::Func(macroexpand));
+    env.insert("macroexpand-1".to_string(), Exp::Func(macroexpand_1));
+    env.insert("list".to_string(), Exp::Func(list));
 
     env
 }
@@ -1,287 +1,482 @@
-use std::collections::HashMap;
+use std::cell::RefCell;
 use std::rc::Rc;
 
+use super::builtin_functions::raise_continuable;
+use super::expander::expand;
 use super::parser::*;
 use super::types::*;
 
 pub fn parse_and_eval(program: String, env: &mut Environment) -> Result<Exp, Exceptions> {
     let parsed_exp = parse(program)?;
-    let eval_exp = eval(&parsed_exp, env)?;
+    let expanded_exp = expand(&parsed_exp, env)?;
+    let eval_exp = eval(&expanded_exp, env)?;
     Ok(eval_exp)
 }
 
+/// What is left to do after evaluating one step of a list expression.
+enum Tail {
+    /// The expression's value is known.
+    Return(Exp),
+    /// The value is that of another expression, which is in tail position.
+    Eval(Exp),
+    /// The value is that of the body of a procedure, which is in tail
+    /// position.
+    Call(Rc<Procedure>),
+}
+
+/// Evaluates `exp` in `env`.
+///
+/// Expressions in tail position (the branches of an `if`, the last
+/// expression of a `begin` and the body of a procedure) are not evaluated
+/// recursively; instead `exp` and `env` are replaced and the loop goes
+/// around again, so tail calls run in constant Rust stack.
+///
+/// Errors are tagged with the span of the innermost list being evaluated
+/// when they were raised, or of the argument being evaluated when that is
+/// an atom, and with the procedure whose body was being evaluated.
 pub fn eval(exp: &Exp, env: &mut Environment) -> Result<Exp, Exceptions> {
-    match &exp {
-        Exp::Atom(x) => match x {
-            Atom::Symbol(y) => env.get(y).map(|z| z.clone()).ok_or(Exceptions::ValueError(
-                format!("{} is not a valid symbol", y).to_string(),
-            )),
-            _ => Ok(exp.clone()),
-        },
-        Exp::List(x) => {
-            if x.is_empty() {
-                return Err(Exceptions::ValueError(
-                    "Expected a non empty list".to_string(),
-                ));
-            } else {
-                let (first, rest) = x.split_first().unwrap();
-                let f = eval(first, env);
-                match first {
-                    Exp::Atom(Atom::Symbol(s)) => match &s[..] {
-                        "if" => if_handler(x, env),
-                        "define" => define_handler(x, env),
-                        "quote" => {
-                            if rest.is_empty() {
-                                Err(Exceptions::ValueError(
-                                    "Expected something after quote".to_string(),
-                                ))
-                            } else {
-                                Ok(rest[0].clone())
-                            }
-                        }
-                        "set!" => {
-                            if rest.len() != 2 {
-                                Err(Exceptions::ValueError(
-                                    "Expected 2 things after set!,symbol followed by an expression"
-                                        .to_string(),
-                                ))
-                            } else {
-                                if let Exp::Atom(Atom::Symbol(x)) = &rest[0] {
-                                    let evaluated_exp = eval(&rest[1], env)?;
-                                    if env.exists(x) {
-                                        env.insert(x.clone(), evaluated_exp.clone());
-                                        Ok(evaluated_exp)
-                                    } else {
-                                        Err(Exceptions::ValueError(
-                                            " set!: assignment disallowed\
-                                                , cannot set variable before its definition "
-                                                .to_string(),
-                                        ))
-                                    }
-                                } else {
-                                    Err(Exceptions::ValueError(
-                                        "Expected a symbol after set!, got something else"
-                                            .to_string(),
-                                    ))
-                                }
-                            }
-                        }
-                        "lambda" => {
-                            // two lists
-                            // one for params and another is body
-                            if rest.len() != 2 {
-                                return Err(Exceptions::SyntaxError(
-                                    "lambda expects a params list and body".to_string(),
-                                ));
-                            }
-                            if let Exp::List(params) = &rest[0] {
-                                let params_as_strings: Result<Vec<String>, Exceptions> = params
-                                    .iter()
-                                    .map(|x| {
-                                        if let Exp::Atom(Atom::Symbol(y)) = x {
-                                            return Ok(y.clone());
-                                        } else {
-                                            return Err(Exceptions::ValueError(
-                                                "non symbol passed in a lambda parameters list"
-                                                    .to_string(),
-                                            ));
-                                        }
-                                    })
-                                    .collect();
-                                Ok(Exp::Procedure((
-                                    params_as_strings?,
-                                    Box::new(rest[1].clone()),
-                                )))
-                            } else {
-                                return Err(Exceptions::ValueError(
-                                    "Expected a params LIST after lambda keyword".to_string(),
-                                ));
-                            }
-                        }
-                        "print!" => printer(rest, env, false),
-                        "println!" => printer(rest, env, true),
-                        _ => {
-                            // must be a function
-                            match f? {
-                                Exp::Func(function) => func_handler(function, rest, env),
-                                Exp::Procedure(proc) => proc_handler(&proc, rest, env),
-                                _ => {
-                                    return Err(Exceptions::ValueError(
-                                        format!("{} is not a defined as a function", s).to_string(),
-                                    ));
-                                }
-                            }
-                        }
-                    },
-                    Exp::Procedure(proc) => proc_handler(proc, rest, env),
-                    Exp::Func(func) => func_handler(*func, rest, env),
-                    _ => match f {
-                        Err(_) => {
-                            return Err(Exceptions::ValueError(
-                                format!(
-                                    "First thing in an expression should be a keyword or a function not {}",
-                                    first
-                                )
-                                .to_string(),
-                            ));
-                        }
-                        Ok(g) => match &g {
-                            Exp::Func(_) => {
-                                let mut new_exp_internals = vec![g];
-                                new_exp_internals.append(&mut rest.to_vec());
-                                let new_exp = Exp::List(Rc::new(new_exp_internals));
-                                return eval(&new_exp, env);
-                            }
-                            Exp::Procedure(_) => {
-                                let mut new_exp_internals = vec![g];
-                                new_exp_internals.append(&mut rest.to_vec());
-                                let new_exp = Exp::List(Rc::new(new_exp_internals));
-                                return eval(&new_exp, env);
-                            }
-                            _ => Ok(exp.clone()),
-                        },
-                    },
-                }
+    let mut x = match exp {
+        Exp::List(x) => Rc::clone(x),
+        _ => return eval_atom(exp, env),
+    };
+    let mut env = env.clone();
+    let mut current: Option<Rc<Procedure>> = None;
+    let trace = |e: Exceptions, x: &List, current: &Option<Rc<Procedure>>| {
+        let e = e.at(x.span.as_ref());
+        match current {
+            Some(proc) => e.in_procedure(proc.display_name()),
+            None => e,
+        }
+    };
+    loop {
+        let next = match step(&x, &mut env) {
+            Ok(Tail::Return(value)) => return Ok(value),
+            Ok(Tail::Eval(next)) => next,
+            Ok(Tail::Call(proc)) => {
+                let body = proc.body.as_ref().clone();
+                current = Some(proc);
+                body
+            }
+            Err(e) => return Err(trace(e, &x, &current)),
+        };
+        x = match next {
+            Exp::List(l) => l,
+            _ => return eval_atom(&next, &env).map_err(|e| trace(e, &x, &current)),
+        };
+    }
+}
+
+/// Evaluates the list `x`, replacing `env` with the environment of the
+/// procedure body when `x` is a call to a user defined procedure.
+fn step(x: &List, env: &mut Environment) -> Result<Tail, Exceptions> {
+    let (first, rest) = x
+        .split_first()
+        .ok_or_else(|| Exceptions::ValueError("Expected a non empty list".to_string()))?;
+    if let Exp::Atom(Atom::Symbol(s)) = first {
+        match &s[..] {
+            "if" => return Ok(Tail::Eval(if_handler(x, env)?)),
+            "begin" => return Ok(Tail::Eval(begin_handler(rest, env)?)),
+            "define" => return define_handler(x, env).map(Tail::Return),
+            "quote" => {
+                return rest.first().cloned().map(Tail::Return).ok_or_else(|| {
+                    Exceptions::ValueError("Expected something after quote".to_string())
+                });
             }
+            "set!" => return set_handler(rest, env).map(Tail::Return),
+            "lambda" => return lambda_handler(rest, env).map(Tail::Return),
+            "guard" => return guard_handler(rest, env),
+            _ => {}
+        }
+    }
+    // must be a function application
+    match eval(first, env).map_err(|e| e.at(x.item_span(0)))? {
+        f @ (Exp::Func(_) | Exp::NativeClosure(_)) => {
+            let args = eval_args(x, env)?;
+            apply(&f, args, env).map(Tail::Return)
+        }
+        Exp::Procedure(proc) => {
+            let args = eval_args(x, env)?;
+            *env = bind_args(&proc, args)?;
+            Ok(Tail::Call(proc))
         }
-        Exp::Str(s) => Ok(Exp::Str(s.clone())),
-        _ => Err(Exceptions::ValueError("Invalid form".to_string())),
+        f => Err(Exceptions::type_error("a procedure", &f)),
     }
 }
 
+fn eval_atom(exp: &Exp, env: &Environment) -> Result<Exp, Exceptions> {
+    match exp {
+        Exp::Atom(Atom::Symbol(y)) => env
+            .get(y)
+            .ok_or_else(|| Exceptions::UnboundVariable(y.clone())),
+        _ => Ok(exp.clone()),
+    }
+}
+
+/// Evaluates the test of an `if` and returns the branch to be evaluated
+/// next, which is in tail position. Any value other than `#f` counts as
+/// true; `(if test conseq)` is `#f` when the test fails.
 fn if_handler(args: &[Exp], env: &mut Environment) -> Result<Exp, Exceptions> {
     let x = args;
-    if x.len() == 4 {
-        let (test, conseq, alt) = (&x[1], &x[2], &x[3]);
-        if let Exp::Atom(Atom::Bool(test_evaluated)) = eval(test, env)? {
-            if test_evaluated {
-                return eval(conseq, env);
-            } else {
-                return eval(alt, env);
-            }
+    if x.len() == 3 || x.len() == 4 {
+        if eval(&x[1], env)?.is_truthy() {
+            Ok(x[2].clone())
         } else {
-            return Err(Exceptions::ValueError(
-                format!("{} doesnt evaluate to a boolean", test).to_string(),
-            ));
+            Ok(x.get(3).cloned().unwrap_or(Exp::Atom(Atom::Bool(false))))
         }
     } else {
-        return Err(Exceptions::ValueError(
+        Err(Exceptions::ValueError(
             "Not a valid if expression".to_string(),
+        ))
+    }
+}
+
+/// Evaluates every expression of a `begin` except the last one, which is
+/// returned to be evaluated in tail position.
+fn begin_handler(args: &[Exp], env: &mut Environment) -> Result<Exp, Exceptions> {
+    let (last, init) = args.split_last().ok_or_else(|| {
+        Exceptions::ValueError("Expected atleast one expression after begin".to_string())
+    })?;
+    for e in init {
+        eval(e, env)?;
+    }
+    Ok(last.clone())
+}
+
+fn set_handler(args: &[Exp], env: &mut Environment) -> Result<Exp, Exceptions> {
+    if args.len() != 2 {
+        Err(Exceptions::ValueError(
+            "Expected 2 things after set!,symbol followed by an expression".to_string(),
+        ))
+    } else if let Exp::Atom(Atom::Symbol(x)) = &args[0] {
+        let evaluated_exp = eval(&args[1], env)?;
+        if env.set(x, evaluated_exp.clone()) {
+            Ok(evaluated_exp)
+        } else {
+            Err(Exceptions::ValueError(
+                " set!: assignment disallowed\
+                    , cannot set variable before its definition "
+                    .to_string(),
+            ))
+        }
+    } else {
+        Err(Exceptions::ValueError(
+            "Expected a symbol after set!, got something else".to_string(),
+        ))
+    }
+}
+
+fn lambda_handler(args: &[Exp], env: &Environment) -> Result<Exp, Exceptions> {
+    // params list followed by the body
+    if args.len() < 2 {
+        return Err(Exceptions::SyntaxError(
+            "lambda expects a params list and body".to_string(),
         ));
     }
+    let (params, rest) = match &args[0] {
+        Exp::List(params) => parse_params(params)?,
+        Exp::Atom(Atom::Symbol(rest)) => (vec![], Some(rest.clone())),
+        _ => {
+            return Err(Exceptions::ValueError(
+                "Expected a params LIST after lambda keyword".to_string(),
+            ))
+        }
+    };
+    Ok(Exp::Procedure(Rc::new(Procedure {
+        name: None,
+        params,
+        rest,
+        body: Box::new(body(&args[1..])),
+        env: env.clone(),
+    })))
+}
+
+/// A procedure body made of `exps`, which are evaluated in order like a
+/// `begin`.
+fn body(exps: &[Exp]) -> Exp {
+    match exps {
+        [exp] => exp.clone(),
+        _ => {
+            let mut items = vec![Exp::Atom(Atom::Symbol("begin".to_string()))];
+            items.extend(exps.iter().cloned());
+            Exp::List(Rc::new(items.into()))
+        }
+    }
+}
+
+/// Splits a parameter list into the required parameters and the parameter
+/// after a `.`, which collects any further arguments.
+fn parse_params(params: &[Exp]) -> Result<(Vec<String>, Option<String>), Exceptions> {
+    let mut names = vec![];
+    let mut iter = params.iter();
+    while let Some(param) = iter.next() {
+        match param {
+            Exp::Atom(Atom::Symbol(s)) if s == "." => {
+                return match (iter.next(), iter.next()) {
+                    (Some(Exp::Atom(Atom::Symbol(rest))), None) => Ok((names, Some(rest.clone()))),
+                    _ => Err(Exceptions::SyntaxError(
+                        "expected exactly one parameter after .".to_string(),
+                    )),
+                };
+            }
+            Exp::Atom(Atom::Symbol(s)) => names.push(s.clone()),
+            _ => {
+                return Err(Exceptions::ValueError(
+                    "non symbol passed in a lambda parameters list".to_string(),
+                ))
+            }
+        }
+    }
+    Ok((names, None))
 }
 
 fn define_handler(args: &[Exp], env: &mut Environment) -> Result<Exp, Exceptions> {
-    if args.len() == 3 {
+    if args.len() >= 3 {
         let (symbol, exp) = (&args[1], &args[2]);
         match symbol {
-            Exp::Atom(Atom::Symbol(x)) => {
+            Exp::Atom(Atom::Symbol(x)) if args.len() == 3 => {
                 /*
                  * (define f 10)
                  * (define g (lambda (x) (+ x x)))
                  */
-                let evaluated_exp = eval(exp, env)?;
+                let evaluated_exp = match eval(exp, env)? {
+                    Exp::Procedure(proc) if proc.name.is_none() => {
+                        Exp::Procedure(Rc::new(Procedure {
+                            name: Some(x.clone()),
+                            ..proc.as_ref().clone()
+                        }))
+                    }
+                    value => value,
+                };
                 env.insert(x.clone(), evaluated_exp.clone());
-                return Ok(evaluated_exp);
+                Ok(evaluated_exp)
             }
             Exp::List(lst) => {
                 /*
                  * (define (f x y) (+ x y))
                  */
-                let all_are_symbols = lst.iter().all(|x| match x {
-                    Exp::Atom(Atom::Symbol(_)) => true,
-                    _ => false,
-                });
+                let all_are_symbols = lst.iter().all(|x| matches!(x, Exp::Atom(Atom::Symbol(_))));
                 if !all_are_symbols {
                     return Err(Exceptions::ValueError(
-                            format!("define expression of form (define ( (ident )+ ) (body) ). This define expression '{}' is not a satisfying '(ident)+' property ",Exp::List(Rc::new(lst.to_vec()))).to_string()
+                            format!("define expression of form (define ( (ident )+ ) (body) ). This define expression '{}' is not a satisfying '(ident)+' property ",Exp::List(Rc::clone(lst))).to_string()
                             ));
                 }
 
-                let (func_name_exp, func_args) = lst.split_first().unwrap();
-                let params = Rc::new(func_args.to_vec());
-                let params_as_strings: Result<Vec<String>, Exceptions> = params
-                    .iter()
-                    .map(|x| {
-                        if let Exp::Atom(Atom::Symbol(y)) = x {
-                            return Ok(y.clone());
-                        } else {
-                            return Err(Exceptions::ValueError(
-                                "non symbol passed in a lambda parameters list".to_string(),
-                            ));
-                        }
-                    })
-                    .collect();
+                let (func_name_exp, func_args) = lst.split_first().ok_or_else(|| {
+                    Exceptions::ValueError("Expected a function name in define".to_string())
+                })?;
+                let (params, rest) = parse_params(func_args)?;
                 /*
                  * This is always true because we checked already with all_are_symbols
                  */
 
-                let proc = Exp::Procedure((params_as_strings?, Box::new(exp.clone())));
                 if let Exp::Atom(Atom::Symbol(func_name)) = func_name_exp {
+                    let proc = Exp::Procedure(Rc::new(Procedure {
+                        name: Some(func_name.clone()),
+                        params,
+                        rest,
+                        body: Box::new(body(&args[2..])),
+                        env: env.clone(),
+                    }));
                     env.insert(func_name.clone(), proc.clone());
+                    Ok(proc)
+                } else {
+                    Err(Exceptions::ValueError(
+                        "Expected a function name in define".to_string(),
+                    ))
                 }
-                Ok(proc)
-            }
-            _ => {
-                return Err(Exceptions::ValueError(
-                    format!("Invalid define expression {}", symbol).to_string(),
-                ));
             }
+            _ => Err(Exceptions::ValueError(
+                format!("Invalid define expression {}", symbol).to_string(),
+            )),
         }
     } else {
-        return Err(Exceptions::ValueError(
+        Err(Exceptions::ValueError(
             "Not a valid define expression".to_string(),
-        ));
+        ))
     }
 }
 
-fn proc_handler(
-    proc: &(Vec<String>, Box<Exp>),
-    rest: &[Exp],
-    env: &mut Environment,
-) -> Result<Exp, Exceptions> {
-    if rest.len() == proc.0.len() {
-        let rest_evaluated: Result<Vec<Exp>, Exceptions> =
-            rest.iter().map(|x| eval(x, env)).collect();
-        let rest_evaluated = rest_evaluated?;
-        env.push_stack_frame(HashMap::new());
-        for i in 0..rest_evaluated.len() {
-            env.insert(proc.0[i].clone(), rest_evaluated[i].clone());
+/// Evaluates the arguments of the call `x`. Errors are tagged with where
+/// the argument is, since atoms don't carry a span themselves.
+fn eval_args(x: &List, env: &mut Environment) -> Result<Vec<Exp>, Exceptions> {
+    (1..x.len())
+        .map(|i| eval(&x[i], env).map_err(|e| e.at(x.item_span(i))))
+        .collect()
+}
+
+fn bind_args(proc: &Procedure, mut args: Vec<Exp>) -> Result<Environment, Exceptions> {
+    let required = proc.params.len();
+    let arity_ok = match proc.rest {
+        None => args.len() == required,
+        Some(_) => args.len() >= required,
+    };
+    if !arity_ok {
+        return Err(Exceptions::ArityMismatch {
+            name: proc.display_name().to_string(),
+            expected: match proc.rest {
+                None => required.to_string(),
+                Some(_) => format!("at least {}", required),
+            },
+            got: args.len(),
+        });
+    }
+    let extra = args.split_off(required);
+    let mut bindings: Env = proc.params.iter().cloned().zip(args).collect();
+    if let Some(rest) = &proc.rest {
+        bindings.insert(rest.clone(), Exp::List(Rc::new(extra.into())));
+    }
+    /*
+     * The body runs in a frame whose parent is the environment the
+     * procedure was created in, not the caller's environment.
+     */
+    Ok(proc.env.extend(bindings))
+}
+
+/// Calls the procedure or builtin `f` with already evaluated `args`.
+/// Builtins are called with `env`.
+pub fn apply(f: &Exp, args: Vec<Exp>, env: &mut Environment) -> Result<Exp, Exceptions> {
+    match f {
+        Exp::Func(function) => function(&args, env),
+        Exp::NativeClosure(native) => native.call(&args, env),
+        Exp::Procedure(proc) => {
+            let mut call_env = bind_args(proc, args)?;
+            eval(proc.body.as_ref(), &mut call_env).map_err(|e| e.in_procedure(proc.display_name()))
         }
-        let ans = eval(proc.1.as_ref(), env);
-        env.pop_stack_frame();
-        ans
-    } else {
-        return Err(Exceptions::ValueError(
-            format!("Expected {} arguments but got {}", proc.0.len(), rest.len()).to_string(),
-        ));
+        _ => Err(Exceptions::type_error("a procedure", f)),
     }
 }
 
-fn func_handler(
-    function: fn(&[Exp]) -> Result<Exp, Exceptions>,
-    args: &[Exp],
-    env: &mut Environment,
-) -> Result<Exp, Exceptions> {
-    let rest_evaluated: Result<Vec<Exp>, Exceptions> = args.iter().map(|x| eval(x, env)).collect();
-    let func_result = function(&rest_evaluated?)?;
-    let eval_again = eval(&func_result, env);
-    eval_again.or_else(|_| Ok(func_result))
+/// The object risp code sees for a caught exception: whatever was passed
+/// to `raise`, or an error object wrapping any other exception.
+pub fn condition(e: Exceptions) -> Exp {
+    match e.root() {
+        Exceptions::Raised(payload) => payload.clone(),
+        _ => Exp::Condition(Rc::new(e)),
+    }
 }
 
-fn printer(args: &[Exp], env: &mut Environment, new_line: bool) -> Result<Exp, Exceptions> {
-    let rest_evaluated: Result<Vec<Exp>, Exceptions> = args.iter().map(|x| eval(x, env)).collect();
-    let printable_form = rest_evaluated
-        .unwrap()
-        .iter()
-        .map(|x| format!("{}", x))
-        .collect::<Vec<String>>()
-        .join(" ");
-    if !new_line {
-        print!("{}", printable_form);
-    } else {
-        println!("{}", printable_form);
+/// (guard (var clause ...) body ...)
+///
+/// Evaluates `body`; if it raises, binds `var` to the condition and tries
+/// the clauses like `cond`, re-raising when none of them match. The body
+/// of the matching clause is in tail position.
+fn guard_handler(args: &[Exp], env: &mut Environment) -> Result<Tail, Exceptions> {
+    let (spec, body) = args.split_first().ok_or_else(guard_syntax_error)?;
+    let (var, clauses) = match spec {
+        Exp::List(spec) => match spec.split_first() {
+            Some((Exp::Atom(Atom::Symbol(var)), clauses)) => (var, clauses),
+            _ => return Err(guard_syntax_error()),
+        },
+        _ => return Err(guard_syntax_error()),
+    };
+    if body.is_empty() {
+        return Err(guard_syntax_error());
+    }
+    // the body is not in tail position, its exceptions have to be caught
+    // here. A `raise-continuable` in it calls the guard's handler first.
+    let caught = Rc::new(RefCell::new(None));
+    env.push_handler(continuable_handler(var, clauses, env, &caught));
+    let result = begin_handler(body, env).and_then(|last| eval(&last, env));
+    env.pop_handler();
+    let e = match result {
+        Ok(value) => return Ok(Tail::Return(value)),
+        Err(e) => e,
+    };
+    let mut guard_env = env.extend(Env::from([(var.clone(), condition(e.clone()))]));
+    let (index, test) = match caught.take() {
+        Some(Caught::Clause(index, test)) => (index, test),
+        Some(Caught::PassedOn) => return Err(e),
+        None => match test_clauses(clauses, &mut guard_env)? {
+            Some(matched) => matched,
+            None => return Err(e),
+        },
+    };
+    match &guard_clause(&clauses[index])?[1..] {
+        [] => Ok(Tail::Return(test)),
+        [Exp::Atom(Atom::Symbol(arrow)), receiver] if arrow == "=>" => {
+            let receiver = eval(receiver, &mut guard_env)?;
+            apply(&receiver, vec![test], env).map(Tail::Return)
+        }
+        exps => {
+            let last = begin_handler(exps, &mut guard_env)?;
+            *env = guard_env;
+            Ok(Tail::Eval(last))
+        }
     }
-    Ok(Exp::Str(printable_form))
-    // unimplemented!()
+}
+
+fn guard_syntax_error() -> Exceptions {
+    Exceptions::SyntaxError("guard expects (guard (var clause ...) body ...)".to_string())
+}
+
+fn guard_clause(clause: &Exp) -> Result<&List, Exceptions> {
+    match clause {
+        Exp::List(clause) if !clause.is_empty() => Ok(clause),
+        _ => Err(guard_syntax_error()),
+    }
+}
+
+/// The index of the first clause of a `guard` whose test holds in
+/// `guard_env`, along with the value of the test.
+fn test_clauses(
+    clauses: &[Exp],
+    guard_env: &mut Environment,
+) -> Result<Option<(usize, Exp)>, Exceptions> {
+    for (index, clause) in clauses.iter().enumerate() {
+        let test = match &guard_clause(clause)?[0] {
+            Exp::Atom(Atom::Symbol(s)) if s == "else" => Exp::Atom(Atom::Bool(true)),
+            test => eval(test, guard_env)?,
+        };
+        if test.is_truthy() {
+            return Ok(Some((index, test)));
+        }
+    }
+    Ok(None)
+}
+
+/// What the handler of a `guard` did with a `raise-continuable`, so that
+/// the clauses aren't tested again once the body is left.
+enum Caught {
+    /// The clause at this index matched, with the value of its test.
+    Clause(usize, Exp),
+    /// No clause matched, so the object was passed on to the outer
+    /// handlers.
+    PassedOn,
+}
+
+/// The handler a `guard` installs while its body runs. It tests the
+/// clauses where `raise-continuable` is called. If one matches, the body
+/// is left to evaluate it; otherwise the outer handlers are called, and
+/// what they return is what `raise-continuable` returns.
+fn continuable_handler(
+    var: &str,
+    clauses: &[Exp],
+    env: &Environment,
+    caught: &Rc<RefCell<Option<Caught>>>,
+) -> Exp {
+    let (var, clauses, env, caught) = (
+        var.to_string(),
+        clauses.to_vec(),
+        env.clone(),
+        Rc::clone(caught),
+    );
+    Exp::NativeClosure(Rc::new(NativeClosure {
+        name: "guard".to_string(),
+        arity: Arity::Exactly(1),
+        func: Box::new(move |args, outer_env| {
+            let mut guard_env = env.extend(Env::from([(var.clone(), args[0].clone())]));
+            match test_clauses(&clauses, &mut guard_env)? {
+                Some((index, test)) => {
+                    *caught.borrow_mut() = Some(Caught::Clause(index, test));
+                    Err(Exceptions::Raised(args[0].clone()))
+                }
+                None => {
+                    *caught.borrow_mut() = Some(Caught::PassedOn);
+                    let value = raise_continuable(args, outer_env)?;
+                    *caught.borrow_mut() = None;
+                    Ok(value)
+                }
+            }
+        }),
+    }))
 }
@@ -0,0 +1,1428 @@
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use super::builtin_functions;
+use super::eval::{apply, eval};
+use super::types::*;
+
+/// Forms the expander understands itself. Only some of them are passed on
+/// to `eval`, the others are rewritten in terms of those.
+const CORE_FORMS: [&str; 25] = [
+    "quote",
+    "if",
+    "define",
+    "set!",
+    "lambda",
+    "begin",
+    "guard",
+    "define-syntax",
+    "let-syntax",
+    "letrec-syntax",
+    "syntax-rules",
+    "define-macro",
+    "quasiquote",
+    "unquote",
+    "unquote-splicing",
+    "let",
+    "let*",
+    "letrec",
+    "letrec*",
+    "cond",
+    "case",
+    "when",
+    "unless",
+    "and",
+    "or",
+];
+
+/// Names `eval` gives a meaning to in some position, so local variables
+/// can't be called that at runtime.
+const RESERVED: [&str; 2] = ["else", "=>"];
+
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    static ALIASES: RefCell<HashMap<Symbol, Alias>> = RefCell::new(HashMap::new());
+    /// How many calls to `expand` and `macroexpand` are running.
+    static EXPANSIONS: Cell<usize> = const { Cell::new(0) };
+    /// The symbols in `syntax-rules` templates that aren't pattern
+    /// variables. Locals with these names are renamed, so that they can't
+    /// capture a template's reference to a global, even one that isn't
+    /// defined yet.
+    static TEMPLATE_NAMES: RefCell<HashSet<Symbol>> = RefCell::new(HashSet::new());
+}
+
+/// Runs `f` as one expansion. Expanded code has no aliases left in it, so
+/// they are dropped once the outermost expansion is done.
+fn expansion<T>(f: impl FnOnce() -> T) -> T {
+    EXPANSIONS.with(|n| n.set(n.get() + 1));
+    let result = f();
+    EXPANSIONS.with(|n| {
+        n.set(n.get() - 1);
+        if n.get() == 0 {
+            ALIASES.with(|a| a.borrow_mut().clear());
+        }
+    });
+    result
+}
+
+/// How many aliases are known, to check that they don't pile up.
+#[cfg(test)]
+pub(crate) fn alias_count() -> usize {
+    ALIASES.with(|a| a.borrow().len())
+}
+
+#[derive(Clone)]
+enum Binding {
+    /// One of `CORE_FORMS`.
+    Core(&'static str),
+    /// A variable, with the name it has at runtime.
+    Variable(Symbol),
+    /// A macro, with the number of scopes visible where it was defined.
+    Macro(Rc<Macro>, usize),
+}
+
+impl Binding {
+    fn same(&self, other: &Binding) -> bool {
+        match (self, other) {
+            (Binding::Core(a), Binding::Core(b)) => a == b,
+            (Binding::Variable(a), Binding::Variable(b)) => a == b,
+            (Binding::Macro(a, _), Binding::Macro(b, _)) => Rc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
+struct Scope {
+    bindings: HashMap<Symbol, Binding>,
+    /// Whether this is the body of a procedure, where internal `define`s
+    /// end up.
+    lambda: bool,
+}
+
+/// What a pattern variable of `syntax-rules` matched.
+#[derive(Clone)]
+enum MatchTree {
+    One(Exp),
+    /// The matches of a subpattern followed by an ellipsis.
+    Many(Vec<MatchTree>),
+}
+
+type Matches = HashMap<Symbol, MatchTree>;
+
+/// Expands every macro use in `exp`, leaving only the forms `eval`
+/// understands.
+///
+/// Expansion is hygienic: symbols introduced by a `syntax-rules` template
+/// refer to the bindings visible where the macro was defined, and variables
+/// bound by a template can't capture variables at the use site. To keep
+/// runtime lookup by name correct, local variables that could be confused
+/// with another binding are renamed to `name.N`.
+///
+/// Top level `define-syntax` forms add their macro to `env` right away, so
+/// later expressions can use it.
+pub fn expand(exp: &Exp, env: &Environment) -> Result<Exp, Exceptions> {
+    expansion(|| {
+        Expander {
+            env,
+            scopes: vec![],
+        }
+        .expand(exp)
+    })
+}
+
+/// Expands `exp` as long as it is a macro use, or just once if `once` is
+/// set. Only the outermost form is expanded, subforms are left as they are.
+pub fn macroexpand(exp: &Exp, env: &Environment, once: bool) -> Result<Exp, Exceptions> {
+    expansion(|| {
+        let mut expander = Expander {
+            env,
+            scopes: vec![],
+        };
+        let mut exp = exp.clone();
+        while let Some(expanded) = expander.expand_once(&exp)? {
+            exp = expanded;
+            if once {
+                break;
+            }
+        }
+        Ok(strip(&exp))
+    })
+}
+
+struct Expander<'a> {
+    env: &'a Environment,
+    scopes: Vec<Scope>,
+}
+
+fn symbol(name: &str) -> Exp {
+    Exp::Atom(Atom::Symbol(name.to_string()))
+}
+
+fn list(items: Vec<Exp>, span: &Option<Span>) -> Exp {
+    Exp::List(Rc::new(List::new(items, span.clone())))
+}
+
+fn syntax_error(message: &str) -> Exceptions {
+    Exceptions::SyntaxError(message.to_string())
+}
+
+/// The symbol `name` was renamed from, if it is an alias.
+fn unalias(name: &str) -> Symbol {
+    let mut name = name.to_string();
+    while let Some(original) = ALIASES.with(|a| a.borrow().get(&name).map(|a| a.name.clone())) {
+        name = original;
+    }
+    name
+}
+
+/// `exp` with every alias replaced by the symbol it was renamed from, for
+/// quoted data.
+fn strip(exp: &Exp) -> Exp {
+    match exp {
+        Exp::Atom(Atom::Symbol(s)) => symbol(&unalias(s)),
+        Exp::List(l) => list(l.iter().map(strip).collect(), &l.span),
+        _ => exp.clone(),
+    }
+}
+
+/// Fails if `form` binds a variable twice. `vars` are the variables it
+/// binds, along with where they are written.
+fn check_unique<'v>(
+    form: &str,
+    vars: impl IntoIterator<Item = (&'v Symbol, Option<&'v Span>)>,
+) -> Result<(), Exceptions> {
+    let mut seen = vec![];
+    for (var, span) in vars {
+        if seen.contains(&var) {
+            return Err(Exceptions::SyntaxError(format!(
+                "duplicate variable {} in {}",
+                unalias(var),
+                form
+            ))
+            .at(span));
+        }
+        seen.push(var);
+    }
+    Ok(())
+}
+
+/// The parameters in `params` from index `start` on, with their spans,
+/// leaving out the `.` before a rest parameter.
+fn params_with_spans(params: &List, start: usize) -> Vec<(&Symbol, Option<&Span>)> {
+    (start..params.len())
+        .filter_map(|i| match &params[i] {
+            p @ Exp::Atom(Atom::Symbol(s)) if !is_dot(p) => Some((s, params.item_span(i))),
+            _ => None,
+        })
+        .collect()
+}
+
+/// The datum of a `(quote datum)` form built by the expander.
+fn quoted_datum(exp: &Exp) -> Option<Exp> {
+    match exp {
+        Exp::List(l) => match &l[..] {
+            [Exp::Atom(Atom::Symbol(q)), datum] if q == "quote" => Some(datum.clone()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Adds the aliases among the symbols in `exp` to `aliases`, along with the
+/// aliases they were renamed from.
+fn collect_aliases(exp: &Exp, aliases: &mut HashMap<Symbol, Alias>) {
+    match exp {
+        Exp::Atom(Atom::Symbol(s)) => {
+            let mut name = s.clone();
+            while let Some(alias) = ALIASES.with(|a| a.borrow().get(&name).cloned()) {
+                aliases.insert(name, alias.clone());
+                name = alias.name;
+            }
+        }
+        Exp::List(l) => l.iter().for_each(|e| collect_aliases(e, aliases)),
+        _ => {}
+    }
+}
+
+fn fresh_id() -> usize {
+    NEXT_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+fn new_alias(name: &str, depth: usize) -> Symbol {
+    let alias = format!("{}#{}", name, fresh_id());
+    ALIASES.with(|a| {
+        a.borrow_mut().insert(
+            alias.clone(),
+            Alias {
+                name: name.to_string(),
+                depth,
+            },
+        )
+    });
+    alias
+}
+
+impl<'a> Expander<'a> {
+    fn resolve(&self, id: &str) -> Binding {
+        self.resolve_in(id, self.scopes.len())
+    }
+
+    /// Looks `id` up in the innermost `limit` scopes, then in the global
+    /// environment.
+    fn resolve_in(&self, id: &str, limit: usize) -> Binding {
+        for scope in self.scopes[..limit].iter().rev() {
+            if let Some(binding) = scope.bindings.get(id) {
+                return binding.clone();
+            }
+        }
+        let alias = ALIASES.with(|a| a.borrow().get(id).map(|a| (a.name.clone(), a.depth)));
+        if let Some((name, depth)) = alias {
+            return self.resolve_in(&name, depth.min(limit));
+        }
+        if let Some(core) = CORE_FORMS.iter().find(|k| **k == id) {
+            return Binding::Core(core);
+        }
+        match self.env.get(id) {
+            Some(Exp::Macro(m)) => Binding::Macro(m, 0),
+            _ => Binding::Variable(id.to_string()),
+        }
+    }
+
+    fn push_scope(&mut self, lambda: bool) {
+        self.scopes.push(Scope {
+            bindings: HashMap::new(),
+            lambda,
+        });
+    }
+
+    /// Binds the variable `id` in the scope at `index`, returning its
+    /// runtime name.
+    fn bind(&mut self, index: usize, id: &str) -> Symbol {
+        let base = unalias(id);
+        let taken = base != id
+            || CORE_FORMS.contains(&&base[..])
+            || RESERVED.contains(&&base[..])
+            || self.env.exists(&base)
+            || TEMPLATE_NAMES.with(|t| t.borrow().contains(&base))
+            || self.scopes.iter().any(|s| {
+                s.bindings
+                    .values()
+                    .any(|b| matches!(b, Binding::Variable(v) if *v == base))
+            });
+        let name = if taken {
+            format!("{}.{}", base, fresh_id())
+        } else {
+            base
+        };
+        self.scopes[index]
+            .bindings
+            .insert(id.to_string(), Binding::Variable(name.clone()));
+        name
+    }
+
+    /// The runtime name of the variable a `define` of `id` defines: a
+    /// local of the innermost procedure body, or a global.
+    fn define_target(&mut self, id: &str) -> Symbol {
+        match self.scopes.iter().rposition(|s| s.lambda) {
+            None => unalias(id),
+            Some(index) => match self.scopes[index].bindings.get(id) {
+                Some(Binding::Variable(name)) => name.clone(),
+                _ => self.bind(index, id),
+            },
+        }
+    }
+
+    fn expand(&mut self, exp: &Exp) -> Result<Exp, Exceptions> {
+        match exp {
+            Exp::Atom(Atom::Symbol(s)) => match self.resolve(s) {
+                Binding::Variable(name) => Ok(symbol(&name)),
+                Binding::Core(name) => Ok(symbol(name)),
+                Binding::Macro(..) => Err(Exceptions::SyntaxError(format!(
+                    "bad use of macro {}",
+                    unalias(s)
+                ))),
+            },
+            Exp::List(l) => self.expand_list(l).map_err(|e| e.at(l.span.as_ref())),
+            _ => Ok(exp.clone()),
+        }
+    }
+
+    fn expand_all(&mut self, exps: &[Exp]) -> Result<Vec<Exp>, Exceptions> {
+        exps.iter().map(|x| self.expand(x)).collect()
+    }
+
+    /// Expands the body of a procedure or `let-syntax`, binding its
+    /// internal `define`s first so that they can refer to each other.
+    fn expand_body(&mut self, body: &[Exp]) -> Result<Vec<Exp>, Exceptions> {
+        if self.scopes.iter().any(|s| s.lambda) {
+            self.declare_defines(body);
+        }
+        self.expand_all(body)
+    }
+
+    /// Binds the variables defined by the `define`s in `body`, including
+    /// those in `begin`s.
+    fn declare_defines(&mut self, body: &[Exp]) {
+        for exp in body {
+            let l = match exp {
+                Exp::List(l) => l,
+                _ => continue,
+            };
+            let head = match l.first() {
+                Some(Exp::Atom(Atom::Symbol(head))) => self.resolve(head),
+                _ => continue,
+            };
+            match (head, l.get(1)) {
+                (Binding::Core("begin"), _) => self.declare_defines(&l[1..]),
+                (Binding::Core("define"), Some(Exp::Atom(Atom::Symbol(id)))) => {
+                    self.define_target(id);
+                }
+                (Binding::Core("define"), Some(Exp::List(sig))) => {
+                    if let Some(Exp::Atom(Atom::Symbol(id))) = sig.first() {
+                        self.define_target(id);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn expand_list(&mut self, l: &Rc<List>) -> Result<Exp, Exceptions> {
+        let binding = match l.first() {
+            Some(Exp::Atom(Atom::Symbol(head))) => self.resolve(head),
+            _ => return Ok(Exp::List(Rc::new(l.with_items(self.expand_all(l)?)))),
+        };
+        match binding {
+            Binding::Core(form) => self.expand_core(form, l),
+            Binding::Macro(m, depth) => {
+                let expanded = self.apply_macro(&m, depth, l)?;
+                self.expand(&expanded)
+            }
+            Binding::Variable(_) => Ok(Exp::List(Rc::new(l.with_items(self.expand_all(l)?)))),
+        }
+    }
+
+    /// The form the macro use `exp` stands for, or `None` if it isn't one.
+    fn expand_once(&mut self, exp: &Exp) -> Result<Option<Exp>, Exceptions> {
+        if let Exp::List(l) = exp {
+            if let Some(Exp::Atom(Atom::Symbol(head))) = l.first() {
+                if let Binding::Macro(m, depth) = self.resolve(head) {
+                    return self.apply_macro(&m, depth, l).map(Some);
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    fn apply_macro(&mut self, m: &Macro, depth: usize, form: &List) -> Result<Exp, Exceptions> {
+        match m {
+            Macro::SyntaxRules(rules) => self.transcribe_macro(rules, depth, form),
+            Macro::Procedure(transformer) => {
+                let args = form[1..].iter().map(strip).collect();
+                match apply(transformer, args, &mut self.env.clone())? {
+                    // lists built by the transformer get the span of the use
+                    Exp::List(l) if l.span.is_none() => Ok(list(l.to_vec(), &form.span)),
+                    expanded => Ok(expanded),
+                }
+            }
+        }
+    }
+
+    /// Binds `id` to the macro `m`, globally at top level.
+    fn bind_macro(&mut self, id: &str, m: Macro) {
+        let m = Rc::new(m);
+        if self.scopes.is_empty() {
+            self.env.insert(unalias(id), Exp::Macro(m));
+        } else {
+            let depth = self.scopes.len();
+            self.scopes[depth - 1]
+                .bindings
+                .insert(id.to_string(), Binding::Macro(m, depth));
+        }
+    }
+
+    /// Evaluates the transformer of a `define-macro` in the global
+    /// environment; it runs during expansion, before any local variable
+    /// exists.
+    fn macro_procedure(&self, name: &str, transformer: &Exp) -> Result<Macro, Exceptions> {
+        let transformer = expand(transformer, self.env)?;
+        match eval(&transformer, &mut self.env.clone())? {
+            Exp::Procedure(proc) if proc.name.is_none() => {
+                Ok(Macro::Procedure(Exp::Procedure(Rc::new(Procedure {
+                    name: Some(name.to_string()),
+                    ..proc.as_ref().clone()
+                }))))
+            }
+            f if f.is_procedure() => Ok(Macro::Procedure(f)),
+            f => Err(Exceptions::type_error("a procedure", &f)),
+        }
+    }
+
+    /// The top level binding of `name`, whatever it is bound to where it is
+    /// used.
+    fn top_level(&self, name: &str) -> Exp {
+        symbol(&new_alias(name, 0))
+    }
+
+    fn lambda(&self, params: Vec<Exp>, body: &[Exp], span: &Option<Span>) -> Exp {
+        let mut items = vec![self.top_level("lambda"), list(params, span)];
+        items.extend(body.iter().cloned());
+        list(items, span)
+    }
+
+    /// Rewrites the binding forms in terms of `lambda` and `define`:
+    ///
+    /// - `(let ((v e) ...) body ...)` is `((lambda (v ...) body ...) e ...)`
+    /// - `(let* ((v e) rest ...) body ...)` is `(let ((v e)) (let* (rest ...) body ...))`
+    /// - `(letrec ((v e) ...) body ...)` is `((lambda () (define v e) ... body ...))`
+    /// - `(let name ((v e) ...) body ...)` is
+    ///   `((letrec ((name (lambda (v ...) body ...))) name) e ...)`
+    fn rewrite_let(&self, form: &str, l: &List) -> Result<Exp, Exceptions> {
+        let span = &l.span;
+        let bad = || {
+            Exceptions::SyntaxError(format!(
+                "{} expects ((name value) ...) followed by a body",
+                form
+            ))
+        };
+        let (name, bindings, body) = match &l[1..] {
+            [Exp::Atom(Atom::Symbol(name)), Exp::List(bindings), body @ ..] if form == "let" => {
+                (Some(name), bindings, body)
+            }
+            [Exp::List(bindings), body @ ..] => (None, bindings, body),
+            _ => return Err(bad()),
+        };
+        if body.is_empty() {
+            return Err(bad());
+        }
+        let mut vars = vec![];
+        let mut inits = vec![];
+        let mut seen = vec![];
+        for binding in bindings.iter() {
+            match binding {
+                Exp::List(b) => match &b[..] {
+                    [var @ Exp::Atom(Atom::Symbol(id)), init] => {
+                        seen.push((id, b.span.as_ref()));
+                        vars.push(var.clone());
+                        inits.push(init.clone());
+                    }
+                    _ => return Err(bad()),
+                },
+                _ => return Err(bad()),
+            }
+        }
+        // let* binds one variable at a time, so it may bind one again
+        if form != "let*" {
+            check_unique(form, seen)?;
+        }
+        match (form, name) {
+            ("let", Some(name)) => {
+                let proc = self.lambda(vars, body, span);
+                let letrec = list(
+                    vec![
+                        self.top_level("letrec"),
+                        list(vec![list(vec![symbol(name), proc], span)], span),
+                        symbol(name),
+                    ],
+                    span,
+                );
+                let mut call = vec![letrec];
+                call.extend(inits);
+                Ok(list(call, span))
+            }
+            ("let", None) => {
+                let mut call = vec![self.lambda(vars, body, span)];
+                call.extend(inits);
+                Ok(list(call, span))
+            }
+            ("let*", _) if bindings.len() > 1 => {
+                let mut inner = vec![self.top_level("let*"), list(bindings[1..].to_vec(), span)];
+                inner.extend(body.iter().cloned());
+                let outer = vec![
+                    self.top_level("let"),
+                    list(vec![bindings[0].clone()], span),
+                    list(inner, span),
+                ];
+                Ok(list(outer, span))
+            }
+            ("let*", _) => {
+                let mut call = vec![self.lambda(vars, body, span)];
+                call.extend(inits);
+                Ok(list(call, span))
+            }
+            // letrec, letrec*
+            _ => {
+                let mut defines: Vec<Exp> = vars
+                    .into_iter()
+                    .zip(inits)
+                    .map(|(var, init)| list(vec![self.top_level("define"), var, init], span))
+                    .collect();
+                defines.extend(body.iter().cloned());
+                Ok(list(vec![self.lambda(vec![], &defines, span)], span))
+            }
+        }
+    }
+
+    /// Whether `exp` is the symbol `name`, not bound to anything else.
+    fn is_keyword(&self, exp: &Exp, name: &str) -> bool {
+        match exp {
+            Exp::Atom(Atom::Symbol(s)) => {
+                matches!(self.resolve(s), Binding::Variable(n) if n == name)
+            }
+            _ => false,
+        }
+    }
+
+    /// A variable that no code but the expander's can refer to.
+    fn temporary(&self) -> Exp {
+        symbol(&new_alias("t", self.scopes.len()))
+    }
+
+    /// `(keyword items ...)`
+    fn form(&self, keyword: &str, items: &[Exp], span: &Option<Span>) -> Exp {
+        let mut form = vec![self.top_level(keyword)];
+        form.extend(items.iter().cloned());
+        list(form, span)
+    }
+
+    /// `(let ((var init)) body)`
+    fn let1(&self, var: &Exp, init: &Exp, body: Exp, span: &Option<Span>) -> Exp {
+        let binding = list(vec![list(vec![var.clone(), init.clone()], span)], span);
+        self.form("let", &[binding, body], span)
+    }
+
+    /// Rewrites the conditionals in terms of `if`:
+    ///
+    /// - `(when test body ...)` is `(if test (begin body ...))`
+    /// - `(unless test body ...)` is `(if test #f (begin body ...))`
+    /// - `(and e rest ...)` is `(if e (and rest ...) #f)`
+    /// - `(or e rest ...)` is `(let ((t e)) (if t t (or rest ...)))`
+    /// - `(cond (test body ...) clause ...)` is
+    ///   `(if test (begin body ...) (cond clause ...))`
+    /// - `(case key ((datum ...) body ...) ...)` is
+    ///   `(let ((t key)) (cond ((or (equal? t 'datum) ...) body ...) ...))`
+    fn rewrite_conditional(&self, form: &str, l: &List) -> Result<Exp, Exceptions> {
+        let span = &l.span;
+        let rest = &l[1..];
+        let false_ = Exp::Atom(Atom::Bool(false));
+        match form {
+            "when" | "unless" => {
+                let (test, body) = match rest.split_first() {
+                    Some((test, body)) if !body.is_empty() => (test, body),
+                    _ => {
+                        return Err(Exceptions::SyntaxError(format!(
+                            "{} expects a test followed by a body",
+                            form
+                        )))
+                    }
+                };
+                let body = self.form("begin", body, span);
+                if form == "when" {
+                    Ok(self.form("if", &[test.clone(), body], span))
+                } else {
+                    Ok(self.form("if", &[test.clone(), false_, body], span))
+                }
+            }
+            "and" => match rest {
+                [] => Ok(Exp::Atom(Atom::Bool(true))),
+                [e] => Ok(e.clone()),
+                [e, more @ ..] => {
+                    let more = self.form("and", more, span);
+                    Ok(self.form("if", &[e.clone(), more, false_], span))
+                }
+            },
+            "or" => match rest {
+                [] => Ok(false_),
+                [e] => Ok(e.clone()),
+                [e, more @ ..] => {
+                    let t = self.temporary();
+                    let more = self.form("or", more, span);
+                    let test = self.form("if", &[t.clone(), t.clone(), more], span);
+                    Ok(self.let1(&t, e, test, span))
+                }
+            },
+            "cond" => self.rewrite_cond(rest, span),
+            // case
+            _ => {
+                let (key, clauses) = rest
+                    .split_first()
+                    .ok_or_else(|| syntax_error("case expects a key followed by clauses"))?;
+                let t = self.temporary();
+                let mut cond_clauses = vec![];
+                for clause in clauses {
+                    let clause = match clause {
+                        Exp::List(c) if c.len() >= 2 => c,
+                        _ => {
+                            return Err(syntax_error("case clauses must be ((datum ...) body ...)"))
+                        }
+                    };
+                    let test = match &clause[0] {
+                        else_ if self.is_keyword(else_, "else") => else_.clone(),
+                        Exp::List(data) => {
+                            let tests: Vec<Exp> = data
+                                .iter()
+                                .map(|d| {
+                                    let datum = self.form("quote", std::slice::from_ref(d), span);
+                                    // the builtin, whatever `equal?` is bound to here
+                                    let equal = Exp::Func(builtin_functions::equal);
+                                    list(vec![equal, t.clone(), datum], span)
+                                })
+                                .collect();
+                            self.form("or", &tests, span)
+                        }
+                        _ => {
+                            return Err(syntax_error("case clauses must be ((datum ...) body ...)"))
+                        }
+                    };
+                    let body = match &clause[1..] {
+                        [arrow, receiver] if self.is_keyword(arrow, "=>") => {
+                            vec![list(vec![receiver.clone(), t.clone()], span)]
+                        }
+                        body => body.to_vec(),
+                    };
+                    let mut cond_clause = vec![test];
+                    cond_clause.extend(body);
+                    cond_clauses.push(list(cond_clause, &clause.span));
+                }
+                let cond = self.form("cond", &cond_clauses, span);
+                Ok(self.let1(&t, key, cond, span))
+            }
+        }
+    }
+
+    fn rewrite_cond(&self, clauses: &[Exp], span: &Option<Span>) -> Result<Exp, Exceptions> {
+        let (clause, more) = match clauses.split_first() {
+            Some((Exp::List(clause), more)) if !clause.is_empty() => (clause, more),
+            Some(_) => return Err(syntax_error("cond clauses must be non empty lists")),
+            None => return Ok(Exp::Atom(Atom::Bool(false))),
+        };
+        let (test, body) = (&clause[0], &clause[1..]);
+        if self.is_keyword(test, "else") {
+            if !more.is_empty() || body.is_empty() {
+                return Err(syntax_error(
+                    "else must be the last cond clause and have a body",
+                ));
+            }
+            return Ok(self.form("begin", body, &clause.span));
+        }
+        let otherwise = self.form("cond", more, span);
+        match body {
+            [] => Ok(self.form("or", &[test.clone(), otherwise], &clause.span)),
+            [arrow, receiver] if self.is_keyword(arrow, "=>") => {
+                let t = self.temporary();
+                let call = list(vec![receiver.clone(), t.clone()], &clause.span);
+                let branch = self.form("if", &[t.clone(), call, otherwise], &clause.span);
+                Ok(self.let1(&t, test, branch, &clause.span))
+            }
+            _ => {
+                let body = self.form("begin", body, &clause.span);
+                Ok(self.form("if", &[test.clone(), body, otherwise], &clause.span))
+            }
+        }
+    }
+
+    /// Translates the template of a quasiquote into calls to `list` and
+    /// `append`. `depth` is the number of quasiquotes the template is in.
+    /// The calls are to the builtins themselves, so they work even where
+    /// those names are bound to something else.
+    fn quasiquote(&mut self, template: &Exp, depth: usize) -> Result<Exp, Exceptions> {
+        let quote = |datum: Exp| list(vec![symbol("quote"), datum], &None);
+        let l = match template {
+            Exp::List(l) => l,
+            _ => return Ok(quote(strip(template))),
+        };
+        match self.special_form(template) {
+            Some(("unquote", e)) if depth == 1 => return self.expand(e),
+            Some((form @ ("unquote" | "unquote-splicing"), e)) => {
+                let e = self.quasiquote(e, depth - 1)?;
+                return Ok(list(
+                    vec![Exp::Func(builtin_functions::list), quote(symbol(form)), e],
+                    &l.span,
+                ));
+            }
+            Some(("quasiquote", e)) => {
+                let e = self.quasiquote(e, depth + 1)?;
+                return Ok(list(
+                    vec![
+                        Exp::Func(builtin_functions::list),
+                        quote(symbol("quasiquote")),
+                        e,
+                    ],
+                    &l.span,
+                ));
+            }
+            _ => {}
+        }
+        // runs of items to be listed, separated by spliced lists
+        let mut segments = vec![];
+        let mut items = vec![Exp::Func(builtin_functions::list)];
+        for item in l.iter() {
+            match self.special_form(item) {
+                Some(("unquote-splicing", e)) if depth == 1 => {
+                    if items.len() > 1 {
+                        segments.push(list(items, &l.span));
+                        items = vec![Exp::Func(builtin_functions::list)];
+                    }
+                    segments.push(self.expand(e)?);
+                }
+                _ => items.push(self.quasiquote(item, depth)?),
+            }
+        }
+        if items.len() > 1 || segments.is_empty() {
+            segments.push(list(items, &l.span));
+        }
+        if segments.len() > 1 {
+            segments.insert(0, Exp::Func(builtin_functions::append));
+            return Ok(list(segments, &l.span));
+        }
+        let listed = segments.pop().unwrap();
+        // nothing was unquoted, so the whole list can be quoted
+        let quoted: Option<Vec<Exp>> = match &listed {
+            Exp::List(items) => items[1..].iter().map(quoted_datum).collect(),
+            _ => None,
+        };
+        match quoted {
+            Some(data) => Ok(quote(list(data, &l.span))),
+            None => Ok(listed),
+        }
+    }
+
+    /// The keyword and operand of `exp` if it is a `(keyword operand)`
+    /// use of a quasiquote related form.
+    fn special_form<'e>(&self, exp: &'e Exp) -> Option<(&'static str, &'e Exp)> {
+        match exp {
+            Exp::List(l) => match &l[..] {
+                [Exp::Atom(Atom::Symbol(head)), operand] => match self.resolve(head) {
+                    Binding::Core(form @ ("quasiquote" | "unquote" | "unquote-splicing")) => {
+                        Some((form, operand))
+                    }
+                    _ => None,
+                },
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    fn expand_core(&mut self, form: &'static str, l: &List) -> Result<Exp, Exceptions> {
+        let rest = &l[1..];
+        let keyword = symbol(form);
+        match form {
+            "quote" => match rest {
+                [datum] => Ok(list(vec![keyword, strip(datum)], &l.span)),
+                _ => Err(syntax_error("quote expects exactly one datum")),
+            },
+            "set!" => match rest {
+                [Exp::Atom(Atom::Symbol(id)), value] => {
+                    let name = match self.resolve(id) {
+                        Binding::Variable(name) => name,
+                        _ => return Err(syntax_error("set! expects a variable")),
+                    };
+                    let value = self.expand(value)?;
+                    Ok(list(vec![keyword, symbol(&name), value], &l.span))
+                }
+                _ => Err(syntax_error(
+                    "set! expects a symbol followed by an expression",
+                )),
+            },
+            "lambda" => match rest {
+                [Exp::Atom(Atom::Symbol(id)), body @ ..] if !body.is_empty() => {
+                    // (lambda args body ...) is (lambda (. args) body ...)
+                    self.push_scope(true);
+                    let result = self.expand_procedure(&[symbol("."), symbol(id)], body);
+                    self.scopes.pop();
+                    let (names, body) = result?;
+                    let mut items = vec![keyword, list(names, &None)];
+                    items.extend(body);
+                    Ok(list(items, &l.span))
+                }
+                [Exp::List(params), body @ ..] if !body.is_empty() => {
+                    check_unique("lambda", params_with_spans(params, 0))?;
+                    self.push_scope(true);
+                    let result = self.expand_procedure(params, body);
+                    self.scopes.pop();
+                    let (names, body) = result?;
+                    let mut items = vec![keyword, list(names, &params.span)];
+                    items.extend(body);
+                    Ok(list(items, &l.span))
+                }
+                _ => Err(syntax_error("lambda expects a params list and body")),
+            },
+            "define" => match rest {
+                [Exp::Atom(Atom::Symbol(id)), value @ ..] => {
+                    let name = self.define_target(id);
+                    let mut items = vec![keyword, symbol(&name)];
+                    items.extend(self.expand_all(value)?);
+                    Ok(list(items, &l.span))
+                }
+                [Exp::List(sig), body @ ..] if !body.is_empty() => {
+                    let (id, params) = match sig.split_first() {
+                        Some((Exp::Atom(Atom::Symbol(id)), params)) => (id, params),
+                        _ => return Err(syntax_error("Expected a function name in define")),
+                    };
+                    check_unique("define", params_with_spans(sig, 1))?;
+                    let name = self.define_target(id);
+                    self.push_scope(true);
+                    let result = self.expand_procedure(params, body);
+                    self.scopes.pop();
+                    let (params, body) = result?;
+                    let mut names = vec![symbol(&name)];
+                    names.extend(params);
+                    let mut items = vec![keyword, list(names, &sig.span)];
+                    items.extend(body);
+                    Ok(list(items, &l.span))
+                }
+                _ => Err(syntax_error("Not a valid define expression")),
+            },
+            "guard" => {
+                let (var, clauses, body) = match rest {
+                    [Exp::List(spec), body @ ..] => match spec.split_first() {
+                        Some((Exp::Atom(Atom::Symbol(var)), clauses)) => (var, clauses, body),
+                        _ => {
+                            return Err(syntax_error(
+                                "guard expects (guard (var clause ...) body ...)",
+                            ))
+                        }
+                    },
+                    _ => {
+                        return Err(syntax_error(
+                            "guard expects (guard (var clause ...) body ...)",
+                        ))
+                    }
+                };
+                let body = self.expand_all(body)?;
+                self.push_scope(false);
+                let index = self.scopes.len() - 1;
+                let var = self.bind(index, var);
+                let clauses: Result<Vec<Exp>, Exceptions> = clauses
+                    .iter()
+                    .map(|clause| match clause {
+                        Exp::List(c) => Ok(list(self.expand_all(c)?, &c.span)),
+                        _ => Err(syntax_error("guard clauses must be lists")),
+                    })
+                    .collect();
+                self.scopes.pop();
+                let mut spec = vec![symbol(&var)];
+                spec.extend(clauses?);
+                let mut items = vec![keyword, list(spec, &l.span)];
+                items.extend(body);
+                Ok(list(items, &l.span))
+            }
+            "define-syntax" => match rest {
+                [Exp::Atom(Atom::Symbol(id)), spec] => {
+                    let m = self.parse_transformer(spec)?;
+                    self.bind_macro(id, m);
+                    Ok(list(vec![symbol("quote"), symbol(&unalias(id))], &l.span))
+                }
+                _ => Err(syntax_error(
+                    "define-syntax expects a name and a syntax-rules form",
+                )),
+            },
+            "let-syntax" | "letrec-syntax" => {
+                let (specs, body) = match rest {
+                    [Exp::List(specs), body @ ..] if !body.is_empty() => (specs, body),
+                    _ => {
+                        return Err(Exceptions::SyntaxError(format!(
+                            "{} expects a list of macro bindings and a body",
+                            form
+                        )))
+                    }
+                };
+                let depth = self.scopes.len() + usize::from(form == "letrec-syntax");
+                let mut bindings = HashMap::new();
+                for spec in specs.iter() {
+                    match spec {
+                        Exp::List(spec) => match &spec[..] {
+                            [Exp::Atom(Atom::Symbol(id)), transformer] => {
+                                let m = Rc::new(self.parse_transformer(transformer)?);
+                                bindings.insert(id.clone(), Binding::Macro(m, depth));
+                            }
+                            _ => {
+                                return Err(syntax_error(
+                                    "macro bindings must be (name transformer)",
+                                ))
+                            }
+                        },
+                        _ => return Err(syntax_error("macro bindings must be (name transformer)")),
+                    }
+                }
+                self.scopes.push(Scope {
+                    bindings,
+                    lambda: false,
+                });
+                let result = self.expand_body(body);
+                self.scopes.pop();
+                let mut items = vec![symbol("begin")];
+                items.extend(result?);
+                Ok(list(items, &l.span))
+            }
+            "syntax-rules" => Err(syntax_error(
+                "syntax-rules can only be used to define a macro",
+            )),
+            "define-macro" => {
+                let (id, transformer) = match rest {
+                    [Exp::List(sig), body @ ..] if !body.is_empty() => match sig.split_first() {
+                        Some((Exp::Atom(Atom::Symbol(id)), params)) => {
+                            let mut transformer =
+                                vec![symbol("lambda"), list(params.to_vec(), &sig.span)];
+                            transformer.extend(body.iter().cloned());
+                            (id, list(transformer, &l.span))
+                        }
+                        _ => return Err(syntax_error("Expected a macro name in define-macro")),
+                    },
+                    [Exp::Atom(Atom::Symbol(id)), transformer] => (id, transformer.clone()),
+                    _ => {
+                        return Err(syntax_error(
+                            "define-macro expects (define-macro (name params ...) body ...)",
+                        ))
+                    }
+                };
+                let m = self.macro_procedure(&unalias(id), &transformer)?;
+                self.bind_macro(id, m);
+                Ok(list(vec![symbol("quote"), symbol(&unalias(id))], &l.span))
+            }
+            "let" | "let*" | "letrec" | "letrec*" => {
+                let rewritten = self.rewrite_let(form, l)?;
+                self.expand(&rewritten)
+            }
+            "cond" | "case" | "when" | "unless" | "and" | "or" => {
+                let rewritten = self.rewrite_conditional(form, l)?;
+                self.expand(&rewritten)
+            }
+            "quasiquote" => match rest {
+                [template] => self.quasiquote(template, 1),
+                _ => Err(syntax_error("quasiquote expects exactly one template")),
+            },
+            "unquote" | "unquote-splicing" => Err(Exceptions::SyntaxError(format!(
+                "{} used outside of a quasiquote",
+                form
+            ))),
+            // if, begin
+            _ => {
+                let mut items = vec![keyword];
+                items.extend(self.expand_all(rest)?);
+                Ok(list(items, &l.span))
+            }
+        }
+    }
+
+    /// Binds `params` in the innermost scope and expands `body`, returning
+    /// the renamed parameters and the expanded body.
+    fn expand_procedure(
+        &mut self,
+        params: &[Exp],
+        body: &[Exp],
+    ) -> Result<(Vec<Exp>, Vec<Exp>), Exceptions> {
+        let index = self.scopes.len() - 1;
+        let mut names = vec![];
+        for param in params.iter() {
+            match param {
+                Exp::Atom(Atom::Symbol(dot)) if dot == "." => names.push(param.clone()),
+                Exp::Atom(Atom::Symbol(id)) => names.push(symbol(&self.bind(index, id))),
+                _ => {
+                    return Err(syntax_error(
+                        "non symbol passed in a lambda parameters list",
+                    ))
+                }
+            }
+        }
+        Ok((names, self.expand_body(body)?))
+    }
+
+    /// Parses `(syntax-rules [ellipsis] (literal ...) (pattern template) ...)`.
+    fn parse_transformer(&self, spec: &Exp) -> Result<Macro, Exceptions> {
+        let bad = || syntax_error("expected (syntax-rules (literal ...) (pattern template) ...)");
+        let spec = match spec {
+            Exp::List(spec) => spec,
+            _ => return Err(bad()),
+        };
+        match spec.first() {
+            Some(Exp::Atom(Atom::Symbol(head))) => match self.resolve(head) {
+                Binding::Core("syntax-rules") => {}
+                _ => return Err(bad()),
+            },
+            _ => return Err(bad()),
+        }
+        let (ellipsis, rest) = match &spec[1..] {
+            [Exp::Atom(Atom::Symbol(e)), rest @ ..] => (unalias(e), rest),
+            rest => ("...".to_string(), rest),
+        };
+        let (literals, rules) = match rest.split_first() {
+            Some((Exp::List(literals), rules)) => (literals, rules),
+            _ => return Err(bad()),
+        };
+        let literals = literals
+            .iter()
+            .map(|l| match l {
+                Exp::Atom(Atom::Symbol(l)) => Ok(l.clone()),
+                _ => Err(bad()),
+            })
+            .collect::<Result<Vec<Symbol>, Exceptions>>()?;
+        let rules = rules
+            .iter()
+            .map(|rule| match rule {
+                Exp::List(rule) => match &rule[..] {
+                    [pattern @ Exp::List(_), template] => Ok((pattern.clone(), template.clone())),
+                    _ => Err(bad()),
+                },
+                _ => Err(bad()),
+            })
+            .collect::<Result<Vec<(Exp, Exp)>, Exceptions>>()?;
+        let mut aliases = HashMap::new();
+        for literal in &literals {
+            collect_aliases(&symbol(literal), &mut aliases);
+        }
+        for (pattern, template) in &rules {
+            collect_aliases(pattern, &mut aliases);
+            collect_aliases(template, &mut aliases);
+        }
+        let rules = SyntaxRules {
+            ellipsis,
+            literals,
+            rules,
+            aliases,
+        };
+        for (pattern, _) in &rules.rules {
+            rules.check_dots(pattern)?;
+            let vars = match pattern {
+                // the keyword position isn't matched
+                Exp::List(pattern) => pattern[1..].iter().flat_map(|p| rules.pattern_vars(p)),
+                _ => continue,
+            };
+            let mut seen = vec![];
+            for var in vars {
+                if seen.contains(&var) {
+                    return Err(Exceptions::SyntaxError(format!(
+                        "duplicate pattern variable {} in syntax-rules pattern",
+                        unalias(&var)
+                    )));
+                }
+                seen.push(var);
+            }
+        }
+        for (pattern, template) in &rules.rules {
+            let vars = rules.pattern_vars(pattern);
+            let mut names = vec![];
+            rules.free_names(template, &vars, &mut names);
+            TEMPLATE_NAMES.with(|t| t.borrow_mut().extend(names));
+        }
+        Ok(Macro::SyntaxRules(rules))
+    }
+
+    /// Rewrites the macro use `form` with the first rule whose pattern
+    /// matches it.
+    fn transcribe_macro(
+        &mut self,
+        rules: &SyntaxRules,
+        depth: usize,
+        form: &List,
+    ) -> Result<Exp, Exceptions> {
+        if !rules.aliases.is_empty() {
+            ALIASES.with(|a| a.borrow_mut().extend(rules.aliases.clone()));
+        }
+        for (pattern, template) in &rules.rules {
+            let pattern = match pattern {
+                Exp::List(pattern) => pattern,
+                _ => continue,
+            };
+            let mut matches = Matches::new();
+            if self.match_list(rules, depth, &pattern[1..], &form[1..], &mut matches)? {
+                let mut renames = HashMap::new();
+                let transcriber = Transcriber {
+                    rules,
+                    depth,
+                    span: &form.span,
+                };
+                return transcriber.transcribe(template, &matches, &mut renames, false);
+            }
+        }
+        Err(Exceptions::SyntaxError(format!(
+            "no syntax-rules pattern matches {}",
+            strip(&list(form.to_vec(), &None))
+        )))
+    }
+
+    fn match_pattern(
+        &self,
+        rules: &SyntaxRules,
+        depth: usize,
+        pattern: &Exp,
+        form: &Exp,
+        matches: &mut Matches,
+    ) -> Result<bool, Exceptions> {
+        Ok(match pattern {
+            Exp::Atom(Atom::Symbol(p)) if rules.literals.contains(p) => match form {
+                Exp::Atom(Atom::Symbol(f)) => self.resolve(f).same(&self.resolve_in(p, depth)),
+                _ => false,
+            },
+            Exp::Atom(Atom::Symbol(p)) if unalias(p) == "_" => true,
+            Exp::Atom(Atom::Symbol(p)) => {
+                matches.insert(p.clone(), MatchTree::One(form.clone()));
+                true
+            }
+            Exp::List(ps) => match form {
+                Exp::List(items) => self.match_list(rules, depth, ps, items, matches)?,
+                _ => false,
+            },
+            _ => pattern == form,
+        })
+    }
+
+    fn match_list(
+        &self,
+        rules: &SyntaxRules,
+        depth: usize,
+        patterns: &[Exp],
+        items: &[Exp],
+        matches: &mut Matches,
+    ) -> Result<bool, Exceptions> {
+        // `(p ... . rest)` matches the items left after `p ...` with `rest`.
+        // Those are all of them when `p ...` ends in an ellipsis.
+        if let Some(dot) = patterns.iter().position(is_dot) {
+            let (head, tail) = (&patterns[..dot], &patterns[dot + 1]);
+            let taken = if head.iter().any(|p| rules.is_ellipsis(p)) {
+                items.len()
+            } else {
+                head.len()
+            };
+            if items.len() < taken {
+                return Ok(false);
+            }
+            let rest = list(items[taken..].to_vec(), &None);
+            return Ok(
+                self.match_list(rules, depth, head, &items[..taken], matches)?
+                    && self.match_pattern(rules, depth, tail, &rest, matches)?,
+            );
+        }
+        let ellipsis = match patterns.iter().position(|p| rules.is_ellipsis(p)) {
+            Some(i) if i > 0 => i,
+            _ => {
+                if patterns.len() != items.len() {
+                    return Ok(false);
+                }
+                for (p, x) in patterns.iter().zip(items) {
+                    if !self.match_pattern(rules, depth, p, x, matches)? {
+                        return Ok(false);
+                    }
+                }
+                return Ok(true);
+            }
+        };
+        let (before, repeated, after) = (
+            &patterns[..ellipsis - 1],
+            &patterns[ellipsis - 1],
+            &patterns[ellipsis + 1..],
+        );
+        if items.len() < before.len() + after.len() {
+            return Ok(false);
+        }
+        let end = items.len() - after.len();
+        if !self.match_list(rules, depth, before, &items[..before.len()], matches)?
+            || !self.match_list(rules, depth, after, &items[end..], matches)?
+        {
+            return Ok(false);
+        }
+        let mut repetitions = vec![];
+        for item in &items[before.len()..end] {
+            let mut m = Matches::new();
+            if !self.match_pattern(rules, depth, repeated, item, &mut m)? {
+                return Ok(false);
+            }
+            repetitions.push(m);
+        }
+        for var in rules.pattern_vars(repeated) {
+            let trees = repetitions
+                .iter_mut()
+                .map(|m| m.remove(&var))
+                .collect::<Option<Vec<_>>>()
+                .ok_or_else(|| {
+                    Exceptions::SyntaxError(format!(
+                        "pattern variable {} is matched more than once",
+                        unalias(&var)
+                    ))
+                })?;
+            matches.insert(var, MatchTree::Many(trees));
+        }
+        Ok(true)
+    }
+}
+
+/// Whether `exp` is the `.` of a dotted list in a pattern, template or
+/// parameter list.
+fn is_dot(exp: &Exp) -> bool {
+    matches!(exp, Exp::Atom(Atom::Symbol(s)) if unalias(s) == ".")
+}
+
+impl SyntaxRules {
+    /// Checks that a `.` in `pattern` only comes right before the last
+    /// subpattern of a list, which isn't an ellipsis.
+    fn check_dots(&self, pattern: &Exp) -> Result<(), Exceptions> {
+        if let Exp::List(l) = pattern {
+            for (i, p) in l.iter().enumerate() {
+                if is_dot(p) && (i == 0 || i + 2 != l.len() || self.is_ellipsis(&l[i + 1])) {
+                    return Err(Exceptions::SyntaxError(format!(
+                        "bad dotted pattern {}",
+                        strip(pattern)
+                    )));
+                }
+                self.check_dots(p)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn is_ellipsis(&self, exp: &Exp) -> bool {
+        match exp {
+            Exp::Atom(Atom::Symbol(s)) => unalias(s) == self.ellipsis && !self.literals.contains(s),
+            _ => false,
+        }
+    }
+
+    fn pattern_vars(&self, pattern: &Exp) -> Vec<Symbol> {
+        match pattern {
+            Exp::Atom(Atom::Symbol(s))
+                if !self.literals.contains(s)
+                    && !self.is_ellipsis(pattern)
+                    && !is_dot(pattern)
+                    && unalias(s) != "_" =>
+            {
+                vec![s.clone()]
+            }
+            Exp::List(l) => l.iter().flat_map(|p| self.pattern_vars(p)).collect(),
+            _ => vec![],
+        }
+    }
+
+    /// Adds the symbols in `template` other than the pattern variables
+    /// `vars` to `names`, as written before any renaming.
+    fn free_names(&self, template: &Exp, vars: &[Symbol], names: &mut Vec<Symbol>) {
+        match template {
+            Exp::Atom(Atom::Symbol(s)) if !vars.contains(s) && !self.is_ellipsis(template) => {
+                names.push(unalias(s))
+            }
+            Exp::List(l) => l.iter().for_each(|t| self.free_names(t, vars, names)),
+            _ => {}
+        }
+    }
+}
+
+/// Fills in a template of a macro for one use of the macro.
+struct Transcriber<'a> {
+    rules: &'a SyntaxRules,
+    depth: usize,
+    /// The span of the macro use, given to every list the template builds.
+    span: &'a Option<Span>,
+}
+
+impl<'a> Transcriber<'a> {
+    fn transcribe(
+        &self,
+        template: &Exp,
+        matches: &Matches,
+        renames: &mut HashMap<Symbol, Symbol>,
+        escaped: bool,
+    ) -> Result<Exp, Exceptions> {
+        match template {
+            Exp::Atom(Atom::Symbol(_)) if is_dot(template) => Ok(symbol(".")),
+            Exp::Atom(Atom::Symbol(s)) => match matches.get(s) {
+                Some(MatchTree::One(exp)) => Ok(exp.clone()),
+                Some(MatchTree::Many(_)) => Err(Exceptions::SyntaxError(format!(
+                    "pattern variable {} used without an ellipsis",
+                    unalias(s)
+                ))),
+                None => {
+                    let alias = renames
+                        .entry(s.clone())
+                        .or_insert_with(|| new_alias(s, self.depth));
+                    Ok(symbol(alias))
+                }
+            },
+            Exp::List(l) => {
+                if let [e, inner] = &l[..] {
+                    if !escaped && self.rules.is_ellipsis(e) {
+                        return self.transcribe(inner, matches, renames, true);
+                    }
+                }
+                let (l, tail) = match &l[..] {
+                    [init @ .., dot, tail] if is_dot(dot) => (init, Some(tail)),
+                    l => (l, None),
+                };
+                let mut items = vec![];
+                let mut i = 0;
+                while i < l.len() {
+                    let mut ellipses = 0;
+                    while !escaped
+                        && i + ellipses + 1 < l.len()
+                        && self.rules.is_ellipsis(&l[i + ellipses + 1])
+                    {
+                        ellipses += 1;
+                    }
+                    if ellipses == 0 {
+                        items.push(self.transcribe(&l[i], matches, renames, escaped)?);
+                    } else {
+                        self.repeat(&l[i], matches, renames, ellipses, &mut items)?;
+                    }
+                    i += ellipses + 1;
+                }
+                // `(a . rest)` with `rest` a list is the list `(a rest ...)`
+                if let Some(tail) = tail {
+                    match self.transcribe(tail, matches, renames, escaped)? {
+                        Exp::List(rest) => items.extend(rest.iter().cloned()),
+                        tail => items.extend([symbol("."), tail]),
+                    }
+                }
+                Ok(list(items, self.span))
+            }
+            _ => Ok(template.clone()),
+        }
+    }
+
+    /// Transcribes `template` once for every match of the pattern variables
+    /// in it that were followed by an ellipsis, `ellipses` levels deep.
+    fn repeat(
+        &self,
+        template: &Exp,
+        matches: &Matches,
+        renames: &mut HashMap<Symbol, Symbol>,
+        ellipses: usize,
+        out: &mut Vec<Exp>,
+    ) -> Result<(), Exceptions> {
+        let vars: Vec<(&Symbol, &Vec<MatchTree>)> = self
+            .rules
+            .pattern_vars(template)
+            .into_iter()
+            .filter_map(|v| match matches.get_key_value(&v) {
+                Some((k, MatchTree::Many(trees))) => Some((k, trees)),
+                _ => None,
+            })
+            .collect();
+        let n = match vars.first() {
+            Some((_, trees)) => trees.len(),
+            None => {
+                return Err(syntax_error(
+                    "no pattern variable to repeat before ellipsis",
+                ))
+            }
+        };
+        if vars.iter().any(|(_, trees)| trees.len() != n) {
+            return Err(syntax_error(
+                "pattern variables under the same ellipsis matched different lengths",
+            ));
+        }
+        for j in 0..n {
+            let mut inner = matches.clone();
+            for (var, trees) in &vars {
+                inner.insert(var.to_string(), trees[j].clone());
+            }
+            if ellipses == 1 {
+                out.push(self.transcribe(template, &inner, renames, false)?);
+            } else {
+                self.repeat(template, &inner, renames, ellipses - 1, out)?;
+            }
+        }
+        Ok(())
+    }
+}
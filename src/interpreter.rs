@@ -0,0 +1,87 @@
+use std::fs;
+use std::path::Path;
+
+use super::default_env::default_env;
+use super::eval::{apply, eval};
+use super::expander::expand;
+use super::parser::read_all;
+use super::types::*;
+
+/// A risp interpreter for embedding in Rust programs. It owns a global
+/// environment holding the builtins, which persists across calls.
+pub struct Interpreter {
+    env: Environment,
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Interpreter {
+    pub fn new() -> Self {
+        Interpreter { env: default_env() }
+    }
+
+    /// The global environment, e.g. to register builtins with
+    /// `Environment::define_native`.
+    pub fn env(&mut self) -> &mut Environment {
+        &mut self.env
+    }
+
+    /// Expands and evaluates every expression in `program` in order,
+    /// returning the value of the last one, or `#f` if there are none.
+    pub fn eval_str(&mut self, program: &str) -> Result<Exp, Exceptions> {
+        self.eval_source(program, "<string>")
+    }
+
+    /// Like `eval_str` for the contents of the file at `path`. Spans in
+    /// errors refer to `path`.
+    pub fn eval_file(&mut self, path: impl AsRef<Path>) -> Result<Exp, Exceptions> {
+        let path = path.as_ref();
+        let program = fs::read_to_string(path).map_err(|e| {
+            Exceptions::ValueError(format!("cannot read {}: {}", path.display(), e))
+        })?;
+        self.eval_source(&program, &path.display().to_string())
+    }
+
+    pub(crate) fn eval_source(&mut self, program: &str, file: &str) -> Result<Exp, Exceptions> {
+        let mut value = Exp::Atom(Atom::Bool(false));
+        for (exp, span) in read_all(program, file)? {
+            value = expand(&exp, &self.env)
+                .and_then(|exp| eval(&exp, &mut self.env))
+                .map_err(|e| e.at(Some(&span)))?;
+        }
+        Ok(value)
+    }
+
+    /// Calls the global procedure `name` with `args` and converts the
+    /// result, e.g. `let n: i64 = interp.call("add", (1, 2))?;`.
+    pub fn call<R: FromExp>(&mut self, name: &str, args: impl IntoArgs) -> Result<R, Exceptions> {
+        let f = self
+            .env
+            .get(name)
+            .ok_or_else(|| Exceptions::UnboundVariable(name.to_string()))?;
+        R::from_exp(&apply(&f, args.into_args(), &mut self.env)?)
+    }
+
+    /// Defines the constructor, predicate and field accessors of the record
+    /// type `T`, see `RispRecord::define_procedures`.
+    pub fn define_record<T: RispRecord>(&mut self) {
+        T::define_procedures(&self.env);
+    }
+
+    pub fn get_global<T: FromExp>(&self, name: &str) -> Result<T, Exceptions> {
+        let value = self
+            .env
+            .get(name)
+            .ok_or_else(|| Exceptions::UnboundVariable(name.to_string()))?;
+        T::from_exp(&value)
+    }
+
+    /// Defines or redefines the global `name`.
+    pub fn set_global(&mut self, name: &str, value: impl IntoExp) {
+        self.env.insert(name.to_string(), value.into_exp());
+    }
+}
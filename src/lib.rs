@@ -1,13 +1,28 @@
 pub mod builtin_functions;
 pub mod default_env;
 pub mod eval;
+pub mod expander;
+pub mod interpreter;
+pub mod number;
 pub mod parser;
 pub mod runner;
 pub mod types;
 
+pub use interpreter::Interpreter;
+#[cfg(feature = "derive")]
+pub use risp_derive::RispValue;
+
 #[cfg(test)]
 mod tests {
-    use crate::{default_env::default_env, eval::parse_and_eval};
+    use crate::{
+        default_env::default_env,
+        eval::{eval, parse_and_eval},
+        expander::alias_count,
+        parser::parse_all,
+        types::{Arity, Atom, Exceptions, Exp, FromExp, IntoExp, Number, Record},
+        Interpreter,
+    };
+    use std::{cell::Cell, rc::Rc};
 
     #[test]
     fn iile() {
@@ -71,6 +86,1926 @@ mod tests {
         assert_eq!(format!("{}", fib_10), "55");
     }
 
+    #[test]
+    fn closures() {
+        let mut env = default_env();
+        let adder = "(define (make-adder n) (lambda (x) (+ x n)))".to_string();
+        parse_and_eval(adder, &mut env).unwrap();
+        parse_and_eval("(define add5 (make-adder 5))".to_string(), &mut env).unwrap();
+        parse_and_eval("(define add10 (make-adder 10))".to_string(), &mut env).unwrap();
+        let ans = parse_and_eval("(add5 1)".to_string(), &mut env).unwrap();
+        assert_eq!(format!("{}", ans), "6");
+        let ans = parse_and_eval("(add10 1)".to_string(), &mut env).unwrap();
+        assert_eq!(format!("{}", ans), "11");
+    }
+
+    #[test]
+    fn counter() {
+        let mut env = default_env();
+        let make_counter = "(define (make-counter n)
+                (lambda () (begin (set! n (+ n 1)) n)))"
+            .to_string();
+        parse_and_eval(make_counter, &mut env).unwrap();
+        parse_and_eval("(define c1 (make-counter 0))".to_string(), &mut env).unwrap();
+        parse_and_eval("(define c2 (make-counter 100))".to_string(), &mut env).unwrap();
+        parse_and_eval("(c1)".to_string(), &mut env).unwrap();
+        let ans = parse_and_eval("(c1)".to_string(), &mut env).unwrap();
+        assert_eq!(format!("{}", ans), "2");
+        let ans = parse_and_eval("(c2)".to_string(), &mut env).unwrap();
+        assert_eq!(format!("{}", ans), "101");
+    }
+
+    #[test]
+    fn lexical_scope() {
+        let mut env = default_env();
+        parse_and_eval("(define x 1)".to_string(), &mut env).unwrap();
+        parse_and_eval("(define (get-x) x)".to_string(), &mut env).unwrap();
+        parse_and_eval("(define (shadow x) (get-x))".to_string(), &mut env).unwrap();
+        let ans = parse_and_eval("(shadow 42)".to_string(), &mut env).unwrap();
+        assert_eq!(format!("{}", ans), "1");
+        let compose = "(define (compose f g) (lambda (x) (f (g x))))".to_string();
+        parse_and_eval(compose, &mut env).unwrap();
+        let ans = parse_and_eval(
+            "((compose (lambda (x) (* x 2)) (lambda (x) (+ x 1))) 5)".to_string(),
+            &mut env,
+        )
+        .unwrap();
+        assert_eq!(format!("{}", ans), "12");
+    }
+
+    #[test]
+    fn tail_calls() {
+        let mut env = default_env();
+        let count_down = "(define (count-down n) (if (= n 0) 'done (count-down (- n 1))))";
+        parse_and_eval(count_down.to_string(), &mut env).unwrap();
+        let ans = parse_and_eval("(count-down 1000000)".to_string(), &mut env).unwrap();
+        assert_eq!(format!("{}", ans), "done");
+
+        let sum =
+            "(define (sum n acc) (if (= n 0) acc (begin (set! acc (+ acc n)) (sum (- n 1) acc))))";
+        parse_and_eval(sum.to_string(), &mut env).unwrap();
+        let ans = parse_and_eval("(sum 1000000 0)".to_string(), &mut env).unwrap();
+        assert_eq!(format!("{}", ans), "500000500000");
+    }
+
+    #[test]
+    fn mutual_tail_calls() {
+        let mut env = default_env();
+        let is_even = "(define (is-even? n) (if (= n 0) #t (is-odd? (- n 1))))";
+        let is_odd = "(define (is-odd? n) (if (= n 0) #f (is-even? (- n 1))))";
+        parse_and_eval(is_even.to_string(), &mut env).unwrap();
+        parse_and_eval(is_odd.to_string(), &mut env).unwrap();
+        let ans = parse_and_eval("(is-even? 1000001)".to_string(), &mut env).unwrap();
+        assert_eq!(format!("{}", ans), "#f");
+    }
+
+    #[test]
+    fn begin_form() {
+        let mut env = default_env();
+        let ans = parse_and_eval(
+            "(begin (define x 1) (set! x (+ x 1)) x)".to_string(),
+            &mut env,
+        )
+        .unwrap();
+        assert_eq!(format!("{}", ans), "2");
+        assert!(parse_and_eval("(begin)".to_string(), &mut env).is_err());
+    }
+
+    #[test]
+    fn error_spans() {
+        let program = "(define (f x)\n  (+ x 1))\n(define (g x)\n   (car x))\n(g 5)";
+        let mut env = default_env();
+        let mut result = Ok(());
+        for exp in parse_all(program, "test.scm").unwrap() {
+            if let Err(e) = eval(&exp, &mut env) {
+                result = Err(e);
+                break;
+            }
+        }
+        let err = result.unwrap_err();
+        assert_eq!(format!("{}", err.span().unwrap()), "test.scm:4:4");
+
+        let err = parse_and_eval("(if 1 2 3 4)".to_string(), &mut env).unwrap_err();
+        assert_eq!(format!("{}", err.span().unwrap()), "1:1");
+        let err = parse_and_eval("(list 1\n  (undefined-fn 2))".to_string(), &mut env).unwrap_err();
+        assert_eq!(format!("{}", err.span().unwrap()), "2:4");
+
+        // atoms are reported where they are, not at the enclosing list
+        let mut interp = Interpreter::new();
+        for (program, expected) in [
+            ("(define x 1)\nx\n  undefined-var", "<string>:3:3"),
+            ("(define (f x)\n  (+ x\n     y))\n(f 1)", "<string>:3:6"),
+            ("(list 1 2\n  (car\n    z) 3)", "<string>:3:5"),
+        ] {
+            let err = interp.eval_str(program).unwrap_err();
+            assert_eq!(format!("{}", err.span().unwrap()), expected, "{}", program);
+        }
+    }
+
+    #[test]
+    fn syntax_error_spans() {
+        let err = parse_all("(+ 1 2)\n(define (f x)\n  (+ x 1)", "test.scm").unwrap_err();
+        assert_eq!(format!("{}", err.span().unwrap()), "test.scm:2:1");
+        let err = parse_all("(+ 1 2))", "test.scm").unwrap_err();
+        assert_eq!(format!("{}", err.span().unwrap()), "test.scm:1:8");
+        assert!(matches!(err.root(), Exceptions::SyntaxError(_)));
+    }
+
+    #[test]
+    fn error_kinds() {
+        let mut env = default_env();
+        let err = parse_and_eval("(+ 1 undefined-var)".to_string(), &mut env).unwrap_err();
+        assert!(matches!(err.root(), Exceptions::UnboundVariable(x) if x == "undefined-var"));
+        let err = parse_and_eval("(car 1 2)".to_string(), &mut env).unwrap_err();
+        assert!(matches!(
+            err.root(),
+            Exceptions::ArityMismatch { got: 2, .. }
+        ));
+        let err = parse_and_eval("((lambda (x) x))".to_string(), &mut env).unwrap_err();
+        assert_eq!(
+            format!("{}", err.root()),
+            "<lambda>: expected 1 arguments, got 0"
+        );
+        let err = parse_and_eval("(length 5)".to_string(), &mut env).unwrap_err();
+        assert_eq!(
+            format!("{}", err.root()),
+            "type error: expected a list, got number 5"
+        );
+        let err = parse_and_eval("(mod 5 0)".to_string(), &mut env).unwrap_err();
+        assert!(matches!(err.root(), Exceptions::DivisionByZero));
+        let err = parse_and_eval("(5 1)".to_string(), &mut env).unwrap_err();
+        assert!(matches!(err.root(), Exceptions::TypeError { .. }));
+    }
+
+    #[test]
+    fn stack_trace() {
+        let program = "(define (f x) (car x))
+                       (define (g x) (+ 1 (f x)))
+                       (define (h x) (g x))
+                       (define (k x) (+ 1 (h x)))
+                       (k 5)";
+        let mut env = default_env();
+        let mut result = Ok(());
+        for exp in parse_all(program, "test.scm").unwrap() {
+            if let Err(e) = eval(&exp, &mut env) {
+                result = Err(e);
+                break;
+            }
+        }
+        let err = result.unwrap_err();
+        // h tail calls g, so it is not on the stack any more
+        assert_eq!(err.stack(), ["f", "g", "k"]);
+        assert_eq!(
+            format!("{}", err),
+            "test.scm:1:15: type error: expected a list, got number 5\n\
+             stack trace (most recent call first):\n    f\n    g\n    k"
+        );
+    }
+
+    #[test]
+    fn guard() {
+        let mut env = default_env();
+        let ans = parse_and_eval(
+            "(guard (e (#t (error-object-message e))) (error \"something bad\" 1 2))".to_string(),
+            &mut env,
+        )
+        .unwrap();
+        assert_eq!(format!("{}", ans), "something bad");
+        let ans = parse_and_eval(
+            "(guard (e ((error-object? e) (error-object-irritants e))) (error \"bad\" 1 2))"
+                .to_string(),
+            &mut env,
+        )
+        .unwrap();
+        assert_eq!(format!("{}", ans), "(1 2)");
+
+        // raised objects are passed as is
+        let ans = parse_and_eval(
+            "(guard (e ((equal? e 42) 'forty-two) (else 'other)) (+ 1 (raise 42)))".to_string(),
+            &mut env,
+        )
+        .unwrap();
+        assert_eq!(format!("{}", ans), "forty-two");
+        let ans = parse_and_eval(
+            "(guard (e ((equal? e 42) 'forty-two) (else 'other)) (raise 'oops))".to_string(),
+            &mut env,
+        )
+        .unwrap();
+        assert_eq!(format!("{}", ans), "other");
+        let ans = parse_and_eval(
+            "(guard (e ((car e) => list)) (raise '(a b)))".to_string(),
+            &mut env,
+        )
+        .unwrap();
+        assert_eq!(format!("{}", ans), "(a)");
+
+        // no clause matches, so the exception is raised again
+        let err = parse_and_eval(
+            "(guard (e ((error-object? e) 'error)) (raise 'oops))".to_string(),
+            &mut env,
+        )
+        .unwrap_err();
+        assert_eq!(format!("{}", err.root()), "uncaught exception: oops");
+
+        // the guard catches raise-continuable before any outer handler
+        let ans = parse_and_eval(
+            "(with-exception-handler
+                (lambda (e) 42)
+                (lambda () (guard (e (#t 'guarded)) (raise-continuable 'x))))"
+                .to_string(),
+            &mut env,
+        )
+        .unwrap();
+        assert_eq!(format!("{}", ans), "guarded");
+        let ans = parse_and_eval(
+            "(guard (e (#t 'guarded))
+                (with-exception-handler
+                    (lambda (e) (+ e 1))
+                    (lambda () (raise-continuable 41))))"
+                .to_string(),
+            &mut env,
+        )
+        .unwrap();
+        assert_eq!(format!("{}", ans), "42");
+        // when no clause matches, the outer handler's value goes back to
+        // the raise-continuable
+        let ans = parse_and_eval(
+            "(with-exception-handler
+                (lambda (e) 10)
+                (lambda () (guard (e2 ((string? e2) 's)) (+ 1 (raise-continuable 'oops)))))"
+                .to_string(),
+            &mut env,
+        )
+        .unwrap();
+        assert_eq!(format!("{}", ans), "11");
+        // the clauses are tested once
+        let ans = parse_and_eval(
+            "(let ((tests 0))
+               (list
+                 (guard (e ((begin (set! tests (+ tests 1)) (symbol? e)) e))
+                   (raise-continuable 'x))
+                 tests))"
+                .to_string(),
+            &mut env,
+        )
+        .unwrap();
+        assert_eq!(format!("{}", ans), "(x 1)");
+        let err = parse_and_eval(
+            "(guard (e ((string? e) 's)) (raise-continuable 'oops))".to_string(),
+            &mut env,
+        )
+        .unwrap_err();
+        assert_eq!(format!("{}", err.root()), "uncaught exception: oops");
+    }
+
+    #[test]
+    fn builtin_errors_as_conditions() {
+        let mut env = default_env();
+        let ans = parse_and_eval(
+            "(guard (e (#t (error-object-irritants e))) (car 5))".to_string(),
+            &mut env,
+        )
+        .unwrap();
+        assert_eq!(format!("{}", ans), "(5)");
+        let ans = parse_and_eval(
+            "(guard (e (#t (error-object-message e))) (mod 1 0))".to_string(),
+            &mut env,
+        )
+        .unwrap();
+        assert_eq!(format!("{}", ans), "division by zero");
+
+        // errors in the clauses are reported where they happen
+        let err = parse_and_eval(
+            "(guard (e ((car e) 'unreachable)) (begin 1\n  (car 5)))".to_string(),
+            &mut env,
+        )
+        .unwrap_err();
+        assert_eq!(format!("{}", err.span().unwrap()), "1:12");
+        // while re-raising keeps the span of the original error
+        let err = parse_and_eval(
+            "(guard (e (#f 'unreachable)) (begin 1\n  (car 5)))".to_string(),
+            &mut env,
+        )
+        .unwrap_err();
+        assert_eq!(format!("{}", err.span().unwrap()), "2:3");
+        let err = parse_and_eval(
+            "(guard (e ((raise e) 'unreachable)) (begin 1\n  (car 5)))".to_string(),
+            &mut env,
+        )
+        .unwrap_err();
+        assert_eq!(format!("{}", err.span().unwrap()), "2:3");
+    }
+
+    #[test]
+    fn with_exception_handler() {
+        let mut env = default_env();
+        let ans = parse_and_eval(
+            "(with-exception-handler
+                (lambda (e) (+ e 1))
+                (lambda () (* 2 (raise-continuable 20))))"
+                .to_string(),
+            &mut env,
+        )
+        .unwrap();
+        assert_eq!(format!("{}", ans), "42");
+
+        // the handler runs, but can't resume a non continuable raise
+        parse_and_eval("(define seen 0)".to_string(), &mut env).unwrap();
+        let err = parse_and_eval(
+            "(with-exception-handler
+                (lambda (e) (set! seen e))
+                (lambda () (raise 7)))"
+                .to_string(),
+            &mut env,
+        )
+        .unwrap_err();
+        assert!(matches!(err.root(), Exceptions::ValueError(_)));
+        let ans = parse_and_eval("seen".to_string(), &mut env).unwrap();
+        assert_eq!(format!("{}", ans), "7");
+
+        // without a handler raise-continuable is just raise
+        let err = parse_and_eval("(raise-continuable 1)".to_string(), &mut env).unwrap_err();
+        assert!(matches!(err.root(), Exceptions::Raised(_)));
+    }
+
+    #[test]
+    fn syntax_rules() {
+        let mut env = default_env();
+        parse_and_eval(
+            "(define-syntax swap!
+                (syntax-rules ()
+                    ((_ a b) ((lambda (tmp) (begin (set! a b) (set! b tmp))) a))))"
+                .to_string(),
+            &mut env,
+        )
+        .unwrap();
+        parse_and_eval("(define tmp 1)".to_string(), &mut env).unwrap();
+        parse_and_eval("(define y 2)".to_string(), &mut env).unwrap();
+        parse_and_eval("(swap! tmp y)".to_string(), &mut env).unwrap();
+        let ans = parse_and_eval("(list tmp y)".to_string(), &mut env).unwrap();
+        assert_eq!(format!("{}", ans), "(2 1)");
+
+        // literals only match the same binding
+        parse_and_eval(
+            "(define-syntax arrow
+                (syntax-rules (=>)
+                    ((_ a => b) (list a b))
+                    ((_ a b) 'no-arrow)))"
+                .to_string(),
+            &mut env,
+        )
+        .unwrap();
+        let ans = parse_and_eval("(arrow 1 => 2)".to_string(), &mut env).unwrap();
+        assert_eq!(format!("{}", ans), "(1 2)");
+        let ans = parse_and_eval("(arrow 1 2)".to_string(), &mut env).unwrap();
+        assert_eq!(format!("{}", ans), "no-arrow");
+        let ans = parse_and_eval("((lambda (=>) (arrow 1 => 2)) 0)".to_string(), &mut env);
+        assert!(ans.is_err());
+
+        let err = parse_and_eval("(swap! 1)".to_string(), &mut env).unwrap_err();
+        assert!(matches!(err.root(), Exceptions::SyntaxError(_)));
+        let err = parse_and_eval("(list swap!)".to_string(), &mut env).unwrap_err();
+        assert!(matches!(err.root(), Exceptions::SyntaxError(_)));
+
+        for program in [
+            "(define-syntax d (syntax-rules () ((_ (a a) ...) 'x)))",
+            "(define-syntax d (syntax-rules () ((_ a (b a)) 'x)))",
+        ] {
+            let err = parse_and_eval(program.to_string(), &mut env).unwrap_err();
+            assert_eq!(
+                format!("{}", err.root()),
+                "syntax error: duplicate pattern variable a in syntax-rules pattern"
+            );
+        }
+
+        parse_and_eval(
+            "(begin
+                (define-syntax rest-of (syntax-rules () ((_ a . rest) (list a 'rest))))
+                (define-syntax all-of (syntax-rules () ((_ . args) (list . args))))
+                (define-syntax last-of (syntax-rules () ((_ a ... . r) '(a ... r)))))"
+                .to_string(),
+            &mut env,
+        )
+        .unwrap();
+        for (program, expected) in [
+            ("(rest-of 1 2 3)", "(1 (2 3))"),
+            ("(rest-of 1)", "(1 ())"),
+            ("(all-of)", "()"),
+            ("(all-of 1 2)", "(1 2)"),
+            ("(last-of 1 2)", "(1 2 ())"),
+        ] {
+            let ans = parse_and_eval(program.to_string(), &mut env).unwrap();
+            assert_eq!(format!("{}", ans), expected, "{}", program);
+        }
+        for program in [
+            "(define-syntax d (syntax-rules () ((_ . a b) 'x)))",
+            "(define-syntax d (syntax-rules () ((_ a . ...) 'x)))",
+        ] {
+            let err = parse_and_eval(program.to_string(), &mut env).unwrap_err();
+            assert!(
+                matches!(err.root(), Exceptions::SyntaxError(_)),
+                "{}",
+                program
+            );
+        }
+    }
+
+    #[test]
+    fn macro_hygiene() {
+        let mut env = default_env();
+        parse_and_eval(
+            "(define-syntax my-or
+                (syntax-rules ()
+                    ((_) #f)
+                    ((_ e) e)
+                    ((_ e r ...) ((lambda (t) (if t t (my-or r ...))) e))))"
+                .to_string(),
+            &mut env,
+        )
+        .unwrap();
+        // the t bound by the template doesn't capture the caller's t
+        parse_and_eval("(define t #t)".to_string(), &mut env).unwrap();
+        let ans = parse_and_eval("(my-or #f t)".to_string(), &mut env).unwrap();
+        assert_eq!(format!("{}", ans), "#t");
+        // and a local if doesn't break the if in the template
+        let ans = parse_and_eval(
+            "((lambda (if) (my-or #f (if 1))) (lambda (x) #t))".to_string(),
+            &mut env,
+        )
+        .unwrap();
+        assert_eq!(format!("{}", ans), "#t");
+
+        parse_and_eval(
+            "(define-syntax while
+                (syntax-rules ()
+                    ((_ c body ...)
+                     ((lambda ()
+                        (begin
+                          (define (loop) (if c (begin body ... (loop)) #f))
+                          (loop)))))))"
+                .to_string(),
+            &mut env,
+        )
+        .unwrap();
+        parse_and_eval("(define i 0)".to_string(), &mut env).unwrap();
+        parse_and_eval("(define loop 10)".to_string(), &mut env).unwrap();
+        parse_and_eval("(define total 0)".to_string(), &mut env).unwrap();
+        parse_and_eval(
+            "(while (< i 5) (set! total (+ total loop)) (set! i (+ i 1)))".to_string(),
+            &mut env,
+        )
+        .unwrap();
+        let ans = parse_and_eval("total".to_string(), &mut env).unwrap();
+        assert_eq!(format!("{}", ans), "50");
+
+        // a template refers to the global even when it is defined after
+        // the local that has the same name
+        for (program, expected) in [
+            (
+                "(define-syntax my-assert (syntax-rules () ((_ e) (check e))))
+                 (define (test check) (my-assert check))
+                 (define (check x) (list 'checked x))
+                 (test 7)",
+                "(checked 7)",
+            ),
+            (
+                "(define-syntax getx (syntax-rules () ((_) x)))
+                 (define (f x) (getx))
+                 (define x 5)
+                 (f 1)",
+                "5",
+            ),
+        ] {
+            let ans = Interpreter::new().eval_str(program).unwrap();
+            assert_eq!(format!("{}", ans), expected, "{}", program);
+        }
+    }
+
+    #[test]
+    fn macro_ellipsis() {
+        let mut env = default_env();
+        parse_and_eval(
+            "(define-syntax pairs
+                (syntax-rules () ((_ (a b) ...) (list (list b a) ...))))"
+                .to_string(),
+            &mut env,
+        )
+        .unwrap();
+        let ans = parse_and_eval("(pairs (1 2) (3 4))".to_string(), &mut env).unwrap();
+        assert_eq!(format!("{}", ans), "((2 1) (4 3))");
+        let ans = parse_and_eval("(pairs)".to_string(), &mut env).unwrap();
+        assert_eq!(format!("{}", ans), "()");
+
+        parse_and_eval(
+            "(define-syntax flatten
+                (syntax-rules () ((_ (a ...) ...) '(a ... ...))))"
+                .to_string(),
+            &mut env,
+        )
+        .unwrap();
+        let ans = parse_and_eval("(flatten (1 2) () (3))".to_string(), &mut env).unwrap();
+        assert_eq!(format!("{}", ans), "(1 2 3)");
+
+        parse_and_eval(
+            "(define-syntax last
+                (syntax-rules ::: () ((_ a ::: z) 'z)))"
+                .to_string(),
+            &mut env,
+        )
+        .unwrap();
+        let ans = parse_and_eval("(last 1 2 3)".to_string(), &mut env).unwrap();
+        assert_eq!(format!("{}", ans), "3");
+
+        // a macro defining macros, escaping the inner ellipsis
+        parse_and_eval(
+            "(define-syntax def-lister
+                (syntax-rules ()
+                    ((_ name)
+                     (define-syntax name
+                        (syntax-rules () ((_ x (... ...)) (list x (... ...))))))))"
+                .to_string(),
+            &mut env,
+        )
+        .unwrap();
+        parse_and_eval("(def-lister my-list)".to_string(), &mut env).unwrap();
+        let ans = parse_and_eval("(my-list 1 2 3)".to_string(), &mut env).unwrap();
+        assert_eq!(format!("{}", ans), "(1 2 3)");
+
+        // aliases don't outlive the expansion that made them
+        for _ in 0..3 {
+            let ans = parse_and_eval(
+                "(let ((x 1)) (cond (#f 1) (else (my-list x))))".to_string(),
+                &mut env,
+            )
+            .unwrap();
+            assert_eq!(format!("{}", ans), "(1)");
+            assert_eq!(alias_count(), 0);
+        }
+    }
+
+    #[test]
+    fn let_syntax() {
+        let mut env = default_env();
+        parse_and_eval(
+            "(define (f x)
+                (let-syntax ((double (syntax-rules () ((_ e) (* 2 e)))))
+                    (double x)))"
+                .to_string(),
+            &mut env,
+        )
+        .unwrap();
+        let ans = parse_and_eval("(f 21)".to_string(), &mut env).unwrap();
+        assert_eq!(format!("{}", ans), "42");
+        assert!(parse_and_eval("(double 1)".to_string(), &mut env).is_err());
+
+        // x in the template is the x of g, not the one around the use
+        parse_and_eval(
+            "(define (g x)
+                (let-syntax ((get-x (syntax-rules () ((_) x))))
+                    ((lambda (x) (get-x)) 5)))"
+                .to_string(),
+            &mut env,
+        )
+        .unwrap();
+        let ans = parse_and_eval("(g 1)".to_string(), &mut env).unwrap();
+        assert_eq!(format!("{}", ans), "1");
+
+        let ans = parse_and_eval(
+            "(letrec-syntax ((count
+                                (syntax-rules ()
+                                    ((_) 0)
+                                    ((_ x y ...) (+ 1 (count y ...))))))
+                (count a b c))"
+                .to_string(),
+            &mut env,
+        )
+        .unwrap();
+        assert_eq!(format!("{}", ans), "3");
+    }
+
+    #[test]
+    fn quasiquote() {
+        let mut env = default_env();
+        parse_and_eval("(define x 5)".to_string(), &mut env).unwrap();
+        parse_and_eval("(define lst '(1 2))".to_string(), &mut env).unwrap();
+        let ans = parse_and_eval("`(a ,x ,@lst b)".to_string(), &mut env).unwrap();
+        assert_eq!(format!("{}", ans), "(a 5 1 2 b)");
+        let ans = parse_and_eval("`(1 ,(+ 1 1) ,@'())".to_string(), &mut env).unwrap();
+        assert_eq!(format!("{}", ans), "(1 2)");
+        let ans = parse_and_eval("`(+ 1 2)".to_string(), &mut env).unwrap();
+        assert_eq!(format!("{}", ans), "(+ 1 2)");
+        // only the innermost quasiquote's unquotes are evaluated
+        let ans = parse_and_eval("`(a `(b ,(c ,x)))".to_string(), &mut env).unwrap();
+        assert_eq!(format!("{}", ans), "(a (quasiquote (b (unquote (c 5)))))");
+        let ans = parse_and_eval("'(a ,b ,@c)".to_string(), &mut env).unwrap();
+        assert_eq!(format!("{}", ans), "(a (unquote b) (unquote-splicing c))");
+        let err = parse_and_eval("(list ,x)".to_string(), &mut env).unwrap_err();
+        assert!(matches!(err.root(), Exceptions::SyntaxError(_)));
+
+        // rebinding list and append doesn't change what quasiquote builds
+        parse_and_eval("(define list 3)".to_string(), &mut env).unwrap();
+        parse_and_eval("(define (append . x) 'mine)".to_string(), &mut env).unwrap();
+        let ans = parse_and_eval("`(1 ,(+ 1 1) ,@lst)".to_string(), &mut env).unwrap();
+        assert_eq!(format!("{}", ans), "(1 2 1 2)");
+        let ans = parse_and_eval("((lambda (list) `(a ,list)) 4)".to_string(), &mut env).unwrap();
+        assert_eq!(format!("{}", ans), "(a 4)");
+    }
+
+    #[test]
+    fn define_macro() {
+        let mut env = default_env();
+        parse_and_eval(
+            "(define-macro (my-unless c . body) `(if ,c #f (begin ,@body)))".to_string(),
+            &mut env,
+        )
+        .unwrap();
+        let ans = parse_and_eval("(my-unless #f 1 2)".to_string(), &mut env).unwrap();
+        assert_eq!(format!("{}", ans), "2");
+        let ans = parse_and_eval("(my-unless #t 1 2)".to_string(), &mut env).unwrap();
+        assert_eq!(format!("{}", ans), "#f");
+
+        // not hygienic: the body sees the it bound by the expansion
+        parse_and_eval(
+            "(define-macro (with-it e body) `((lambda (it) ,body) ,e))".to_string(),
+            &mut env,
+        )
+        .unwrap();
+        let ans = parse_and_eval("(with-it 5 (+ it 1))".to_string(), &mut env).unwrap();
+        assert_eq!(format!("{}", ans), "6");
+
+        parse_and_eval(
+            "(define-macro (my-when c . body) `(my-unless (not ,c) ,@body))".to_string(),
+            &mut env,
+        )
+        .unwrap();
+        let ans = parse_and_eval("(macroexpand-1 '(my-when x 1))".to_string(), &mut env).unwrap();
+        assert_eq!(format!("{}", ans), "(my-unless (not x) 1)");
+        let ans = parse_and_eval("(macroexpand '(my-when x 1))".to_string(), &mut env).unwrap();
+        assert_eq!(format!("{}", ans), "(if (not x) #f (begin 1))");
+        let ans = parse_and_eval("(macroexpand '(+ 1 2))".to_string(), &mut env).unwrap();
+        assert_eq!(format!("{}", ans), "(+ 1 2)");
+
+        parse_and_eval(
+            "(define-syntax swap!
+                (syntax-rules ()
+                    ((_ a b) ((lambda (tmp) (begin (set! a b) (set! b tmp))) a))))"
+                .to_string(),
+            &mut env,
+        )
+        .unwrap();
+        let ans = parse_and_eval("(macroexpand '(swap! p q))".to_string(), &mut env).unwrap();
+        assert_eq!(
+            format!("{}", ans),
+            "((lambda (tmp) (begin (set! p q) (set! q tmp))) p)"
+        );
+    }
+
+    #[test]
+    fn variadic_procedures() {
+        let mut env = default_env();
+        let ans = parse_and_eval("((lambda (a . rest) rest) 1 2 3)".to_string(), &mut env).unwrap();
+        assert_eq!(format!("{}", ans), "(2 3)");
+        let ans = parse_and_eval("((lambda args args))".to_string(), &mut env).unwrap();
+        assert_eq!(format!("{}", ans), "()");
+        parse_and_eval("(define (f a b . c) c)".to_string(), &mut env).unwrap();
+        let err = parse_and_eval("(f 1)".to_string(), &mut env).unwrap_err();
+        assert_eq!(
+            format!("{}", err.root()),
+            "f: expected at least 2 arguments, got 1"
+        );
+    }
+
+    #[test]
+    fn let_forms() {
+        let mut env = default_env();
+        parse_and_eval("(define x 10)".to_string(), &mut env).unwrap();
+        let ans = parse_and_eval("(let ((x 1) (y x)) (+ x y))".to_string(), &mut env).unwrap();
+        assert_eq!(format!("{}", ans), "11");
+        let ans = parse_and_eval("(let* ((x 1) (y x)) (+ x y))".to_string(), &mut env).unwrap();
+        assert_eq!(format!("{}", ans), "2");
+        let ans = parse_and_eval("(let () (define z 3) (* z z))".to_string(), &mut env).unwrap();
+        assert_eq!(format!("{}", ans), "9");
+        let ans = parse_and_eval(
+            "(letrec ((ev? (lambda (n) (if (= n 0) #t (od? (- n 1)))))
+                      (od? (lambda (n) (if (= n 0) #f (ev? (- n 1))))))
+                (ev? 100))"
+                .to_string(),
+            &mut env,
+        )
+        .unwrap();
+        assert_eq!(format!("{}", ans), "#t");
+        let ans = parse_and_eval(
+            "(letrec* ((a 1) (b (+ a 1))) (list a b))".to_string(),
+            &mut env,
+        )
+        .unwrap();
+        assert_eq!(format!("{}", ans), "(1 2)");
+        // shadowing the keywords the forms are rewritten into
+        let ans = parse_and_eval(
+            "(let ((lambda 1) (define 2)) (let ((y 3)) (+ lambda define y)))".to_string(),
+            &mut env,
+        )
+        .unwrap();
+        assert_eq!(format!("{}", ans), "6");
+        let err = parse_and_eval("(let ((x)) x)".to_string(), &mut env).unwrap_err();
+        assert!(matches!(err.root(), Exceptions::SyntaxError(_)));
+
+        // a variable can only be bound once, except by let*
+        let ans = parse_and_eval("(let* ((x 1) (x (+ x 1))) x)".to_string(), &mut env).unwrap();
+        assert_eq!(format!("{}", ans), "2");
+        for (program, span, message) in [
+            (
+                "(let ((x 1)\n      (x 2)) x)",
+                "2:7",
+                "duplicate variable x in let",
+            ),
+            (
+                "(let loop ((i 0) (i 1)) i)",
+                "1:18",
+                "duplicate variable i in let",
+            ),
+            (
+                "(letrec ((f 1) (f 2)) f)",
+                "1:16",
+                "duplicate variable f in letrec",
+            ),
+            (
+                "(lambda (a b a) a)",
+                "1:14",
+                "duplicate variable a in lambda",
+            ),
+            (
+                "(lambda (a . a) a)",
+                "1:14",
+                "duplicate variable a in lambda",
+            ),
+            (
+                "(define (f a a) a)",
+                "1:14",
+                "duplicate variable a in define",
+            ),
+        ] {
+            let err = parse_and_eval(program.to_string(), &mut env).unwrap_err();
+            assert_eq!(format!("{}", err.span().unwrap()), span, "{}", program);
+            assert_eq!(
+                format!("{}", err.root()),
+                format!("syntax error: {}", message)
+            );
+        }
+    }
+
+    #[test]
+    fn named_let() {
+        let mut env = default_env();
+        let ans = parse_and_eval(
+            "(let loop ((i 0) (acc 0))
+                (if (= i 1000000) acc (loop (+ i 1) (+ acc i))))"
+                .to_string(),
+            &mut env,
+        )
+        .unwrap();
+        assert_eq!(format!("{}", ans), "499999500000");
+        // the initial values are evaluated outside the loop's scope
+        parse_and_eval("(define (f n) (* n 2))".to_string(), &mut env).unwrap();
+        let ans = parse_and_eval(
+            "(let f ((n (f 3))) (if (= n 0) 'done (f (- n 1))))".to_string(),
+            &mut env,
+        )
+        .unwrap();
+        assert_eq!(format!("{}", ans), "done");
+    }
+
+    #[test]
+    fn cond_and_case() {
+        let mut env = default_env();
+        parse_and_eval(
+            "(define (classify n) (cond ((< n 0) 'negative) ((= n 0) 'zero) (else 'positive)))"
+                .to_string(),
+            &mut env,
+        )
+        .unwrap();
+        let ans = parse_and_eval(
+            "(list (classify -5) (classify 0) (classify 5))".to_string(),
+            &mut env,
+        )
+        .unwrap();
+        assert_eq!(format!("{}", ans), "(negative zero positive)");
+        let ans = parse_and_eval(
+            "(cond ((car '(7)) => (lambda (x) (* x 2))) (else 0))".to_string(),
+            &mut env,
+        )
+        .unwrap();
+        assert_eq!(format!("{}", ans), "14");
+        let ans = parse_and_eval("(cond (#f) (3))".to_string(), &mut env).unwrap();
+        assert_eq!(format!("{}", ans), "3");
+        let ans = parse_and_eval("(cond (#f 1))".to_string(), &mut env).unwrap();
+        assert_eq!(format!("{}", ans), "#f");
+        let err = parse_and_eval("(cond (else 1) (#t 2))".to_string(), &mut env).unwrap_err();
+        assert!(matches!(err.root(), Exceptions::SyntaxError(_)));
+
+        parse_and_eval(
+            "(define (kind x) (case x ((1 2 3) 'small) ((a b) 'letter) (else 'other)))".to_string(),
+            &mut env,
+        )
+        .unwrap();
+        let ans =
+            parse_and_eval("(list (kind 2) (kind 'b) (kind 10))".to_string(), &mut env).unwrap();
+        assert_eq!(format!("{}", ans), "(small letter other)");
+        let ans = parse_and_eval(
+            "(case 5 ((5) => (lambda (x) (+ x 1))) (else 0))".to_string(),
+            &mut env,
+        )
+        .unwrap();
+        assert_eq!(format!("{}", ans), "6");
+        // redefining equal? doesn't change how case compares
+        let ans = Interpreter::new()
+            .eval_str("(define (equal? a b) #f) (case 2 ((1) 'one) ((2) 'two) (else 'other))")
+            .unwrap();
+        assert_eq!(format!("{}", ans), "two");
+
+        // the clauses are in tail position
+        parse_and_eval(
+            "(define (count n) (cond ((= n 0) 'done) (else (count (- n 1)))))".to_string(),
+            &mut env,
+        )
+        .unwrap();
+        let ans = parse_and_eval("(count 100000)".to_string(), &mut env).unwrap();
+        assert_eq!(format!("{}", ans), "done");
+    }
+
+    #[test]
+    fn when_unless_and_or() {
+        let mut env = default_env();
+        let ans = parse_and_eval("(when (> 1 0) 'a 'b)".to_string(), &mut env).unwrap();
+        assert_eq!(format!("{}", ans), "b");
+        let ans = parse_and_eval("(when #f 'a)".to_string(), &mut env).unwrap();
+        assert_eq!(format!("{}", ans), "#f");
+        let ans = parse_and_eval("(unless #f 1 2)".to_string(), &mut env).unwrap();
+        assert_eq!(format!("{}", ans), "2");
+
+        let ans = parse_and_eval("(and 1 2 3)".to_string(), &mut env).unwrap();
+        assert_eq!(format!("{}", ans), "3");
+        let ans = parse_and_eval("(list (and) (or))".to_string(), &mut env).unwrap();
+        assert_eq!(format!("{}", ans), "(#t #f)");
+        // both short circuit, car is never called
+        let ans = parse_and_eval("(and 1 #f (car 5))".to_string(), &mut env).unwrap();
+        assert_eq!(format!("{}", ans), "#f");
+        let ans = parse_and_eval("(or #f 2 (car 5))".to_string(), &mut env).unwrap();
+        assert_eq!(format!("{}", ans), "2");
+        let ans = parse_and_eval("(let ((t 5)) (or #f t))".to_string(), &mut env).unwrap();
+        assert_eq!(format!("{}", ans), "5");
+    }
+
+    #[test]
+    fn truthiness() {
+        let mut env = default_env();
+        let ans = parse_and_eval(
+            "(list (if '() 'yes 'no) (if 0 'yes 'no) (if #f 'yes 'no) (if #f 1))".to_string(),
+            &mut env,
+        )
+        .unwrap();
+        assert_eq!(format!("{}", ans), "(yes yes no #f)");
+        let ans = parse_and_eval("(list (not 0) (not #f))".to_string(), &mut env).unwrap();
+        assert_eq!(format!("{}", ans), "(#f #t)");
+    }
+
+    #[test]
+    fn multi_expression_bodies() {
+        let mut env = default_env();
+        parse_and_eval("(define log '())".to_string(), &mut env).unwrap();
+        let ans = parse_and_eval(
+            "((lambda (x) (set! log (cons 1 log)) (set! log (cons 2 log)) (* x 2)) 21)".to_string(),
+            &mut env,
+        )
+        .unwrap();
+        assert_eq!(format!("{}", ans), "42");
+        let ans = parse_and_eval("log".to_string(), &mut env).unwrap();
+        assert_eq!(format!("{}", ans), "(2 1)");
+
+        // internal defines can refer to each other and shadow globals
+        parse_and_eval(
+            "(define (parity n)
+                (define (length n) (if (= n 0) 'even (odd (- n 1))))
+                (define (odd n) (if (= n 0) 'odd (length (- n 1))))
+                (length n))"
+                .to_string(),
+            &mut env,
+        )
+        .unwrap();
+        let ans = parse_and_eval("(list (parity 10) (parity 7))".to_string(), &mut env).unwrap();
+        assert_eq!(format!("{}", ans), "(even odd)");
+        let ans = parse_and_eval("(length '(1 2 3))".to_string(), &mut env).unwrap();
+        assert_eq!(format!("{}", ans), "3");
+
+        // the last expression is in tail position
+        parse_and_eval(
+            "(define (loop n) (define m (- n 1)) (if (= n 0) 'done (loop m)))".to_string(),
+            &mut env,
+        )
+        .unwrap();
+        let ans = parse_and_eval("(loop 1000000)".to_string(), &mut env).unwrap();
+        assert_eq!(format!("{}", ans), "done");
+    }
+
+    #[test]
+    fn higher_order_builtins() {
+        let mut env = default_env();
+        let cases = [
+            ("(map + '(1 2) '(10 20 30))", "(11 22)"),
+            ("(map car '((a) (b)))", "(a b)"),
+            ("(map (lambda (x) (list x)) '(a b))", "((a) (b))"),
+            ("(apply + 1 2 '(3 4))", "10"),
+            ("(apply (lambda (a b) (- a b)) '(10 4))", "6"),
+            ("(filter (lambda (x) (> x 2)) '(1 3 2 4))", "(3 4)"),
+            ("(fold cons '() '(1 2 3))", "(3 2 1)"),
+            ("(fold + 0 '(1 2 3))", "6"),
+            ("(sort '(3 1 2) <)", "(1 2 3)"),
+            (
+                "(sort '((1 a) (0 b) (1 c)) (lambda (x y) (< (car x) (car y))))",
+                "((0 b) (1 a) (1 c))",
+            ),
+            // results of builtins are not evaluated again
+            ("(list '+ 1 2)", "(+ 1 2)"),
+        ];
+        for (program, expected) in cases {
+            let ans = parse_and_eval(program.to_string(), &mut env).unwrap();
+            assert_eq!(format!("{}", ans), expected, "{}", program);
+        }
+
+        parse_and_eval("(define total 0)".to_string(), &mut env).unwrap();
+        parse_and_eval(
+            "(for-each (lambda (x y) (set! total (+ total (* x y)))) '(1 2) '(3 4))".to_string(),
+            &mut env,
+        )
+        .unwrap();
+        let ans = parse_and_eval("total".to_string(), &mut env).unwrap();
+        assert_eq!(format!("{}", ans), "11");
+
+        // errors in the callbacks come out with their stack
+        parse_and_eval("(define (f x) (car x))".to_string(), &mut env).unwrap();
+        let err = parse_and_eval("(map f '(1))".to_string(), &mut env).unwrap_err();
+        assert_eq!(err.stack(), ["f"]);
+        let err = parse_and_eval("(map 1 '(1))".to_string(), &mut env).unwrap_err();
+        assert!(matches!(err.root(), Exceptions::TypeError { .. }));
+    }
+
+    #[test]
+    fn native_closures() {
+        let mut env = default_env();
+        let calls = Rc::new(Cell::new(0));
+        let counter = Rc::clone(&calls);
+        env.define_native("tick!", 0, move |_, _| {
+            counter.set(counter.get() + 1);
+            Ok(Exp::Atom(Atom::Number(Number::Int(counter.get()))))
+        });
+        let prefix = String::from("item-");
+        env.define_native("label", Arity::AtLeast(1), move |args, _| {
+            let names: Vec<String> = args.iter().map(|x| format!("{}{}", prefix, x)).collect();
+            Ok(names.join(" ").into_exp())
+        });
+
+        parse_and_eval("(tick!)".to_string(), &mut env).unwrap();
+        let ans = parse_and_eval("(tick!)".to_string(), &mut env).unwrap();
+        assert_eq!(format!("{}", ans), "2");
+        assert_eq!(calls.get(), 2);
+        let ans = parse_and_eval("(label 1 2)".to_string(), &mut env).unwrap();
+        assert_eq!(format!("{}", ans), "item-1 item-2");
+        let ans = parse_and_eval("(map label '(a b))".to_string(), &mut env).unwrap();
+        assert_eq!(format!("{}", ans), "(item-a item-b)");
+        let ans = parse_and_eval("(procedure? label)".to_string(), &mut env).unwrap();
+        assert_eq!(format!("{}", ans), "#t");
+
+        let err = parse_and_eval("(tick! 1)".to_string(), &mut env).unwrap_err();
+        assert_eq!(
+            format!("{}", err.root()),
+            "tick!: expected 0 arguments, got 1"
+        );
+        let err = parse_and_eval("(label)".to_string(), &mut env).unwrap_err();
+        assert_eq!(
+            format!("{}", err.root()),
+            "label: expected at least 1 arguments, got 0"
+        );
+    }
+
+    #[test]
+    fn interpreter() {
+        let mut interp = Interpreter::new();
+        let ans = interp
+            .eval_str("(define (add a b) (+ a b)) (define greeting \"hi\") (add 1 2)")
+            .unwrap();
+        assert_eq!(format!("{}", ans), "3");
+        let n: i64 = interp.call("add", (40, 2)).unwrap();
+        assert_eq!(n, 42);
+        let x: f64 = interp.call("add", (0.5, 2)).unwrap();
+        assert_eq!(x, 2.5);
+        let s: String = interp.get_global("greeting").unwrap();
+        assert_eq!(s, "hi");
+
+        interp.set_global("xs", vec![3_i64, 1, 2]);
+        let sorted: Vec<i64> = interp
+            .eval_str("(sort xs <)")
+            .and_then(|x| Vec::from_exp(&x))
+            .unwrap();
+        assert_eq!(sorted, vec![1, 2, 3]);
+        let lt: Exp = interp.get_global("<").unwrap();
+        let sorted: Vec<i64> = interp.call("sort", (vec![2, 1], lt)).unwrap();
+        assert_eq!(sorted, vec![1, 2]);
+        let pair: (i64, Option<String>) = interp
+            .eval_str("(list 1 #f)")
+            .and_then(|x| FromExp::from_exp(&x))
+            .unwrap();
+        assert_eq!(pair, (1, None));
+        assert_eq!(
+            format!("{}", (1, "a", true, Some(2.5)).into_exp()),
+            "(1 a #t 2.5)"
+        );
+
+        let err = interp.get_global::<i64>("greeting").unwrap_err();
+        assert_eq!(
+            format!("{}", err),
+            "type error: expected an integer, got string \"hi\""
+        );
+        let err = interp.call::<i64>("missing", ()).unwrap_err();
+        assert_eq!(format!("{}", err), "unbound variable: missing");
+        let err = interp.call::<i64>("add", (1,)).unwrap_err();
+        assert_eq!(
+            format!("{}", err.root()),
+            "add: expected 2 arguments, got 1"
+        );
+
+        let path =
+            std::env::temp_dir().join(format!("risp-interpreter-{}.scm", std::process::id()));
+        std::fs::write(&path, "(define (twice x) (* 2 x))\n(twice greeting)").unwrap();
+        let err = interp.eval_file(&path).unwrap_err();
+        assert_eq!(
+            format!("{}", err.span().unwrap()),
+            format!("{}:1:19", path.display())
+        );
+        assert_eq!(interp.call::<i64>("twice", (21,)).unwrap(), 42);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn numeric_tower() {
+        let mut env = default_env();
+        for (program, expected) in [
+            ("(+ 9007199254740993 0)", "9007199254740993"),
+            ("(- 9007199254740993 1)", "9007199254740992"),
+            ("(exact? (* 2.0 3))", "#f"),
+            ("(exact? (* 2 3))", "#t"),
+            ("(inexact? (+ 1 0.5))", "#t"),
+            ("(/ 6 3)", "2"),
+            ("(exact? (/ 6 3))", "#t"),
+            ("(/ 1 4)", "1/4"),
+            ("(/ 2)", "1/2"),
+            ("(- 5)", "-5"),
+            ("(- 10 1 2 3)", "4"),
+            ("(+)", "0"),
+            ("(*)", "1"),
+            ("(expt 2 62)", "4611686018427387904"),
+            ("(expt 2 -1)", "1/2"),
+            ("(max 1 3 2)", "3"),
+            ("(exact? (max 1 3.0 2))", "#f"),
+            (
+                "(min 9007199254740993 9007199254740992.0)",
+                "9007199254740992.0",
+            ),
+            ("(abs -7)", "7"),
+            ("(+ 9223372036854775807 1)", "9223372036854775808"),
+            ("(- -9223372036854775807 2)", "-9223372036854775809"),
+            ("(abs (- -9223372036854775807 1))", "9223372036854775808"),
+            ("(expt 2 64)", "18446744073709551616"),
+            ("(expt -1 4000000001)", "-1"),
+            ("(expt 1/2 3)", "1/8"),
+            ("(exact? (expt 2 1000000))", "#t"),
+            ("(exact->inexact 1)", "1.0"),
+            ("(exact? (exact->inexact 1))", "#f"),
+            ("(inexact->exact 4.0)", "4"),
+            ("(inexact->exact 1.5)", "3/2"),
+            ("(exact? (inexact->exact 4.0))", "#t"),
+            ("(equal? (exact 2.0) 2)", "#t"),
+        ] {
+            let ans = parse_and_eval(program.to_string(), &mut env).unwrap();
+            assert_eq!(format!("{}", ans), expected, "{}", program);
+        }
+
+        for (program, expected) in [
+            (
+                "(expt 2 4294967296)",
+                "expt: exponent 4294967296 is too large",
+            ),
+            (
+                "(exact +inf.0)",
+                "inexact->exact: +inf.0 has no exact representation",
+            ),
+            (
+                "(inexact->exact -inf.0)",
+                "inexact->exact: -inf.0 has no exact representation",
+            ),
+            (
+                "(exact +nan.0)",
+                "inexact->exact: +nan.0 has no exact representation",
+            ),
+            (
+                "(expt 2 4000000000)",
+                "expt: exponent 4000000000 is too large",
+            ),
+            (
+                "(expt 1/3 -100000000)",
+                "expt: exponent -100000000 is too large",
+            ),
+            ("(/ 1 0)", "division by zero"),
+            ("(exact? 'a)", "type error: expected a number, got symbol a"),
+            (
+                "(+ 1 \"2\")",
+                "type error: expected a number, got string \"2\"",
+            ),
+        ] {
+            let err = parse_and_eval(program.to_string(), &mut env).unwrap_err();
+            assert_eq!(format!("{}", err.root()), expected, "{}", program);
+        }
+    }
+
+    #[test]
+    fn bignums() {
+        let mut env = default_env();
+        parse_and_eval(
+            "(define (fact x) (if (<= x 1) 1 (* x (fact (- x 1)))))".to_string(),
+            &mut env,
+        )
+        .unwrap();
+        for (program, expected) in [
+            ("(fact 25)", "15511210043330985984000000"),
+            ("(/ (fact 25) (fact 23))", "600"),
+            ("(equal? (/ (fact 25) (fact 23)) 600)", "#t"),
+            ("(- (fact 21) (fact 21))", "0"),
+            ("(- 100000000000000000000 99999999999999999999)", "1"),
+            ("(* -1 -9223372036854775808)", "9223372036854775808"),
+            ("(exact? (fact 30))", "#t"),
+            ("(exact? (* 1.0 (fact 30)))", "#f"),
+            ("(< (fact 20) (fact 21))", "#t"),
+            ("(> 100000000000000000000 1e20)", "#f"),
+            ("(= 100000000000000000000 1e20)", "#t"),
+            ("(< 100000000000000000001 1e20)", "#f"),
+            ("(= 9007199254740993 9007199254740992.0)", "#f"),
+            ("(max 1 100000000000000000000)", "100000000000000000000"),
+            ("(inexact->exact 1e20)", "100000000000000000000"),
+            ("(number? -123456789012345678901234567890)", "#t"),
+        ] {
+            let ans = parse_and_eval(program.to_string(), &mut env).unwrap();
+            assert_eq!(format!("{}", ans), expected, "{}", program);
+        }
+        let ans = parse_and_eval("(/ (fact 22) (fact 22))".to_string(), &mut env).unwrap();
+        assert_eq!(ans, Exp::Atom(Atom::Number(Number::Int(1))));
+    }
+
+    #[test]
+    fn rationals() {
+        let mut env = default_env();
+        for (program, expected) in [
+            ("(/ 1 3)", "1/3"),
+            ("(+ 1/3 1/6)", "1/2"),
+            ("(* 3 1/3)", "1"),
+            ("(exact? (* 3 1/3))", "#t"),
+            ("(equal? (* 3 1/3) 1)", "#t"),
+            ("-6/4", "-3/2"),
+            ("+4/2", "2"),
+            (
+                "(let loop ((i 0) (sum 0)) (if (= i 10) sum (loop (+ i 1) (+ sum 1/10))))",
+                "1",
+            ),
+            ("(- 1/2)", "-1/2"),
+            ("(/ 1/2 1/4)", "2"),
+            ("(/ 100000000000000000000 3)", "100000000000000000000/3"),
+            ("(+ 1/2 0.25)", "0.75"),
+            ("(exact->inexact 1/4)", "0.25"),
+            ("(inexact->exact 0.1)", "3602879701896397/36028797018963968"),
+            ("(expt 2/3 3)", "8/27"),
+            ("(expt 2/3 -2)", "9/4"),
+            ("(abs -1/2)", "1/2"),
+            ("(< 1/3 0.3333)", "#f"),
+            ("(> 1/3 0.3333)", "#t"),
+            ("(= 1/2 0.5)", "#t"),
+            ("(max 1/2 1/3)", "1/2"),
+            ("(numerator 6/4)", "3"),
+            ("(denominator 6/4)", "2"),
+            ("(denominator 5)", "1"),
+            ("(numerator -1/3)", "-1"),
+            ("(numerator 0.5)", "1.0"),
+            ("(exact? (denominator 0.5))", "#f"),
+            ("(rationalize 1/3 1/100)", "1/3"),
+            ("(rationalize 3/10 1/10)", "1/3"),
+            ("(rationalize -3/10 1/10)", "-1/3"),
+            ("(rationalize 1/4 1/4)", "0"),
+            ("(rationalize 0.3 1/10)", "0.3333333333333333"),
+            ("(rationalize 22/7 0)", "22/7"),
+            ("(number? 1/3)", "#t"),
+            ("'1/x", "1/x"),
+        ] {
+            let ans = parse_and_eval(program.to_string(), &mut env).unwrap();
+            assert_eq!(format!("{}", ans), expected, "{}", program);
+        }
+        let err = parse_and_eval("(/ 1/2 0)".to_string(), &mut env).unwrap_err();
+        assert_eq!(format!("{}", err.root()), "division by zero");
+    }
+
+    #[test]
+    fn integer_division() {
+        let mut env = default_env();
+        for (program, expected) in [
+            ("(quotient 17 5)", "3"),
+            ("(quotient -17 5)", "-3"),
+            ("(remainder 17 -5)", "2"),
+            ("(remainder -17 5)", "-2"),
+            ("(modulo 17 -5)", "-3"),
+            ("(modulo -17 5)", "3"),
+            ("(modulo 13 4)", "1"),
+            ("(floor/ -7 2)", "(-4 1)"),
+            ("(truncate/ -7 2)", "(-3 -1)"),
+            ("(floor-quotient 7 -2)", "-4"),
+            ("(floor-remainder 7 -2)", "-1"),
+            ("(truncate-quotient 7 -2)", "-3"),
+            ("(truncate-remainder 7 -2)", "1"),
+            ("(quotient -9223372036854775808 -1)", "9223372036854775808"),
+            ("(remainder 100000000000000000007 10)", "7"),
+            ("(modulo -100000000000000000007 10)", "3"),
+            ("(mod -7 2)", "-1"),
+            ("(mod -9223372036854775808 -1)", "0"),
+            ("(mod 100000000000000000007 10)", "7"),
+            ("(mod 7.0 2)", "1.0"),
+            ("(quotient 7.0 2)", "3.0"),
+            ("(exact? (quotient 7.0 2))", "#f"),
+            ("(gcd 32 -36)", "4"),
+            ("(gcd)", "0"),
+            ("(gcd 0 5)", "5"),
+            ("(lcm 32 -36)", "288"),
+            ("(lcm)", "1"),
+            ("(lcm 4 0)", "0"),
+            ("(gcd 100000000000000000000 15)", "5"),
+            ("(exact-integer-sqrt 17)", "(4 1)"),
+            ("(exact-integer-sqrt 0)", "(0 0)"),
+            (
+                "(exact-integer-sqrt 100000000000000000000000000001)",
+                "(316227766016837 590207432515432)",
+            ),
+            ("(even? 0)", "#t"),
+            ("(even? -3)", "#f"),
+            ("(odd? 100000000000000000001)", "#t"),
+            ("(odd? 3.0)", "#t"),
+            ("(zero? 0)", "#t"),
+            ("(zero? 0.0)", "#t"),
+            ("(zero? 1/2)", "#f"),
+            ("(positive? 1/2)", "#t"),
+            ("(positive? -0.5)", "#f"),
+            ("(negative? -100000000000000000000)", "#t"),
+            ("(negative? 0)", "#f"),
+        ] {
+            let ans = parse_and_eval(program.to_string(), &mut env).unwrap();
+            assert_eq!(format!("{}", ans), expected, "{}", program);
+        }
+
+        for (program, expected) in [
+            ("(quotient 1 0)", "division by zero"),
+            ("(modulo 1 0.0)", "division by zero"),
+            (
+                "(quotient 1/2 1)",
+                "type error: expected an integer, got number 1/2",
+            ),
+            (
+                "(gcd 1.5)",
+                "type error: expected an integer, got number 1.5",
+            ),
+            (
+                "(even? 'a)",
+                "type error: expected an integer, got symbol a",
+            ),
+            (
+                "(exact-integer-sqrt -4)",
+                "type error: expected an exact non-negative integer, got number -4",
+            ),
+            (
+                "(exact-integer-sqrt 4.0)",
+                "type error: expected an exact non-negative integer, got number 4.0",
+            ),
+            ("(zero? '())", "type error: expected a number, got list ()"),
+            ("(modulo 1)", "modulo: expected 2 arguments, got 1"),
+            (
+                "(mod 7.9 2)",
+                "type error: expected an integer, got number 7.9",
+            ),
+        ] {
+            let err = parse_and_eval(program.to_string(), &mut env).unwrap_err();
+            assert_eq!(format!("{}", err.root()), expected, "{}", program);
+        }
+    }
+
+    #[test]
+    fn math_library() {
+        let mut env = default_env();
+        for (program, expected) in [
+            ("(sqrt 16)", "4"),
+            ("(exact? (sqrt 16))", "#t"),
+            ("(sqrt 9/4)", "3/2"),
+            ("(sqrt 100000000000000000000)", "10000000000"),
+            ("(sqrt 2)", "1.4142135623730951"),
+            ("(sqrt 16.0)", "4.0"),
+            ("(exact? (sqrt 16.0))", "#f"),
+            ("(nan? (sqrt -4))", "#t"),
+            ("(exp 0)", "1"),
+            ("(exact? (exp 0))", "#t"),
+            ("(exp 1)", "2.718281828459045"),
+            ("(log 1)", "0"),
+            ("(log (exp 2))", "2.0"),
+            ("(log 8 2)", "3.0"),
+            ("(log 1000 10)", "3.0"),
+            ("(log 27 3)", "3.0"),
+            ("(log 0.0)", "-inf.0"),
+            ("(sin 0)", "0"),
+            ("(exact? (sin 0))", "#t"),
+            ("(exact? (sin 0.0))", "#f"),
+            ("(cos 0)", "1"),
+            ("(tan 0)", "0"),
+            ("(asin 1)", "1.5707963267948966"),
+            ("(acos 1)", "0"),
+            ("(acos -1)", "3.141592653589793"),
+            ("(atan 1)", "0.7853981633974483"),
+            ("(atan 1 -1)", "2.356194490192345"),
+            ("(atan -1 0)", "-1.5707963267948966"),
+            ("(floor 7/2)", "3"),
+            ("(floor -7/2)", "-4"),
+            ("(ceiling 7/2)", "4"),
+            ("(truncate -7/2)", "-3"),
+            ("(round 7/2)", "4"),
+            ("(round 5/2)", "2"),
+            ("(round -5/2)", "-2"),
+            ("(round 8/3)", "3"),
+            ("(round 2.5)", "2.0"),
+            ("(round 3.5)", "4.0"),
+            ("(round -2.5)", "-2.0"),
+            ("(exact? (floor 2.5))", "#f"),
+            ("(floor -4.3)", "-5.0"),
+            ("(ceiling -4.3)", "-4.0"),
+            ("(truncate -4.7)", "-4.0"),
+            ("(round 7)", "7"),
+            ("(square 5)", "25"),
+            ("(square 1/2)", "1/4"),
+            ("(square 3000000000)", "9000000000000000000"),
+            ("(square 4000000000)", "16000000000000000000"),
+            ("(square 1.5)", "2.25"),
+            ("(exact-integer? 32)", "#t"),
+            ("(exact-integer? 32.0)", "#f"),
+            ("(exact-integer? 1/2)", "#f"),
+            ("(exact-integer? 100000000000000000000)", "#t"),
+            ("(exact-integer? 'a)", "#f"),
+            ("(nan? 1)", "#f"),
+            ("(infinite? (/ 1.0 0.0))", "#t"),
+            ("(infinite? 100000000000000000000)", "#f"),
+            ("(finite? 1.5)", "#t"),
+        ] {
+            let ans = parse_and_eval(program.to_string(), &mut env).unwrap();
+            assert_eq!(format!("{}", ans), expected, "{}", program);
+        }
+
+        for (program, expected) in [
+            ("(sqrt 'a)", "type error: expected a number, got symbol a"),
+            ("(log)", "log: expected 1 or 2 arguments, got 0"),
+            ("(atan 1 2 3)", "atan: expected 1 or 2 arguments, got 3"),
+            (
+                "(nan? \"x\")",
+                "type error: expected a number, got string \"x\"",
+            ),
+        ] {
+            let err = parse_and_eval(program.to_string(), &mut env).unwrap_err();
+            assert_eq!(format!("{}", err.root()), expected, "{}", program);
+        }
+    }
+
+    #[test]
+    fn number_syntax() {
+        let mut env = default_env();
+        for (program, expected) in [
+            ("#xFF", "255"),
+            ("#x-ff", "-255"),
+            ("#b1010", "10"),
+            ("#o777", "511"),
+            ("#d99", "99"),
+            ("#x1/A", "1/10"),
+            ("#xFFFFFFFFFFFFFFFFFF", "4722366482869645213695"),
+            ("#e1.5", "3/2"),
+            ("#e0.1", "1/10"),
+            ("#e1e3", "1000"),
+            ("#e-1.25e-1", "-1/8"),
+            ("#i3/4", "0.75"),
+            ("#i5", "5.0"),
+            ("#x#e10", "16"),
+            ("#i#b11", "3.0"),
+            ("1e10", "10000000000.0"),
+            ("1.5e-7", "1.5e-7"),
+            (".5", "0.5"),
+            ("-0.0", "-0.0"),
+            ("6.", "6.0"),
+            ("(* 2.0 3)", "6.0"),
+            ("(+ 1 2)", "3"),
+            ("1e100", "1.0e100"),
+            ("1e21", "1.0e21"),
+            ("-1e-7", "-1.0e-7"),
+            ("1.5e300", "1.5e300"),
+            ("(number->string 1e21)", "1.0e21"),
+            ("+inf.0", "+inf.0"),
+            ("-inf.0", "-inf.0"),
+            ("+nan.0", "+nan.0"),
+            ("(/ 1.0 0.0)", "+inf.0"),
+            ("(nan? +nan.0)", "#t"),
+            ("(infinite? -inf.0)", "#t"),
+            (
+                "'(inf nan infinity #xg 1e 1/0 - + .)",
+                "(inf nan infinity #xg 1e 1/0 - + .)",
+            ),
+            ("(number? 'inf)", "#f"),
+            ("(number->string 255)", "255"),
+            ("(number->string 255 16)", "ff"),
+            ("(number->string -10 2)", "-1010"),
+            ("(number->string 1/3 2)", "1/11"),
+            ("(number->string 2.5)", "2.5"),
+            ("(number->string 3.0)", "3.0"),
+            ("(string->number \"ff\" 16)", "255"),
+            ("(string->number \"#xff\")", "255"),
+            ("(string->number \"101\" 2)", "5"),
+            ("(string->number \"1e3\")", "1000.0"),
+            ("(string->number \"-7/14\")", "-1/2"),
+            ("(string->number \"abc\")", "#f"),
+            ("(string->number \"12\" 2)", "#f"),
+            ("(string->number \"\")", "#f"),
+            // floats read back as the same inexact number
+            (
+                "(map (lambda (x)
+                        (let ((y (string->number (number->string x))))
+                          (and (inexact? y) (= x y))))
+                      (list 1e21 1e-7 -2.5e-300 123.0 0.1))",
+                "(#t #t #t #t #t)",
+            ),
+            ("(string->number \"#e1e400000000\")", "#f"),
+            ("(string->number \"#e1e-400000000\")", "#f"),
+        ] {
+            let ans = parse_and_eval(program.to_string(), &mut env).unwrap();
+            assert_eq!(format!("{}", ans), expected, "{}", program);
+        }
+
+        for (program, expected) in [
+            (
+                "(number->string 1.5 2)",
+                "number->string: 1.5 is inexact and can only be written in radix 10",
+            ),
+            (
+                "(string->number \"1\" 3)",
+                "string->number: radix must be 2, 8, 10 or 16, got 3",
+            ),
+            (
+                "(string->number 1)",
+                "type error: expected a string, got number 1",
+            ),
+        ] {
+            let err = parse_and_eval(program.to_string(), &mut env).unwrap_err();
+            assert_eq!(format!("{}", err.root()), expected, "{}", program);
+        }
+    }
+
+    #[test]
+    fn comparison_chains() {
+        let mut env = default_env();
+        for (program, expected) in [
+            ("(< 1 2 3)", "#t"),
+            ("(< 1 3 2)", "#f"),
+            ("(<= 1 1 2)", "#t"),
+            ("(> 3 2 1 0)", "#t"),
+            ("(>= 3 3 4)", "#f"),
+            ("(= 1 1 1.0)", "#t"),
+            ("(= 1 1 2)", "#f"),
+            ("(= 1/2 0.5 2/4)", "#t"),
+            ("(< 5)", "#t"),
+            ("(= 9007199254740993 9007199254740992.0)", "#f"),
+            ("(< 9007199254740992 9007199254740993)", "#t"),
+            ("(< -inf.0 -100000000000000000000 1/3 +inf.0)", "#t"),
+            ("(= +nan.0 +nan.0)", "#f"),
+            ("(< 1 +nan.0)", "#f"),
+            ("(>= +nan.0 1)", "#f"),
+        ] {
+            let ans = parse_and_eval(program.to_string(), &mut env).unwrap();
+            assert_eq!(format!("{}", ans), expected, "{}", program);
+        }
+
+        for (program, expected) in [
+            ("(< 1 'a)", "type error: expected a number, got symbol a"),
+            (
+                "(< 2 1 \"x\")",
+                "type error: expected a number, got string \"x\"",
+            ),
+            ("(= #t #t)", "type error: expected a number, got boolean #t"),
+            ("(<)", "<: expected at least 1 arguments, got 0"),
+        ] {
+            let err = parse_and_eval(program.to_string(), &mut env).unwrap_err();
+            assert_eq!(format!("{}", err.root()), expected, "{}", program);
+        }
+    }
+
+    #[test]
+    fn strings() {
+        let mut env = default_env();
+        for (program, expected) in [
+            ("(string-length \"héllo\")", "5"),
+            ("(string-append \"foo\" \"\" \"bar\")", "\"foobar\""),
+            ("(string-append)", "\"\""),
+            ("(substring \"héllo\" 1 3)", "\"él\""),
+            ("(substring \"hello\" 2)", "\"llo\""),
+            ("(string-ref \"abc\" 2)", "#\\c"),
+            ("(string=? \"a\" \"a\" \"a\")", "#t"),
+            ("(string<? \"abc\" \"abd\" \"b\")", "#t"),
+            ("(string<? \"b\" \"a\")", "#f"),
+            ("(string>=? \"b\" \"b\" \"a\")", "#t"),
+            ("(string-ci=? \"HeLLo\" \"hello\")", "#t"),
+            ("(string-ci<? \"apple\" \"BANANA\")", "#t"),
+            ("(string<? \"apple\" \"BANANA\")", "#f"),
+            ("(string-upcase \"straße\")", "\"STRASSE\""),
+            ("(string-downcase \"ABC\")", "\"abc\""),
+            (
+                "(string-split \"a,b,,c\" \",\")",
+                "(\"a\" \"b\" \"\" \"c\")",
+            ),
+            ("(string-split \"  two  words \")", "(\"two\" \"words\")"),
+            ("(string-join '(\"a\" \"b\" \"c\") \", \")", "\"a, b, c\""),
+            ("(string-join '(\"a\" \"b\"))", "\"a b\""),
+            ("(string-join '())", "\"\""),
+            ("(string-index \"ab1c\" char-numeric?)", "2"),
+            ("(string-index \"abc\" (lambda (c) #f))", "#f"),
+            ("(string-index \"abc\" #\\c)", "2"),
+            ("(string-split \"a b\" #\\space)", "(\"a\" \"b\")"),
+            ("(string-contains \"héllo world\" \"wor\")", "6"),
+            ("(string-contains \"hello\" \"xyz\")", "#f"),
+            ("(string->list \"abc\")", "(#\\a #\\b #\\c)"),
+            ("(list->string (string->list \"héllo\"))", "\"héllo\""),
+            ("(string->symbol \"foo\")", "foo"),
+            ("(symbol->string 'foo)", "\"foo\""),
+            (
+                "(list (string? \"a\") (string? 'a) (symbol? 'a) (symbol? \"a\"))",
+                "(#t #f #t #f)",
+            ),
+            ("'(\"a\\\"b\" c \"d\\\\e\")", "(\"a\\\"b\" c \"d\\\\e\")"),
+        ] {
+            let ans = parse_and_eval(program.to_string(), &mut env).unwrap();
+            assert_eq!(format!("{}", ans.written()), expected, "{}", program);
+        }
+
+        // display shows strings without quotes, also inside lists
+        let ans = parse_and_eval("(list \"a b\" 'c)".to_string(), &mut env).unwrap();
+        assert_eq!(format!("{}", ans), "(a b c)");
+        assert_eq!(format!("{}", ans.written()), "(\"a b\" c)");
+
+        for (program, expected) in [
+            (
+                "(string-length 'a)",
+                "type error: expected a string, got symbol a",
+            ),
+            (
+                "(substring \"abc\" 2 5)",
+                "substring: range 2 to 5 is out of bounds for a string of length 3",
+            ),
+            (
+                "(substring \"abc\" 2 1)",
+                "substring: range 2 to 1 is out of bounds for a string of length 3",
+            ),
+            (
+                "(string-ref \"abc\" 3)",
+                "string-ref: index 3 is out of bounds for a string of length 3",
+            ),
+            (
+                "(string-ref \"abc\" -1)",
+                "type error: expected an exact non-negative integer, got number -1",
+            ),
+            (
+                "(string-split \"abc\" \"\")",
+                "string-split: the separator is empty",
+            ),
+            (
+                "(list->string '(\"a\"))",
+                "type error: expected a character, got string \"a\"",
+            ),
+            (
+                "(symbol->string \"a\")",
+                "type error: expected a symbol, got string \"a\"",
+            ),
+            (
+                "(string=?)",
+                "string=?: expected at least 1 arguments, got 0",
+            ),
+        ] {
+            let err = parse_and_eval(program.to_string(), &mut env).unwrap_err();
+            assert_eq!(format!("{}", err.root()), expected, "{}", program);
+        }
+    }
+
+    #[test]
+    fn characters() {
+        let mut env = default_env();
+        for (program, expected) in [
+            ("#\\a", "#\\a"),
+            ("#\\space", "#\\space"),
+            ("#\\newline", "#\\newline"),
+            ("#\\x41", "#\\A"),
+            ("#\\x", "#\\x"),
+            ("#\\(", "#\\("),
+            ("'(#\\) #\\ )", "(#\\) #\\space)"),
+            ("#\\λ", "#\\λ"),
+            ("(integer->char 7)", "#\\alarm"),
+            ("(integer->char 1)", "#\\x1"),
+            ("(list (char? #\\a) (char? \"a\") (char? 'a))", "(#t #f #f)"),
+            ("(char->integer #\\A)", "65"),
+            ("(char->integer #\\x1F600)", "128512"),
+            ("(integer->char 955)", "#\\λ"),
+            ("(list (char-alphabetic? #\\a) (char-alphabetic? #\\λ) (char-alphabetic? #\\1))", "(#t #t #f)"),
+            ("(list (char-numeric? #\\7) (char-numeric? #\\x663) (char-numeric? #\\a))", "(#t #t #f)"),
+            ("(list (char-whitespace? #\\space) (char-whitespace? #\\tab) (char-whitespace? #\\a))", "(#t #t #f)"),
+            ("(list (char-upper-case? #\\A) (char-lower-case? #\\A))", "(#t #f)"),
+            ("(char-upcase #\\a)", "#\\A"),
+            ("(char-upcase #\\λ)", "#\\Λ"),
+            ("(char-upcase #\\ß)", "#\\ß"),
+            ("(char-downcase #\\A)", "#\\a"),
+            ("(char=? #\\a #\\a #\\a)", "#t"),
+            ("(char<? #\\a #\\b #\\c)", "#t"),
+            ("(char<? #\\a #\\c #\\b)", "#f"),
+            ("(char>=? #\\b #\\b #\\a)", "#t"),
+            ("(equal? #\\a #\\a)", "#t"),
+            ("(list->string (list #\\h #\\i))", "\"hi\""),
+        ] {
+            let ans = parse_and_eval(program.to_string(), &mut env).unwrap();
+            assert_eq!(format!("{}", ans.written()), expected, "{}", program);
+        }
+        let ans = parse_and_eval("(list #\\a #\\space)".to_string(), &mut env).unwrap();
+        assert_eq!(format!("{}", ans), "(a  )");
+
+        for (program, expected) in [
+            ("#\\foo", "syntax error: unknown character #\\foo"),
+            ("#\\xd800", "syntax error: unknown character #\\xd800"),
+            (
+                "(char->integer \"a\")",
+                "type error: expected a character, got string \"a\"",
+            ),
+            (
+                "(integer->char 55296)",
+                "integer->char: 55296 is not a Unicode scalar value",
+            ),
+            (
+                "(integer->char -1)",
+                "type error: expected an exact non-negative integer, got number -1",
+            ),
+            (
+                "(char<? #\\a 1)",
+                "type error: expected a character, got number 1",
+            ),
+        ] {
+            let err = parse_and_eval(program.to_string(), &mut env).unwrap_err();
+            assert_eq!(format!("{}", err.root()), expected, "{}", program);
+        }
+    }
+
+    #[test]
+    fn string_escapes() {
+        let mut env = default_env();
+        for (program, expected) in [
+            (r#""a\nb""#, "a\nb"),
+            (r#""tab\there""#, "tab\there"),
+            (r#""\r\a\b""#, "\r\u{7}\u{8}"),
+            (r#""say \"hi\" \\ \|""#, "say \"hi\" \\ |"),
+            (r#""\x41;\x3bb;""#, "Aλ"),
+            ("\"one \\   \n    two\"", "one two"),
+            ("\"one \\\r\n two\"", "one two"),
+            ("\"multi\nline\"", "multi\nline"),
+        ] {
+            let ans = parse_and_eval(program.to_string(), &mut env).unwrap();
+            assert_eq!(ans, expected.into_exp(), "{}", program);
+        }
+
+        // write escapes strings again, so they read back the same
+        for (program, expected) in [
+            (r#""a\nb\tc""#, r#""a\nb\tc""#),
+            (r#""\"q\" \\""#, r#""\"q\" \\""#),
+            (r#""\x1;\a""#, r#""\x1;\a""#),
+            (r#"(list "x\ny" "é")"#, r#"("x\ny" "é")"#),
+        ] {
+            let ans = parse_and_eval(program.to_string(), &mut env).unwrap();
+            let written = format!("{}", ans.written());
+            assert_eq!(written, expected, "{}", program);
+            let again = parse_and_eval(format!("'{}", written), &mut env).unwrap();
+            assert_eq!(again, ans, "{}", program);
+        }
+
+        for (program, expected) in [
+            (r#""abc"#, "syntax error: unterminated string"),
+            (r#""abc\"#, "syntax error: unterminated string"),
+            (r#"(f "abc)"#, "syntax error: unterminated string"),
+            (r#""a\qb""#, "syntax error: invalid escape \\q in string"),
+            (r#""\x41""#, "syntax error: invalid escape \\x in string"),
+            (r#""\xzz;""#, "syntax error: invalid escape \\x in string"),
+            (r#""\xd800;""#, "syntax error: invalid escape \\x in string"),
+            (r#""a\ b""#, "syntax error: invalid escape \\  in string"),
+        ] {
+            let err = parse_and_eval(program.to_string(), &mut env).unwrap_err();
+            assert_eq!(format!("{}", err.root()), expected, "{}", program);
+        }
+        let err = parse_and_eval("\"ok\"\n  \"a\\q\"".to_string(), &mut env).unwrap_err();
+        assert_eq!(
+            format!("{}", err),
+            "2:5: syntax error: invalid escape \\q in string"
+        );
+    }
+
+    #[test]
+    fn mutable_strings() {
+        let mut env = default_env();
+        for (program, expected) in [
+            ("(make-string 3 #\\x)", "\"xxx\""),
+            ("(make-string 2)", "\"  \""),
+            ("(make-string 0 #\\x)", "\"\""),
+            (
+                "(let ((s (make-string 3 #\\a))) (string-set! s 1 #\\λ) s)",
+                "\"aλa\"",
+            ),
+            (
+                "(let ((s (string-copy \"héllo\"))) (string-set! s 1 #\\e) s)",
+                "\"hello\"",
+            ),
+            ("(string-copy \"hello\" 1 3)", "\"el\""),
+            ("(string-copy \"hello\" 3)", "\"lo\""),
+            (
+                "(let ((s (make-string 5 #\\-))) (string-fill! s #\\*) s)",
+                "\"*****\"",
+            ),
+            (
+                "(let ((s (string-copy \"abcde\"))) (string-fill! s #\\z 1 3) s)",
+                "\"azzde\"",
+            ),
+            // the copy is a separate string, while other names share it
+            (
+                "(let* ((a (make-string 2 #\\a)) (b (string-copy a)) (c a))
+                   (string-set! a 0 #\\b)
+                   (list a b c))",
+                "(\"ba\" \"aa\" \"ba\")",
+            ),
+            (
+                "(let ((s (make-string 1 #\\a))) (list (same_obj? s s) (same_obj? s (string-copy s))))",
+                "(#t #f)",
+            ),
+        ] {
+            let ans = parse_and_eval(program.to_string(), &mut env).unwrap();
+            assert_eq!(format!("{}", ans.written()), expected, "{}", program);
+        }
+
+        for (program, expected) in [
+            (
+                "(string-set! (make-string 2) 2 #\\a)",
+                "string-set!: index 2 is out of bounds for a string of length 2",
+            ),
+            (
+                "(string-set! (make-string 2) 0 \"a\")",
+                "type error: expected a character, got string \"a\"",
+            ),
+            (
+                "(string-set! 'a 0 #\\a)",
+                "type error: expected a string, got symbol a",
+            ),
+            (
+                "(string-fill! (make-string 2) #\\a 1 3)",
+                "string-fill!: range 1 to 3 is out of bounds for a string of length 2",
+            ),
+            (
+                "(make-string -1)",
+                "type error: expected an exact non-negative integer, got number -1",
+            ),
+            (
+                "(string-copy)",
+                "string-copy: expected 1 to 3 arguments, got 0",
+            ),
+            (
+                "(substring \"a\")",
+                "substring: expected 2 or 3 arguments, got 1",
+            ),
+            (
+                "(string-fill! \"ab\" #\\a)",
+                "string-fill!: cannot change the string literal \"ab\"",
+            ),
+            (
+                "(string-set! (car '(\"ab\")) 0 #\\a)",
+                "string-set!: cannot change the string literal \"ab\"",
+            ),
+        ] {
+            let err = parse_and_eval(program.to_string(), &mut env).unwrap_err();
+            assert_eq!(format!("{}", err.root()), expected, "{}", program);
+        }
+
+        // a literal returned from a procedure body stays as written
+        parse_and_eval("(define (g) \"abc\")".to_string(), &mut env).unwrap();
+        let err = parse_and_eval("(string-set! (g) 0 #\\z)".to_string(), &mut env).unwrap_err();
+        assert_eq!(
+            format!("{}", err.root()),
+            "string-set!: cannot change the string literal \"abc\""
+        );
+        let ans = parse_and_eval("(g)".to_string(), &mut env).unwrap();
+        assert_eq!(format!("{}", ans.written()), "\"abc\"");
+    }
+
+    #[test]
+    fn string_ports() {
+        let mut env = default_env();
+        env.insert(
+            "eof-lookalike".to_string(),
+            Exp::Record(Rc::new(Record::new("eof-object", vec![]))),
+        );
+        for (program, expected) in [
+            (
+                "(let ((out (open-output-string)))
+                   (display \"total: \" out)
+                   (write 42 out)
+                   (write-char #\\space out)
+                   (write \"q\" out)
+                   (newline out)
+                   (write-string \"done\" out)
+                   (get-output-string out))",
+                "\"total: 42 \\\"q\\\"\\ndone\"",
+            ),
+            (
+                "(let ((out (open-output-string)))
+                   (for-each (lambda (x) (display x out) (display \",\" out)) '(1 2 3))
+                   (get-output-string out))",
+                "\"1,2,3,\"",
+            ),
+            ("(get-output-string (open-output-string))", "\"\""),
+            (
+                "(let ((in (open-input-string \"ab\")))
+                   (list (peek-char in) (read-char in) (read-char in) (read-char in)))",
+                "(#\\a #\\a #\\b #<eof-object>)",
+            ),
+            (
+                "(let ((in (open-input-string \"one\\ntwo\\r\\n\\nlast\")))
+                   (list (read-line in) (read-line in) (read-line in) (read-line in) (read-line in)))",
+                "(\"one\" \"two\" \"\" \"last\" #<eof-object>)",
+            ),
+            (
+                "(let ((in (open-input-string \"12 345\")))
+                   (list (string->number (read-string 2 in)) (read-string 10 in) (read-string 1 in)))",
+                "(12 \" 345\" #<eof-object>)",
+            ),
+            (
+                "(list (eof-object? (eof-object)) (eof-object? \"\") (eof-object? (read-char (open-input-string \"\"))))",
+                "(#t #f #t)",
+            ),
+            // the input port reads a copy, so later changes don't show up
+            (
+                "(let* ((s (make-string 2 #\\a)) (in (open-input-string s)))
+                   (string-set! s 0 #\\b)
+                   (read-string 2 in))",
+                "\"aa\"",
+            ),
+            ("(open-output-string)", "#<output-port>"),
+            (
+                "(let ((in (open-input-string \" (a \\\"b\\\" . c)\n 12 #t  \")))
+                   (list (read in) (read in) (read in) (read in) (read-char in)))",
+                "((a \"b\" . c) 12 #t #<eof-object> #<eof-object>)",
+            ),
+            (
+                "(let ((in (open-input-string \"x(y)\")))
+                   (list (read in) (peek-char in)))",
+                "(x #\\()",
+            ),
+            // only the eof object itself counts
+            ("(eof-object? eof-lookalike)", "#f"),
+        ] {
+            let ans = parse_and_eval(program.to_string(), &mut env).unwrap();
+            assert_eq!(format!("{}", ans.written()), expected, "{}", program);
+        }
+
+        for (program, expected) in [
+            (
+                "(read (open-input-string \"(1 2\"))",
+                "syntax error: Non matching parens, this ( is never closed",
+            ),
+            (
+                "(read-char (open-output-string))",
+                "type error: expected an input port, got port #<output-port>",
+            ),
+            (
+                "(display 1 (open-input-string \"\"))",
+                "type error: expected an output port, got port #<input-port>",
+            ),
+            (
+                "(get-output-string \"s\")",
+                "type error: expected an output port, got string \"s\"",
+            ),
+            ("(newline 1 2)", "newline: expected 0 or 1 arguments, got 2"),
+        ] {
+            let err = parse_and_eval(program.to_string(), &mut env).unwrap_err();
+            assert_eq!(format!("{}", err.root()), expected, "{}", program);
+        }
+    }
+
     #[test]
     fn test() {}
 }
@@ -5,6 +5,7 @@ use risp::runner::{repl, run_from_source_code};
 use std::env;
 use std::fs::File;
 use std::io::Read;
+use std::process::exit;
 
 fn main() -> std::io::Result<()> {
     if env::args().len() >= 2 {
@@ -12,7 +13,10 @@ fn main() -> std::io::Result<()> {
         let mut file = File::open(&args[1])?;
         let mut contents = String::new();
         file.read_to_string(&mut contents)?;
-        run_from_source_code(contents);
+        if let Err(e) = run_from_source_code(contents, &args[1]) {
+            eprintln!("{}", e);
+            exit(1);
+        }
     } else {
         let mut def_env = default_env();
         loop {
@@ -22,8 +26,8 @@ fn main() -> std::io::Result<()> {
                     continue;
                 }
                 Some(x) => match parse_and_eval(x.clone(), &mut def_env) {
-                    Ok(y) => println!("=> {}", y),
-                    Err(y) => eprintln!("{:?}", y),
+                    Ok(y) => println!("=> {}", y.written()),
+                    Err(y) => eprintln!("{}", y),
                 },
             }
         }
@@ -0,0 +1,535 @@
+//! Arithmetic on `Number`s following Scheme's exactness rules: integers and
+//! rationals are exact and floats inexact. An operation on exact numbers
+//! gives an exact result and an operation involving an inexact number gives
+//! an inexact result.
+//!
+//! Exact numbers are kept normalised: integers are `Int`s when they fit in
+//! an `i64` and `BigInt`s otherwise, and a `Rational` is never an integer.
+//! Results are promoted when an `i64` operation would overflow and demoted
+//! again by `Number::from_rational` and `Number::from_bigint`.
+
+use super::types::{Exceptions, Number};
+use num_bigint::BigInt;
+use num_integer::Integer;
+use num_rational::BigRational;
+use num_traits::{One, Signed, ToPrimitive, Zero};
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+
+/// How many bits the numerator or denominator of an exact power may have,
+/// estimated as the bits of the base times the exponent. That is a 2 MB
+/// number; much bigger ones take too long to compute or don't fit in memory.
+const MAX_POWER_BITS: u64 = 1 << 24;
+
+impl Number {
+    pub fn from_bigint(x: BigInt) -> Number {
+        match x.to_i64() {
+            Some(x) => Number::Int(x),
+            None => Number::BigInt(x),
+        }
+    }
+
+    pub fn from_rational(x: BigRational) -> Number {
+        if x.is_integer() {
+            Number::from_bigint(x.to_integer())
+        } else {
+            Number::Rational(x)
+        }
+    }
+
+    pub fn is_exact(&self) -> bool {
+        !matches!(self, Number::Float(_))
+    }
+
+    pub fn is_integer(&self) -> bool {
+        match self {
+            Number::Int(_) | Number::BigInt(_) => true,
+            Number::Rational(_) => false,
+            Number::Float(x) => x.fract() == 0.0,
+        }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        match self {
+            Number::Int(x) => *x == 0,
+            Number::BigInt(_) | Number::Rational(_) => false,
+            Number::Float(x) => *x == 0.0,
+        }
+    }
+
+    /// The value of an exact integer as a `BigInt`.
+    pub fn to_bigint(&self) -> Option<BigInt> {
+        match self {
+            Number::Int(x) => Some(BigInt::from(*x)),
+            Number::BigInt(x) => Some(x.clone()),
+            Number::Rational(_) | Number::Float(_) => None,
+        }
+    }
+
+    /// The value of an exact number as a `BigRational`.
+    pub fn to_rational(&self) -> Option<BigRational> {
+        match self {
+            Number::Rational(x) => Some(x.clone()),
+            _ => self.to_bigint().map(BigRational::from_integer),
+        }
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        match self {
+            Number::Int(x) => *x as f64,
+            Number::BigInt(x) => x.to_f64().unwrap_or(f64::NAN),
+            Number::Rational(x) => x.to_f64().unwrap_or(f64::NAN),
+            Number::Float(x) => *x,
+        }
+    }
+
+    pub fn to_inexact(&self) -> Number {
+        Number::Float(self.to_f64())
+    }
+
+    /// The exact number equal to `self`. Every finite float has one, e.g.
+    /// `0.5` is `1/2`.
+    pub fn to_exact(&self) -> Result<Number, Exceptions> {
+        match self {
+            Number::Float(x) => BigRational::from_float(*x)
+                .map(Number::from_rational)
+                .ok_or_else(|| {
+                    Exceptions::ValueError(format!(
+                        "inexact->exact: {} has no exact representation",
+                        self
+                    ))
+                }),
+            _ => Ok(self.clone()),
+        }
+    }
+
+    fn arith(
+        &self,
+        other: &Number,
+        int_op: fn(i64, i64) -> Option<i64>,
+        exact_op: fn(BigRational, BigRational) -> BigRational,
+        float_op: fn(f64, f64) -> f64,
+    ) -> Number {
+        if let (Number::Int(x), Number::Int(y)) = (self, other) {
+            if let Some(z) = int_op(*x, *y) {
+                return Number::Int(z);
+            }
+        }
+        match (self.to_rational(), other.to_rational()) {
+            (Some(x), Some(y)) => Number::from_rational(exact_op(x, y)),
+            _ => Number::Float(float_op(self.to_f64(), other.to_f64())),
+        }
+    }
+
+    pub fn add(&self, other: &Number) -> Result<Number, Exceptions> {
+        Ok(self.arith(other, i64::checked_add, |x, y| x + y, |x, y| x + y))
+    }
+
+    pub fn sub(&self, other: &Number) -> Result<Number, Exceptions> {
+        Ok(self.arith(other, i64::checked_sub, |x, y| x - y, |x, y| x - y))
+    }
+
+    pub fn mul(&self, other: &Number) -> Result<Number, Exceptions> {
+        Ok(self.arith(other, i64::checked_mul, |x, y| x * y, |x, y| x * y))
+    }
+
+    /// Dividing by an exact zero is an error. Exact division is exact, so
+    /// `(/ 1 3)` is `1/3`.
+    pub fn div(&self, other: &Number) -> Result<Number, Exceptions> {
+        if other.is_exact() && other.is_zero() {
+            return Err(Exceptions::DivisionByZero);
+        }
+        let exact_quotient = |x: i64, y: i64| match x.checked_rem(y) {
+            Some(0) => x.checked_div(y),
+            _ => None,
+        };
+        Ok(self.arith(other, exact_quotient, |x, y| x / y, |x, y| x / y))
+    }
+
+    pub fn neg(&self) -> Result<Number, Exceptions> {
+        Number::Int(0).sub(self)
+    }
+
+    pub fn abs(&self) -> Result<Number, Exceptions> {
+        match self {
+            Number::Int(x) => Ok(match x.checked_abs() {
+                Some(x) => Number::Int(x),
+                None => Number::BigInt(BigInt::from(*x).abs()),
+            }),
+            Number::BigInt(x) => Ok(Number::BigInt(x.abs())),
+            Number::Rational(x) => Ok(Number::Rational(x.abs())),
+            Number::Float(x) => Ok(Number::Float(x.abs())),
+        }
+    }
+
+    /// `self` raised to `other`, exact when both are exact and the exponent
+    /// is an integer.
+    pub fn pow(&self, other: &Number) -> Result<Number, Exceptions> {
+        let too_large = || Exceptions::ValueError(format!("expt: exponent {} is too large", other));
+        match (self.to_rational(), other) {
+            (Some(x), Number::Int(y)) => {
+                let n = u32::try_from(y.unsigned_abs()).map_err(|_| too_large())?;
+                // 0, 1 and -1 stay small whatever the exponent
+                let bits = x.numer().bits().max(x.denom().bits());
+                if bits > 1 && bits.saturating_mul(n.into()) > MAX_POWER_BITS {
+                    return Err(too_large());
+                }
+                let power = BigRational::new_raw(x.numer().pow(n), x.denom().pow(n));
+                if *y >= 0 {
+                    Ok(Number::from_rational(power))
+                } else if power.is_zero() {
+                    Err(Exceptions::DivisionByZero)
+                } else {
+                    Ok(Number::from_rational(power.recip()))
+                }
+            }
+            // Only 0, 1 and -1 have powers small enough to compute.
+            (Some(x), Number::BigInt(y)) if x.is_zero() && y.is_negative() => {
+                Err(Exceptions::DivisionByZero)
+            }
+            (Some(x), Number::BigInt(y)) if x.is_zero() || x.abs().is_one() => {
+                Ok(Number::from_rational(if y.is_even() { x.abs() } else { x }))
+            }
+            (Some(_), Number::BigInt(_)) => Err(too_large()),
+            _ => Ok(Number::Float(self.to_f64().powf(other.to_f64()))),
+        }
+    }
+
+    /// Compares the values exactly, without converting exact numbers to
+    /// floats. `None` if either is NaN.
+    pub fn compare(&self, other: &Number) -> Option<Ordering> {
+        match (self, other) {
+            (Number::Int(x), Number::Int(y)) => Some(x.cmp(y)),
+            (Number::Float(x), Number::Float(y)) => x.partial_cmp(y),
+            (Number::Float(x), _) => compare_exact_float(other, *x).map(Ordering::reverse),
+            (_, Number::Float(y)) => compare_exact_float(self, *y),
+            _ => Some(self.to_rational().cmp(&other.to_rational())),
+        }
+    }
+
+    /// The simplest rational within `tolerance` of `self`, inexact if
+    /// either argument is.
+    pub fn rationalize(&self, tolerance: &Number) -> Result<Number, Exceptions> {
+        let inexact = !self.is_exact() || !tolerance.is_exact();
+        let (x, y) = (self.to_f64(), tolerance.to_f64());
+        if inexact && !(x.is_finite() && y.is_finite()) {
+            return Ok(Number::Float(
+                if x.is_nan() || y.is_nan() || (x.is_infinite() && y.is_infinite()) {
+                    f64::NAN
+                } else if y.is_infinite() {
+                    0.0
+                } else {
+                    x
+                },
+            ));
+        }
+        let (x, y) = (self.to_exact()?, tolerance.to_exact()?.abs()?);
+        let (x, y) = (x.to_rational().unwrap(), y.to_rational().unwrap());
+        let simplest = Number::from_rational(simplest_between(&(&x - &y), &(&x + &y)));
+        if inexact {
+            Ok(simplest.to_inexact())
+        } else {
+            Ok(simplest)
+        }
+    }
+    /// The integer `self` as an exact `BigInt`, along with whether it was
+    /// inexact. Only meant for numbers for which `is_integer` holds.
+    fn integer_value(&self) -> (BigInt, bool) {
+        let exact = self.to_exact().ok().and_then(|x| x.to_bigint());
+        (exact.expect("not an integer"), !self.is_exact())
+    }
+
+    fn from_integer(x: BigInt, inexact: bool) -> Number {
+        let x = Number::from_bigint(x);
+        if inexact {
+            x.to_inexact()
+        } else {
+            x
+        }
+    }
+
+    /// The quotient and remainder of dividing the integers `self` and
+    /// `other`, with the quotient rounded towards negative infinity if
+    /// `floor` holds and towards zero otherwise. The remainder has the sign
+    /// of `other` in the first case and of `self` in the second.
+    pub fn div_rem(&self, other: &Number, floor: bool) -> Result<(Number, Number), Exceptions> {
+        if other.is_zero() {
+            return Err(Exceptions::DivisionByZero);
+        }
+        if let (Number::Int(x), Number::Int(y)) = (self, other) {
+            if !(*x == i64::MIN && *y == -1) {
+                let (q, r) = if floor {
+                    x.div_mod_floor(y)
+                } else {
+                    x.div_rem(y)
+                };
+                return Ok((Number::Int(q), Number::Int(r)));
+            }
+        }
+        let ((x, x_inexact), (y, y_inexact)) = (self.integer_value(), other.integer_value());
+        let inexact = x_inexact || y_inexact;
+        let (q, r) = if floor {
+            x.div_mod_floor(&y)
+        } else {
+            x.div_rem(&y)
+        };
+        Ok((
+            Number::from_integer(q, inexact),
+            Number::from_integer(r, inexact),
+        ))
+    }
+
+    /// The greatest common divisor of the integers `self` and `other`,
+    /// which is never negative.
+    pub fn gcd(&self, other: &Number) -> Number {
+        let ((x, x_inexact), (y, y_inexact)) = (self.integer_value(), other.integer_value());
+        Number::from_integer(x.gcd(&y), x_inexact || y_inexact)
+    }
+
+    /// The least common multiple of the integers `self` and `other`, which
+    /// is never negative.
+    pub fn lcm(&self, other: &Number) -> Number {
+        let ((x, x_inexact), (y, y_inexact)) = (self.integer_value(), other.integer_value());
+        Number::from_integer(x.lcm(&y), x_inexact || y_inexact)
+    }
+
+    /// Rounds to an integer with `exact` for rationals and `inexact` for
+    /// floats. Integers are left alone.
+    fn round_with(
+        &self,
+        exact: fn(&BigRational) -> BigRational,
+        inexact: fn(f64) -> f64,
+    ) -> Number {
+        match self {
+            Number::Rational(x) => Number::from_rational(exact(x)),
+            Number::Float(x) => Number::Float(inexact(*x)),
+            _ => self.clone(),
+        }
+    }
+
+    pub fn floor(&self) -> Number {
+        self.round_with(BigRational::floor, f64::floor)
+    }
+
+    pub fn ceiling(&self) -> Number {
+        self.round_with(BigRational::ceil, f64::ceil)
+    }
+
+    pub fn truncate(&self) -> Number {
+        self.round_with(BigRational::trunc, f64::trunc)
+    }
+
+    /// Rounds to the nearest integer, and to the even one on a tie.
+    pub fn round(&self) -> Number {
+        self.round_with(round_half_even, f64::round_ties_even)
+    }
+
+    /// The square root, which is exact when `self` is the square of an
+    /// exact number. Negative numbers have no real square root and give NaN.
+    pub fn sqrt(&self) -> Number {
+        match self.to_rational() {
+            Some(x) if !x.is_negative() => {
+                let (n, d) = (x.numer().sqrt(), x.denom().sqrt());
+                if &n * &n == *x.numer() && &d * &d == *x.denom() {
+                    return Number::from_rational(BigRational::new_raw(n, d));
+                }
+            }
+            _ => {}
+        }
+        Number::Float(self.to_f64().sqrt())
+    }
+
+    /// `self` written in base `radix`. Only exact numbers can be written in
+    /// bases other than 10.
+    pub fn to_string_radix(&self, radix: u32) -> Option<String> {
+        match self {
+            _ if radix == 10 => Some(self.to_string()),
+            Number::Int(x) => Some(BigInt::from(*x).to_str_radix(radix)),
+            Number::BigInt(x) => Some(x.to_str_radix(radix)),
+            Number::Rational(x) => Some(format!(
+                "{}/{}",
+                x.numer().to_str_radix(radix),
+                x.denom().to_str_radix(radix)
+            )),
+            Number::Float(_) => None,
+        }
+    }
+
+    /// Whether the integer `self` is even.
+    pub fn is_even(&self) -> bool {
+        match self {
+            Number::Int(x) => x % 2 == 0,
+            _ => self.integer_value().0.is_even(),
+        }
+    }
+
+    /// The largest `s` with `s * s <= self` and the rest `self - s * s`,
+    /// for an exact non-negative integer `self`.
+    pub fn exact_integer_sqrt(&self) -> (Number, Number) {
+        let x = self.to_bigint().expect("not an exact integer");
+        let s = x.sqrt();
+        let rest = &x - &s * &s;
+        (Number::from_bigint(s), Number::from_bigint(rest))
+    }
+}
+
+fn compare_exact_float(x: &Number, y: f64) -> Option<Ordering> {
+    if y.is_nan() {
+        None
+    } else if y.is_infinite() {
+        Some(if y > 0.0 {
+            Ordering::Less
+        } else {
+            Ordering::Greater
+        })
+    } else {
+        Some(x.to_rational()?.cmp(&BigRational::from_float(y)?))
+    }
+}
+
+fn round_half_even(x: &BigRational) -> BigRational {
+    let floor = x.floor();
+    let half = BigRational::new(BigInt::from(1), BigInt::from(2));
+    match (x - &floor).cmp(&half) {
+        Ordering::Less => floor,
+        Ordering::Equal if floor.to_integer().is_even() => floor,
+        _ => floor + BigRational::one(),
+    }
+}
+
+/// The rational with the smallest denominator in `[lo, hi]`.
+fn simplest_between(lo: &BigRational, hi: &BigRational) -> BigRational {
+    if lo.is_positive() {
+        simplest_positive(lo, hi)
+    } else if hi.is_negative() {
+        -simplest_positive(&-hi, &-lo)
+    } else {
+        BigRational::zero()
+    }
+}
+
+fn simplest_positive(lo: &BigRational, hi: &BigRational) -> BigRational {
+    let floor = lo.floor();
+    if floor == *lo {
+        floor
+    } else if floor < hi.floor() {
+        floor + BigRational::one()
+    } else {
+        let rest = simplest_positive(&(hi - &floor).recip(), &(lo - &floor).recip());
+        floor + rest.recip()
+    }
+}
+
+/// Reads a number in Scheme syntax: an integer, a fraction such as `-1/3`,
+/// a decimal such as `1.5e3`, or one of `+inf.0`, `-inf.0` and `+nan.0`.
+/// Digits are in base `radix` unless the number starts with one of the
+/// prefixes `#x`, `#o`, `#b` or `#d`. The prefixes `#e` and `#i` make the
+/// number exact or inexact, so `#e1.5` is `3/2`. Decimals are only allowed
+/// in base 10.
+pub fn parse_number(token: &str, radix: u32) -> Option<Number> {
+    if radix == 10 {
+        if let Ok(x) = token.parse::<i64>() {
+            return Some(Number::Int(x));
+        }
+    }
+    let (mut radix_prefix, mut exactness) = (None, None);
+    let mut rest = token;
+    while rest.len() >= 2 && rest.starts_with('#') {
+        match rest.as_bytes()[1].to_ascii_lowercase() {
+            b'x' if radix_prefix.is_none() => radix_prefix = Some(16),
+            b'o' if radix_prefix.is_none() => radix_prefix = Some(8),
+            b'b' if radix_prefix.is_none() => radix_prefix = Some(2),
+            b'd' if radix_prefix.is_none() => radix_prefix = Some(10),
+            b'e' if exactness.is_none() => exactness = Some(true),
+            b'i' if exactness.is_none() => exactness = Some(false),
+            _ => return None,
+        }
+        rest = &rest[2..];
+    }
+    let n = parse_real(rest, radix_prefix.unwrap_or(radix), exactness == Some(true))?;
+    match exactness {
+        Some(true) => n.to_exact().ok(),
+        Some(false) => Some(n.to_inexact()),
+        None => Some(n),
+    }
+}
+
+/// A number without prefixes. Decimals are read as exact rationals if
+/// `exact` holds, so that `#e0.1` is exactly `1/10`.
+fn parse_real(s: &str, radix: u32, exact: bool) -> Option<Number> {
+    match s.to_ascii_lowercase().as_str() {
+        "+inf.0" => return Some(Number::Float(f64::INFINITY)),
+        "-inf.0" => return Some(Number::Float(f64::NEG_INFINITY)),
+        "+nan.0" | "-nan.0" => return Some(Number::Float(f64::NAN)),
+        _ => {}
+    }
+    let (negative, unsigned) = match s.strip_prefix('-') {
+        Some(unsigned) => (true, unsigned),
+        None => (false, s.strip_prefix('+').unwrap_or(s)),
+    };
+    let is_digits = |s: &str| !s.is_empty() && s.chars().all(|c| c.is_digit(radix));
+    let magnitude = if let Some((n, d)) = unsigned.split_once('/') {
+        if !is_digits(n) || !is_digits(d) {
+            return None;
+        }
+        let n = BigInt::parse_bytes(n.as_bytes(), radix)?;
+        let d = BigInt::parse_bytes(d.as_bytes(), radix)?;
+        if d.is_zero() {
+            return None;
+        }
+        Number::from_rational(BigRational::new(n, d))
+    } else if is_digits(unsigned) {
+        Number::from_bigint(BigInt::parse_bytes(unsigned.as_bytes(), radix)?)
+    } else if radix == 10 && is_decimal(unsigned) {
+        if !exact {
+            // Parsed with the sign so that -0.0 keeps it.
+            return s.parse().ok().map(Number::Float);
+        }
+        Number::from_rational(exact_decimal(unsigned)?)
+    } else {
+        return None;
+    };
+    if negative {
+        magnitude.neg().ok()
+    } else {
+        Some(magnitude)
+    }
+}
+
+/// Splits an unsigned decimal into its integer digits, fraction digits and
+/// exponent, e.g. `1.25e-3` into `1`, `25` and `-3`.
+fn split_decimal(s: &str) -> Option<(&str, &str, i64)> {
+    let (mantissa, exponent) = match s.find(['e', 'E']) {
+        Some(i) => (&s[..i], s[i + 1..].parse().ok()?),
+        None => (s, 0),
+    };
+    let (int, frac) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    Some((int, frac, exponent))
+}
+
+fn is_decimal(s: &str) -> bool {
+    let digits = |s: &str| s.bytes().all(|b| b.is_ascii_digit());
+    match split_decimal(s) {
+        Some((int, frac, _)) => digits(int) && digits(frac) && !(int.is_empty() && frac.is_empty()),
+        None => false,
+    }
+}
+
+/// How many digits an exact decimal like `#e1e400` may be scaled by. Any
+/// more and computing the power of ten would take too long.
+const MAX_DECIMAL_SCALE: u64 = 1_000_000;
+
+fn exact_decimal(s: &str) -> Option<BigRational> {
+    let (int, frac, exponent) = split_decimal(s)?;
+    let mantissa = BigInt::parse_bytes(format!("{}{}", int, frac).as_bytes(), 10)?;
+    let scale = exponent.checked_sub(frac.len() as i64)?;
+    if scale.unsigned_abs() > MAX_DECIMAL_SCALE {
+        return None;
+    }
+    let power = BigInt::from(10).pow(scale.unsigned_abs() as u32);
+    if scale >= 0 {
+        Some(BigRational::from_integer(mantissa * power))
+    } else {
+        Some(BigRational::new(mantissa, power))
+    }
+}
@@ -1,37 +1,76 @@
+use super::number::parse_number;
 use super::types::*;
+use std::collections::VecDeque;
 use std::rc::Rc;
-fn tokenize(chars: &str) -> Vec<String> {
+
+/// A token along with where it starts in the source.
+struct Token {
+    text: String,
+    span: Span,
+}
+
+fn tokenize(chars: &str, file: Option<&Rc<str>>) -> Result<Vec<Token>, Exceptions> {
     let mut toks = vec![];
     let mut i = 0;
     let characters: Vec<char> = chars.chars().collect();
+    let mut positions = Vec::with_capacity(characters.len());
+    let (mut line, mut column) = (1, 1);
+    for c in &characters {
+        positions.push((line, column));
+        if *c == '\n' {
+            line += 1;
+            column = 1;
+        } else {
+            column += 1;
+        }
+    }
+    let span_at = |i: usize| Span {
+        file: file.cloned(),
+        line: positions[i].0,
+        column: positions[i].1,
+    };
     while i < characters.len() {
         match characters[i] {
-            '(' => toks.push("(".to_string()),
-            ')' => toks.push(")".to_string()),
+            '(' => toks.push(Token {
+                text: "(".to_string(),
+                span: span_at(i),
+            }),
+            ')' => toks.push(Token {
+                text: ")".to_string(),
+                span: span_at(i),
+            }),
             '"' => {
-                toks.push("\"".to_string());
+                let span = span_at(i);
+                toks.push(Token {
+                    text: "\"".to_string(),
+                    span: span.clone(),
+                });
                 i += 1;
-                let mut s = String::new();
-                while i < characters.len() && characters[i] != '"' {
-                    match characters[i] {
-                        '\\' => {
-                            if i + 1 < characters.len() {
-                                s.push(characters[i + 1]);
-                                i += 1;
-                            } else {
-                                panic!("Invalid escape '\'");
-                            }
-                        }
-                        c => {
-                            s.push(c);
-                        }
-                    }
+                let s = string_literal(&characters, &mut i).map_err(|(msg, at)| {
+                    Exceptions::SyntaxError(msg).at(Some(&at.map_or(span.clone(), span_at)))
+                })?;
+                toks.push(Token { text: s, span });
+            }
+            '\'' | '`' => {
+                toks.push(Token {
+                    text: characters[i].to_string(),
+                    span: span_at(i),
+                });
+            }
+            ',' => {
+                let span = span_at(i);
+                if characters.get(i + 1) == Some(&'@') {
+                    toks.push(Token {
+                        text: ",@".to_string(),
+                        span,
+                    });
                     i += 1;
+                } else {
+                    toks.push(Token {
+                        text: ",".to_string(),
+                        span,
+                    });
                 }
-                toks.push(s);
-            }
-            '\'' => {
-                toks.push("'".to_string());
             }
             _ => {
                 if characters[i].is_whitespace() {
@@ -40,74 +79,184 @@ fn tokenize(chars: &str) -> Vec<String> {
                     }
                     i -= 1;
                 } else {
+                    let span = span_at(i);
+                    // The character after `#\` belongs to the token even
+                    // when it is a delimiter, as in `#\(` or `#\ `.
+                    let char_literal = characters[i] == '#' && characters.get(i + 1) == Some(&'\\');
                     let mut s = String::new();
                     while i < characters.len()
-                        && (!characters[i].is_whitespace()
-                            && characters[i] != ')'
-                            && characters[i] != '(')
+                        && ((char_literal && s.len() == 2)
+                            || (!characters[i].is_whitespace()
+                                && characters[i] != ')'
+                                && characters[i] != '('))
                     {
                         s.push(characters[i]);
                         i += 1;
                     }
                     i -= 1;
-                    toks.push(s);
+                    toks.push(Token { text: s, span });
                 }
             }
         }
         i += 1;
     }
-    toks
+    Ok(toks)
+}
+
+/// Reads the contents of a string literal from `characters[*i]`, just after
+/// the opening quote, leaving `*i` at the closing quote. Errors come with
+/// the index of a bad escape, or no index for an unterminated string.
+fn string_literal(characters: &[char], i: &mut usize) -> Result<String, (String, Option<usize>)> {
+    let mut s = String::new();
+    loop {
+        match characters.get(*i) {
+            None => return Err(("unterminated string".to_string(), None)),
+            Some('"') => return Ok(s),
+            Some('\\') => {
+                let start = *i;
+                let bad_escape = || {
+                    (
+                        format!(
+                            "invalid escape {} in string",
+                            characters[start..].iter().take(2).collect::<String>()
+                        ),
+                        Some(start),
+                    )
+                };
+                *i += 1;
+                match characters.get(*i) {
+                    None => return Err(("unterminated string".to_string(), None)),
+                    Some('a') => s.push('\u{7}'),
+                    Some('b') => s.push('\u{8}'),
+                    Some('t') => s.push('\t'),
+                    Some('n') => s.push('\n'),
+                    Some('r') => s.push('\r'),
+                    Some(c @ ('"' | '\\' | '|')) => s.push(*c),
+                    Some('x') => {
+                        let hex: String = characters[*i + 1..]
+                            .iter()
+                            .take_while(|c| **c != ';' && **c != '"')
+                            .collect();
+                        *i += hex.chars().count() + 1;
+                        match (
+                            characters.get(*i),
+                            u32::from_str_radix(&hex, 16)
+                                .ok()
+                                .and_then(std::char::from_u32),
+                        ) {
+                            (Some(';'), Some(c)) => s.push(c),
+                            _ => return Err(bad_escape()),
+                        }
+                    }
+                    // A backslash at the end of a line joins it to the next,
+                    // skipping the whitespace around the line break.
+                    Some(c) if c.is_whitespace() => {
+                        while characters.get(*i).is_some_and(|c| *c == ' ' || *c == '\t') {
+                            *i += 1;
+                        }
+                        match characters.get(*i) {
+                            Some('\n') => *i += 1,
+                            Some('\r') if characters.get(*i + 1) == Some(&'\n') => *i += 2,
+                            _ => return Err(bad_escape()),
+                        }
+                        while characters.get(*i).is_some_and(|c| *c == ' ' || *c == '\t') {
+                            *i += 1;
+                        }
+                        continue;
+                    }
+                    Some(_) => return Err(bad_escape()),
+                }
+            }
+            Some(c) => s.push(*c),
+        }
+        *i += 1;
+    }
 }
 
-fn atom(token: String) -> Atom {
-    match &token.parse::<i64>() {
-        Ok(x) => return Atom::Number(Number::Int(*x)),
-        Err(_) => match &token.parse::<f64>() {
-            Ok(x) => return Atom::Number(Number::Float(*x)),
-            Err(_) => match &token[..] {
-                "#t" => Atom::Bool(true),
-                "#f" => Atom::Bool(false),
-                _ => Atom::Symbol(token),
-            },
+fn atom(token: String) -> Result<Atom, Exceptions> {
+    if let Some(name) = token.strip_prefix("#\\") {
+        return char_literal(name).map(Atom::Char);
+    }
+    Ok(match parse_number(&token, 10) {
+        Some(x) => Atom::Number(x),
+        None => match &token[..] {
+            "#t" => Atom::Bool(true),
+            "#f" => Atom::Bool(false),
+            _ => Atom::Symbol(token),
         },
+    })
+}
+
+/// The character written `#\name`: a single character, one of
+/// `CHAR_NAMES` or `x` followed by a hex scalar value.
+fn char_literal(name: &str) -> Result<char, Exceptions> {
+    let mut chars = name.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        return Ok(c);
+    }
+    if let Some((_, c)) = CHAR_NAMES.iter().find(|(n, _)| *n == name) {
+        return Ok(*c);
     }
+    name.strip_prefix('x')
+        .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+        .and_then(std::char::from_u32)
+        .ok_or_else(|| Exceptions::SyntaxError(format!("unknown character #\\{}", name)))
 }
 
-fn read_from_tokens(tokens: &mut Vec<String>) -> Result<Exp, Exceptions> {
-    if tokens.is_empty() {
-        return Err(Exceptions::SyntaxError("Unexpected EOF".to_string()));
+/// The form `'x`, `` `x ``, `,x` or `,@x` stands for.
+fn quote_keyword(token: &str) -> Option<&'static str> {
+    match token {
+        "'" => Some("quote"),
+        "`" => Some("quasiquote"),
+        "," => Some("unquote"),
+        ",@" => Some("unquote-splicing"),
+        _ => None,
     }
-    let token = tokens[0].clone();
-    tokens.remove(0);
+}
+
+fn read_from_tokens(tokens: &mut VecDeque<Token>) -> Result<Exp, Exceptions> {
+    let Token { text: token, span } = tokens
+        .pop_front()
+        .ok_or_else(|| Exceptions::SyntaxError("Unexpected EOF".to_string()))?;
     if token == "(" {
         let mut l = Vec::new();
-        while tokens[0] != ")" {
-            l.push(read_from_tokens(tokens).unwrap());
-            if tokens.is_empty() {
-                return Err(Exceptions::SyntaxError("Non matching parens".to_string()));
+        let mut item_spans = Vec::new();
+        loop {
+            match tokens.front() {
+                None => {
+                    return Err(Exceptions::SyntaxError(
+                        "Non matching parens, this ( is never closed".to_string(),
+                    )
+                    .at(Some(&span)));
+                }
+                Some(t) if t.text == ")" => break,
+                Some(t) => {
+                    item_spans.push(t.span.clone());
+                    l.push(read_from_tokens(tokens)?);
+                }
             }
         }
-        tokens.remove(0);
-        return Ok(Exp::List(Rc::new(l)));
+        tokens.pop_front();
+        Ok(Exp::List(Rc::new(List::read(l, span, item_spans))))
     } else if token == ")" {
-        return Err(Exceptions::SyntaxError("Unexpected )".to_string()));
-    } else if token == "'" {
-        let mut lst = vec![];
-        lst.push(Exp::Atom(Atom::Symbol("quote".to_string())));
-        lst.push(read_from_tokens(tokens).unwrap());
-        return Ok(Exp::List(Rc::new(lst)));
+        Err(Exceptions::SyntaxError("Unexpected )".to_string()).at(Some(&span)))
+    } else if let Some(keyword) = quote_keyword(&token) {
+        let lst = vec![
+            Exp::Atom(Atom::Symbol(keyword.to_string())),
+            read_from_tokens(tokens).map_err(|e| e.at(Some(&span)))?,
+        ];
+        Ok(Exp::List(Rc::new(List::new(lst, Some(span)))))
     } else if token == "\"" {
-        let s = tokens[0].clone();
-        tokens.remove(0);
-        return Ok(Exp::Str(s));
+        let s = tokens.pop_front().map(|t| t.text).unwrap_or_default();
+        Ok(Exp::Str(Rc::new(Text::literal(s))))
     } else {
-        return Ok(Exp::Atom(atom(token)));
+        Ok(Exp::Atom(atom(token).map_err(|e| e.at(Some(&span)))?))
     }
 }
 
 fn _get_matching_parens_and_remaining(program: &str) -> Option<(&str, &str)> {
     if !program.starts_with("(") {
-        return None;
+        None
     } else {
         let mut stack: Vec<char> = vec![];
         let mut ends_at = 0;
@@ -122,22 +271,71 @@ fn _get_matching_parens_and_remaining(program: &str) -> Option<(&str, &str)> {
                 break;
             }
         }
-        return Some((&program[..ends_at], &program[ends_at..]));
+        Some((&program[..ends_at], &program[ends_at..]))
     }
 }
 
 pub fn parse(program: String) -> Result<Exp, Exceptions> {
-    let mut tokenized = tokenize(&program);
+    let mut tokenized: VecDeque<Token> = tokenize(&program, None)?.into();
     let ans = read_from_tokens(&mut tokenized);
-    if tokenized.is_empty() {
-        ans
-    } else {
-        Err(Exceptions::SyntaxError(
+    match tokenized.front() {
+        None => ans,
+        Some(t) => Err(Exceptions::SyntaxError(
             format!(
                 "Non matching parens or invalid expression : '{}'",
-                tokenized.join(" ")
+                tokenized
+                    .iter()
+                    .map(|t| t.text.as_str())
+                    .collect::<Vec<&str>>()
+                    .join(" ")
             )
             .to_string(),
-        ))
+        )
+        .at(Some(&t.span))),
+    }
+}
+
+/// Parses every top level expression in `program`. Spans of the resulting
+/// lists refer to `file`.
+pub fn parse_all(program: &str, file: &str) -> Result<Vec<Exp>, Exceptions> {
+    Ok(read_all(program, file)?
+        .into_iter()
+        .map(|(exp, _)| exp)
+        .collect())
+}
+
+/// Reads the first expression in `text`, along with how many characters
+/// it takes up, including the whitespace after it. `None` if there is only
+/// whitespace.
+pub fn read_prefix(text: &str) -> Result<Option<(Exp, usize)>, Exceptions> {
+    let mut tokens: VecDeque<Token> = tokenize(text, None)?.into();
+    if tokens.is_empty() {
+        return Ok(None);
+    }
+    let exp = read_from_tokens(&mut tokens)?;
+    let taken = match tokens.front() {
+        Some(t) => {
+            let lines: usize = text
+                .split('\n')
+                .take(t.span.line - 1)
+                .map(|line| line.chars().count() + 1)
+                .sum();
+            lines + t.span.column - 1
+        }
+        None => text.chars().count(),
+    };
+    Ok(Some((exp, taken)))
+}
+
+/// Like `parse_all`, along with where each expression starts, which atoms
+/// don't record themselves.
+pub(crate) fn read_all(program: &str, file: &str) -> Result<Vec<(Exp, Span)>, Exceptions> {
+    let file: Rc<str> = Rc::from(file);
+    let mut tokenized: VecDeque<Token> = tokenize(program, Some(&file))?.into();
+    let mut exps = vec![];
+    while let Some(t) = tokenized.front() {
+        let span = t.span.clone();
+        exps.push((read_from_tokens(&mut tokenized)?, span));
     }
+    Ok(exps)
 }
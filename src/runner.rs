@@ -2,12 +2,12 @@ use std::io::{self, Write};
 
 use std::process::exit;
 
-use super::default_env::*;
-use super::eval::parse_and_eval;
+use super::interpreter::Interpreter;
+use super::types::Exceptions;
 use rustyline::error::ReadlineError;
 use rustyline::Editor;
 
-const RISP_HISTORY_FILE: &'static str = ".risp_history";
+const RISP_HISTORY_FILE: &str = ".risp_history";
 
 fn add_to_history_rl(x: &str, rl: &mut Editor<()>) {
     rl.add_history_entry(x);
@@ -15,13 +15,13 @@ fn add_to_history_rl(x: &str, rl: &mut Editor<()>) {
 }
 
 enum ParensStatus {
-    MATCHING,
+    Matching,
     LeftParensMore,
     RightParensMore,
 }
 
 enum LineStatus {
-    EXIT,
+    Exit,
     GoNextLine(String),
     InvalidSExpr(String),
     ValidSExpr(String),
@@ -44,7 +44,7 @@ fn line_status(s: &str, stack: &mut Vec<char>) -> ParensStatus {
         }
     }
     if stack.is_empty() {
-        ParensStatus::MATCHING
+        ParensStatus::Matching
     } else {
         ParensStatus::LeftParensMore
     }
@@ -71,19 +71,13 @@ fn handle_first_line(
         }
     }
     if s.as_str() == "exit" {
-        return Some(LineStatus::EXIT);
+        Some(LineStatus::Exit)
     } else {
         let status = line_status(s.as_str(), stack);
         match status {
-            ParensStatus::MATCHING => {
-                return Some(LineStatus::ValidSExpr(s));
-            }
-            ParensStatus::LeftParensMore => {
-                return Some(LineStatus::GoNextLine(s));
-            }
-            ParensStatus::RightParensMore => {
-                return Some(LineStatus::InvalidSExpr(s));
-            }
+            ParensStatus::Matching => Some(LineStatus::ValidSExpr(s)),
+            ParensStatus::LeftParensMore => Some(LineStatus::GoNextLine(s)),
+            ParensStatus::RightParensMore => Some(LineStatus::InvalidSExpr(s)),
         }
     }
 }
@@ -104,15 +98,9 @@ fn handle_non_first_lines(
     s += &line.unwrap();
     let status = line_status(s.as_str(), stack);
     match status {
-        ParensStatus::MATCHING => {
-            return Some(LineStatus::ValidSExpr(s));
-        }
-        ParensStatus::LeftParensMore => {
-            return Some(LineStatus::GoNextLine(s));
-        }
-        ParensStatus::RightParensMore => {
-            return Some(LineStatus::InvalidSExpr(s));
-        }
+        ParensStatus::Matching => Some(LineStatus::ValidSExpr(s)),
+        ParensStatus::LeftParensMore => Some(LineStatus::GoNextLine(s)),
+        ParensStatus::RightParensMore => Some(LineStatus::InvalidSExpr(s)),
     }
 }
 
@@ -130,11 +118,9 @@ pub fn repl() -> Option<String> {
     let mut ret_none = false;
     let line = rl.readline(">>>");
     let first_line = handle_first_line(line, &mut stack);
-    if first_line.is_none() {
-        return None;
-    }
+    first_line.as_ref()?;
     match first_line.unwrap() {
-        LineStatus::EXIT => {
+        LineStatus::Exit => {
             add_to_history_rl("exit", &mut rl);
             println!("Exiting!!!");
             exit(0);
@@ -159,7 +145,7 @@ pub fn repl() -> Option<String> {
         }
         match line.unwrap() {
             // doesnt make sense
-            LineStatus::EXIT => unimplemented!(),
+            LineStatus::Exit => unimplemented!(),
             LineStatus::GoNextLine(x) => {
                 add_to_history_rl(x.as_str(), &mut rl);
                 s += &x;
@@ -179,63 +165,8 @@ pub fn repl() -> Option<String> {
     }
 }
 
-pub fn run_from_source_code(program: String) {
-    let mut def_env = default_env();
-    let mut lines: Vec<String> = program.lines().map(|x| x.to_string()).rev().collect();
-    let mut line_num = -1;
-
-    while !lines.is_empty() {
-        line_num += 1;
-        let mut stack: Vec<char> = Vec::new();
-        let mut s = String::new();
-
-        let first_line = lines
-            .last()
-            .map(|x| x.clone())
-            .ok_or(ReadlineError::Interrupted);
-        lines.pop();
-        let first_line = handle_first_line(first_line, &mut stack);
-        match first_line.unwrap() {
-            LineStatus::EXIT => {
-                exit(0);
-            }
-            LineStatus::GoNextLine(x) => {
-                s += &x;
-                s.push('\n');
-            }
-            LineStatus::ValidSExpr(x) | LineStatus::InvalidSExpr(x) => match x.as_str() {
-                "" => continue,
-                _ => {
-                    parse_and_eval(x, &mut def_env).unwrap();
-                    continue;
-                }
-            },
-        }
-
-        loop {
-            let next_line = lines
-                .last()
-                .map(|x| x.clone())
-                .ok_or(ReadlineError::Interrupted);
-            line_num += 1;
-            let next_line = handle_non_first_lines(next_line, &mut stack);
-            if next_line.is_none() {
-                panic!("Incomplete expression at line {}", line_num);
-            }
-            lines.pop();
-            match next_line.unwrap() {
-                // doesnt make sense
-                LineStatus::EXIT => unimplemented!(),
-                LineStatus::GoNextLine(x) => {
-                    s += &x;
-                    s.push('\n');
-                }
-                LineStatus::ValidSExpr(x) | LineStatus::InvalidSExpr(x) => {
-                    s += &x;
-                    parse_and_eval(s, &mut &mut def_env).unwrap();
-                    break;
-                }
-            }
-        }
-    }
+/// Expands and evaluates every expression in `program` in order, stopping
+/// at the first error. `file` is only used in the spans of errors.
+pub fn run_from_source_code(program: String, file: &str) -> Result<(), Exceptions> {
+    Interpreter::new().eval_source(&program, file).map(|_| ())
 }
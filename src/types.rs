@@ -1,5 +1,10 @@
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use num_traits::ToPrimitive;
+use std::cell::{Cell, Ref, RefCell};
 use std::collections::HashMap;
 use std::fmt;
+use std::ops::Deref;
 use std::rc::Rc;
 
 pub type Symbol = String;
@@ -9,10 +14,170 @@ pub type Env = HashMap<String, Exp>;
 pub enum Exceptions {
     ValueError(String),
     SyntaxError(String),
+    UnboundVariable(Symbol),
+    ArityMismatch {
+        name: String,
+        expected: String,
+        got: usize,
+    },
+    TypeError {
+        expected: String,
+        actual: Exp,
+    },
+    DivisionByZero,
+    /// Raised by risp code itself through `error`.
+    UserError {
+        message: String,
+        irritants: Vec<Exp>,
+    },
+    /// An arbitrary object passed to `raise` or `raise-continuable`.
+    Raised(Exp),
+    /// Another exception together with where it was raised.
+    Traced(Box<Exceptions>, Trace),
+}
+
+/// Where an exception was raised: the span of the innermost form being
+/// evaluated and the names of the procedures it propagated out of,
+/// innermost first. Procedures that were left through a tail call are no
+/// longer on the stack and so don't show up.
+#[derive(Debug, Clone, Default)]
+pub struct Trace {
+    pub span: Option<Span>,
+    pub stack: Vec<String>,
+}
+
+impl Exceptions {
+    pub fn type_error(expected: &str, actual: &Exp) -> Exceptions {
+        Exceptions::TypeError {
+            expected: expected.to_string(),
+            actual: actual.clone(),
+        }
+    }
+
+    fn traced(self) -> (Exceptions, Trace) {
+        match self {
+            Exceptions::Traced(e, trace) => (*e, trace),
+            e => (e, Trace::default()),
+        }
+    }
+
+    /// Attaches `span` to the exception unless it already carries one; the
+    /// innermost form that failed is the most useful one to report.
+    pub fn at(self, span: Option<&Span>) -> Exceptions {
+        match span {
+            Some(span) if self.span().is_none() => {
+                let (e, mut trace) = self.traced();
+                trace.span = Some(span.clone());
+                Exceptions::Traced(Box::new(e), trace)
+            }
+            _ => self,
+        }
+    }
+
+    /// Records that the exception propagated out of the body of the
+    /// procedure `name`.
+    pub fn in_procedure(self, name: &str) -> Exceptions {
+        let (e, mut trace) = self.traced();
+        trace.stack.push(name.to_string());
+        Exceptions::Traced(Box::new(e), trace)
+    }
+
+    pub fn span(&self) -> Option<&Span> {
+        match self {
+            Exceptions::Traced(_, trace) => trace.span.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// Names of the procedures the exception propagated out of, innermost
+    /// first.
+    pub fn stack(&self) -> &[String] {
+        match self {
+            Exceptions::Traced(_, trace) => &trace.stack,
+            _ => &[],
+        }
+    }
+
+    /// The exception with any location information stripped off.
+    pub fn root(&self) -> &Exceptions {
+        match self {
+            Exceptions::Traced(e, _) => e.root(),
+            e => e,
+        }
+    }
+}
+
+impl fmt::Display for Exceptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Exceptions::ValueError(msg) => write!(f, "{}", msg),
+            Exceptions::SyntaxError(msg) => write!(f, "syntax error: {}", msg),
+            Exceptions::UnboundVariable(name) => write!(f, "unbound variable: {}", name),
+            Exceptions::ArityMismatch {
+                name,
+                expected,
+                got,
+            } => write!(f, "{}: expected {} arguments, got {}", name, expected, got),
+            Exceptions::TypeError { expected, actual } => write!(
+                f,
+                "type error: expected {}, got {} {}",
+                expected,
+                actual.type_name(),
+                actual.written()
+            ),
+            Exceptions::DivisionByZero => write!(f, "division by zero"),
+            Exceptions::Raised(payload) => {
+                write!(f, "uncaught exception: {}", payload.written())
+            }
+            Exceptions::UserError { message, irritants } => {
+                write!(f, "{}", message)?;
+                for irritant in irritants {
+                    write!(f, " {}", irritant.written())?;
+                }
+                Ok(())
+            }
+            Exceptions::Traced(e, trace) => {
+                if let Some(span) = &trace.span {
+                    write!(f, "{}: ", span)?;
+                }
+                write!(f, "{}", e)?;
+                if !trace.stack.is_empty() {
+                    write!(f, "\nstack trace (most recent call first):")?;
+                    for name in &trace.stack {
+                        write!(f, "\n    {}", name)?;
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for Exceptions {}
+
+/// A position in source code. Lines and columns both start at 1.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Span {
+    pub file: Option<Rc<str>>,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.file {
+            Some(file) => write!(f, "{}:{}:{}", file, self.line, self.column),
+            None => write!(f, "{}:{}", self.line, self.column),
+        }
+    }
 }
 #[derive(Debug, Clone, PartialEq)]
 pub enum Number {
     Int(i64),
+    /// An exact integer outside the range of `i64`.
+    BigInt(BigInt),
+    /// An exact fraction in lowest terms, whose denominator is above 1.
+    Rational(BigRational),
     Float(f64),
 }
 #[derive(Debug, Clone, PartialEq)]
@@ -20,106 +185,434 @@ pub enum Atom {
     Bool(bool),
     Symbol(Symbol),
     Number(Number),
+    Char(char),
 }
 
+/// Characters with a name in `#\name` literals, which `write` also uses.
+pub const CHAR_NAMES: &[(&str, char)] = &[
+    ("alarm", '\u{7}'),
+    ("backspace", '\u{8}'),
+    ("delete", '\u{7f}'),
+    ("escape", '\u{1b}'),
+    ("newline", '\n'),
+    ("null", '\0'),
+    ("return", '\r'),
+    ("space", ' '),
+    ("tab", '\t'),
+];
+
 #[derive(Clone)]
 pub enum Exp {
     Atom(Atom),
-    List(Rc<Vec<Exp>>),
-    Str(String),
-    Func(fn(&[Exp]) -> Result<Exp, Exceptions>),
-    Procedure((Vec<String>, Box<Exp>)),
+    List(Rc<List>),
+    /// A string, which `string-set!` and `string-fill!` change in place.
+    Str(Rc<Text>),
+    /// A builtin. It gets the environment it is called from, so it can
+    /// call back into the evaluator with `eval::apply`.
+    Func(fn(&[Exp], &mut Environment) -> Result<Exp, Exceptions>),
+    /// A builtin implemented by a Rust closure, which unlike `Func` can
+    /// capture state. See `Environment::define_native`.
+    NativeClosure(Rc<NativeClosure>),
+    Procedure(Rc<Procedure>),
+    /// A caught exception, as seen by `guard` clauses and exception
+    /// handlers.
+    Condition(Rc<Exceptions>),
+    /// A macro bound with `define-syntax`. Only the expander looks at these.
+    Macro(Rc<Macro>),
+    /// A record, e.g. a Rust struct converted with `RispRecord`.
+    Record(Rc<Record>),
+    /// A string port, made by `open-output-string` or `open-input-string`.
+    Port(Rc<Port>),
+    /// The eof object, which reading from an input port gives at the end.
+    Eof,
+}
+
+pub enum Port {
+    /// Collects everything written to it, for `get-output-string`.
+    Output(RefCell<String>),
+    /// The characters of a string and how many of them have been read.
+    Input(Vec<char>, Cell<usize>),
+}
+
+pub enum Macro {
+    SyntaxRules(SyntaxRules),
+    /// A `define-macro` transformer: a procedure called with the
+    /// unevaluated arguments of the macro use, returning the form to use
+    /// instead.
+    Procedure(Exp),
+}
+
+/// A `syntax-rules` transformer. The first element of each pattern stands
+/// for the macro keyword and is ignored when matching.
+pub struct SyntaxRules {
+    pub ellipsis: Symbol,
+    pub literals: Vec<Symbol>,
+    pub rules: Vec<(Exp, Exp)>,
+    /// The aliases in the literals and rules, for a macro defined by a
+    /// macro template. They are forgotten once the expansion that made them
+    /// is done, but the macro may be used later.
+    pub aliases: HashMap<Symbol, Alias>,
+}
+
+/// A symbol introduced by a macro template, renamed so that it can't be
+/// confused with the symbols at the macro use site.
+#[derive(Clone)]
+pub struct Alias {
+    /// The symbol as written in the template.
+    pub name: Symbol,
+    /// How many scopes were visible where the macro was defined. The alias
+    /// is resolved there, not at the use site.
+    pub depth: usize,
+}
+
+/// A record value: the name of its type and its fields in order.
+#[derive(Clone, PartialEq)]
+pub struct Record {
+    pub name: Symbol,
+    pub fields: Vec<(Symbol, Exp)>,
+}
+
+impl Record {
+    pub fn new(name: &str, fields: Vec<(Symbol, Exp)>) -> Self {
+        Record {
+            name: name.to_string(),
+            fields,
+        }
+    }
+
+    /// `exp` as a record of type `name`, or a type error.
+    pub fn of<'a>(exp: &'a Exp, name: &str) -> Result<&'a Record, Exceptions> {
+        match exp {
+            Exp::Record(r) if r.name == name => Ok(r),
+            _ => Err(Exceptions::type_error(&format!("a {} record", name), exp)),
+        }
+    }
+
+    pub fn field(&self, field: &str) -> Result<&Exp, Exceptions> {
+        self.fields
+            .iter()
+            .find(|(name, _)| name == field)
+            .map(|(_, value)| value)
+            .ok_or_else(|| {
+                Exceptions::ValueError(format!("{} record has no field {}", self.name, field))
+            })
+    }
+}
+
+/// The characters of a string. They are borrowed through `Deref`, like a
+/// `RefCell<String>`.
+pub struct Text {
+    chars: RefCell<String>,
+    /// Set for string literals, which are part of the program and so can't
+    /// be changed.
+    pub literal: bool,
+}
+
+impl Text {
+    pub fn new(chars: String) -> Text {
+        Text {
+            chars: RefCell::new(chars),
+            literal: false,
+        }
+    }
+
+    pub fn literal(chars: String) -> Text {
+        Text {
+            chars: RefCell::new(chars),
+            literal: true,
+        }
+    }
+}
+
+impl Deref for Text {
+    type Target = RefCell<String>;
+
+    fn deref(&self) -> &RefCell<String> {
+        &self.chars
+    }
+}
+
+impl PartialEq for Text {
+    fn eq(&self, other: &Text) -> bool {
+        self.chars == other.chars
+    }
+}
+
+/// The elements of a list, along with the span of its opening paren when
+/// the list was read from source code.
+#[derive(Clone)]
+pub struct List {
+    items: Vec<Exp>,
+    pub span: Option<Span>,
+    /// Where each item starts, when the list was read from source code.
+    /// Nested lists have their own span, but atoms don't.
+    item_spans: Vec<Span>,
+}
+
+impl List {
+    pub fn new(items: Vec<Exp>, span: Option<Span>) -> Self {
+        List {
+            items,
+            span,
+            item_spans: vec![],
+        }
+    }
+
+    /// A list read from source code, with the span of each item.
+    pub fn read(items: Vec<Exp>, span: Span, item_spans: Vec<Span>) -> Self {
+        List {
+            items,
+            span: Some(span),
+            item_spans,
+        }
+    }
+
+    /// `items` in place of the items of this list, keeping the spans. Each
+    /// of them has to stand for the item at the same position.
+    pub fn with_items(&self, items: Vec<Exp>) -> Self {
+        List {
+            items,
+            span: self.span.clone(),
+            item_spans: self.item_spans.clone(),
+        }
+    }
+
+    /// Where the `i`th item starts, if known.
+    pub fn item_span(&self, i: usize) -> Option<&Span> {
+        self.item_spans.get(i)
+    }
+}
+
+impl From<Vec<Exp>> for List {
+    fn from(items: Vec<Exp>) -> Self {
+        List::new(items, None)
+    }
+}
+
+impl Deref for List {
+    type Target = Vec<Exp>;
+    fn deref(&self) -> &Vec<Exp> {
+        &self.items
+    }
+}
+
+/// Spans are not part of a list's value.
+impl PartialEq for List {
+    fn eq(&self, other: &Self) -> bool {
+        self.items == other.items
+    }
+}
+
+/// A user defined procedure together with the environment it was created
+/// in, so free variables in `body` resolve lexically.
+#[derive(Clone)]
+pub struct Procedure {
+    /// The name the procedure was defined with, for stack traces.
+    pub name: Option<String>,
+    pub params: Vec<String>,
+    /// Bound to a list of the arguments left over after `params`, for
+    /// procedures taking a variable number of arguments.
+    pub rest: Option<String>,
+    pub body: Box<Exp>,
+    pub env: Environment,
+}
+
+impl Procedure {
+    pub fn display_name(&self) -> &str {
+        self.name.as_deref().unwrap_or("<lambda>")
+    }
+}
+
+/// The number of arguments a native closure accepts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Arity {
+    Exactly(usize),
+    AtLeast(usize),
+}
+
+impl Arity {
+    pub fn accepts(&self, n: usize) -> bool {
+        match *self {
+            Arity::Exactly(x) => n == x,
+            Arity::AtLeast(x) => n >= x,
+        }
+    }
+}
+
+impl From<usize> for Arity {
+    fn from(n: usize) -> Self {
+        Arity::Exactly(n)
+    }
+}
+
+impl fmt::Display for Arity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Arity::Exactly(n) => write!(f, "{}", n),
+            Arity::AtLeast(n) => write!(f, "at least {}", n),
+        }
+    }
+}
+
+pub type NativeFn = dyn Fn(&[Exp], &mut Environment) -> Result<Exp, Exceptions>;
+
+pub struct NativeClosure {
+    pub name: String,
+    pub arity: Arity,
+    pub func: Box<NativeFn>,
+}
+
+impl NativeClosure {
+    /// Checks the number of arguments and calls the closure.
+    pub fn call(&self, args: &[Exp], env: &mut Environment) -> Result<Exp, Exceptions> {
+        if !self.arity.accepts(args.len()) {
+            return Err(Exceptions::ArityMismatch {
+                name: self.name.clone(),
+                expected: self.arity.to_string(),
+                got: args.len(),
+            });
+        }
+        (self.func)(args, env)
+    }
 }
 
 // credits : https://www.reddit.com/r/rust/comments/3vchld/how_to_check_if_two_borrowed_objects_are_the_same/
 fn _is_same_object<T>(a: &T, b: &T) -> bool {
-    a as *const T == b as *const T
+    std::ptr::eq(a, b)
 }
+/// Values of different types are never equal. Procedures, conditions,
+/// macros and ports are compared by identity, records by their fields.
 impl PartialEq for Exp {
     fn eq(&self, other: &Self) -> bool {
-        match &self {
-            Exp::Func(x) => {
-                if let Exp::Func(other_at) = &other {
-                    return (*other_at as usize) == (*x as usize);
-                } else {
-                    panic!("Mismatch in types in lhs and rhs, lhs is a Func but rhs is not");
-                }
-            }
-            Exp::Procedure(p) => {
-                if let Exp::Procedure(other_at) = &other {
-                    return p == other_at;
-                } else {
-                    panic!("Mismatch in types in lhs and rhs, lhs is a Procedure but rhs is not");
-                }
-            }
-            Exp::Atom(at) => {
-                if let Exp::Atom(other_at) = other {
-                    return at == other_at;
-                } else {
-                    panic!("Mismatch in types in lhs and rhs, lhs is an Atom but rhs is not");
-                }
-            }
-            Exp::Str(s) => {
-                if let Exp::Str(t) = other {
-                    return s == t;
-                } else {
-                    panic!("Mismatch in types in lhs and rhs, lhs is a string but rhs is not");
-                }
-            }
-            Exp::List(lst) => {
-                if let Exp::List(other_lst) = other {
-                    return lst == other_lst;
-                } else {
-                    panic!("Mismatch in types in lhs and rhs, lhs is a list but rhs is not");
-                }
-            }
+        match (self, other) {
+            (Exp::Func(x), Exp::Func(y)) => *x as usize == *y as usize,
+            (Exp::NativeClosure(x), Exp::NativeClosure(y)) => Rc::ptr_eq(x, y),
+            (Exp::Procedure(p), Exp::Procedure(q)) => Rc::ptr_eq(p, q),
+            (Exp::Condition(p), Exp::Condition(q)) => Rc::ptr_eq(p, q),
+            (Exp::Macro(p), Exp::Macro(q)) => Rc::ptr_eq(p, q),
+            (Exp::Record(r), Exp::Record(s)) => r == s,
+            (Exp::Port(p), Exp::Port(q)) => Rc::ptr_eq(p, q),
+            (Exp::Eof, Exp::Eof) => true,
+            (Exp::Atom(a), Exp::Atom(b)) => a == b,
+            (Exp::Str(s), Exp::Str(t)) => s == t,
+            (Exp::List(lst), Exp::List(other_lst)) => lst == other_lst,
+            _ => false,
         }
     }
 }
 
+/// A single scope: the bindings introduced by one `define`-level, `lambda`
+/// call or the global environment, plus a link to the enclosing scope.
+struct Frame {
+    vars: Env,
+    parent: Option<Environment>,
+}
+
+/// A shareable handle to a chain of frames.
+///
+/// Cloning an `Environment` is cheap and yields a handle to the *same*
+/// frame, which is what lets procedures capture the environment they were
+/// created in and keep it alive after the defining call has returned.
+#[derive(Clone)]
 pub struct Environment {
-    maps: Vec<Env>,
+    frame: Rc<RefCell<Frame>>,
+    /// Handlers installed by `with-exception-handler`, innermost last.
+    /// They are dynamically rather than lexically scoped, so every frame
+    /// descending from the same root shares one stack.
+    handlers: Rc<RefCell<Vec<Exp>>>,
+}
+impl Default for Environment {
+    fn default() -> Self {
+        Self::new()
+    }
 }
+
 impl Environment {
     pub fn new() -> Self {
         Environment {
-            maps: vec![HashMap::new()],
+            frame: Rc::new(RefCell::new(Frame {
+                vars: HashMap::new(),
+                parent: None,
+            })),
+            handlers: Rc::new(RefCell::new(vec![])),
         }
     }
 
-    pub fn insert(&mut self, key: String, val: Exp) -> Option<Exp> {
-        self.maps.last_mut().unwrap().insert(key, val)
+    /// Creates a new child frame holding `vars` whose parent is `self`.
+    pub fn extend(&self, vars: Env) -> Environment {
+        Environment {
+            frame: Rc::new(RefCell::new(Frame {
+                vars,
+                parent: Some(self.clone()),
+            })),
+            handlers: Rc::clone(&self.handlers),
+        }
     }
 
-    pub fn get(&self, key: &String) -> Option<&Exp> {
-        let i = &self.maps.len();
-        for map_index in (0..(*i)).rev() {
-            if let Some(x) = self.maps[map_index].get(key) {
-                return Some(x);
-            } else {
-                continue;
-            }
-        }
-        return None;
+    /// Binds `name` to a builtin implemented by the closure `func`, which
+    /// is called with the evaluated arguments once their number has been
+    /// checked against `arity`.
+    pub fn define_native<F>(&self, name: &str, arity: impl Into<Arity>, func: F)
+    where
+        F: Fn(&[Exp], &mut Environment) -> Result<Exp, Exceptions> + 'static,
+    {
+        let native = NativeClosure {
+            name: name.to_string(),
+            arity: arity.into(),
+            func: Box::new(func),
+        };
+        self.insert(name.to_string(), Exp::NativeClosure(Rc::new(native)));
     }
 
-    pub fn push_stack_frame(&mut self, new_frame_data: Env) {
-        self.maps.push(new_frame_data);
+    /// Binds `key` in the innermost frame, shadowing any outer binding.
+    pub fn insert(&self, key: String, val: Exp) -> Option<Exp> {
+        self.frame.borrow_mut().vars.insert(key, val)
     }
-    pub fn pop_stack_frame(&mut self) {
-        self.maps.pop();
+
+    pub fn get(&self, key: &str) -> Option<Exp> {
+        let mut env = self.clone();
+        loop {
+            let parent = {
+                let frame = env.frame.borrow();
+                if let Some(x) = frame.vars.get(key) {
+                    return Some(x.clone());
+                }
+                frame.parent.clone()
+            };
+            env = parent?;
+        }
     }
 
-    pub fn exists(&self, key: &String) -> bool {
-        let i = &self.maps.len();
-        for map_index in (0..(*i)).rev() {
-            if self.maps[map_index].get(key).is_some() {
-                return true;
-            } else {
-                continue;
+    /// Rebinds `key` in the nearest frame that already defines it.
+    /// Returns `false` if `key` is not bound anywhere in the chain.
+    pub fn set(&self, key: &str, val: Exp) -> bool {
+        let mut env = self.clone();
+        loop {
+            let parent = {
+                let mut frame = env.frame.borrow_mut();
+                if let Some(x) = frame.vars.get_mut(key) {
+                    *x = val;
+                    return true;
+                }
+                frame.parent.clone()
+            };
+            match parent {
+                Some(p) => env = p,
+                None => return false,
             }
         }
-        return false;
+    }
+
+    pub fn exists(&self, key: &str) -> bool {
+        self.get(key).is_some()
+    }
+
+    pub fn push_handler(&self, handler: Exp) {
+        self.handlers.borrow_mut().push(handler);
+    }
+
+    pub fn pop_handler(&self) -> Option<Exp> {
+        self.handlers.borrow_mut().pop()
     }
 }
 
@@ -132,6 +625,7 @@ impl ToFloat for Exp {
         if let Exp::Atom(Atom::Number(y)) = self {
             match y {
                 Number::Int(z) => return Some(*z as f64),
+                Number::BigInt(_) | Number::Rational(_) => return Some(y.to_f64()),
                 Number::Float(z) => return Some(*z),
             }
         }
@@ -146,6 +640,8 @@ impl ToInt for Exp {
         if let Exp::Atom(Atom::Number(y)) = self {
             match y {
                 Number::Int(z) => return Some(*z),
+                Number::BigInt(_) => return None,
+                Number::Rational(z) => return z.to_integer().to_i64(),
                 Number::Float(z) => return Some(*z as i64),
             }
         }
@@ -166,38 +662,458 @@ impl ToBool for Exp {
 }
 pub fn get_bool(x: &Exp) -> Result<bool, Exceptions> {
     x.to_bool()
-        .ok_or(Exceptions::ValueError("Not a boolean".to_string()))
+        .ok_or_else(|| Exceptions::type_error("a boolean", x))
 }
 pub fn get_float(x: &Exp) -> Result<f64, Exceptions> {
     x.to_f64()
-        .ok_or(Exceptions::ValueError("Not a number".to_string()))
+        .ok_or_else(|| Exceptions::type_error("a number", x))
 }
 pub fn get_int(x: &Exp) -> Result<i64, Exceptions> {
     x.to_i64()
-        .ok_or(Exceptions::ValueError("Not a number".to_string()))
+        .ok_or_else(|| Exceptions::type_error("a number", x))
+}
+pub fn get_number(x: &Exp) -> Result<&Number, Exceptions> {
+    match x {
+        Exp::Atom(Atom::Number(n)) => Ok(n),
+        _ => Err(Exceptions::type_error("a number", x)),
+    }
+}
+pub fn get_integer(x: &Exp) -> Result<&Number, Exceptions> {
+    match x {
+        Exp::Atom(Atom::Number(n)) if n.is_integer() => Ok(n),
+        _ => Err(Exceptions::type_error("an integer", x)),
+    }
+}
+pub fn get_char(x: &Exp) -> Result<char, Exceptions> {
+    match x {
+        Exp::Atom(Atom::Char(c)) => Ok(*c),
+        _ => Err(Exceptions::type_error("a character", x)),
+    }
+}
+pub fn get_string(x: &Exp) -> Result<Ref<'_, String>, Exceptions> {
+    match x {
+        Exp::Str(s) => Ok(s.borrow()),
+        _ => Err(Exceptions::type_error("a string", x)),
+    }
+}
+/// An exact non-negative integer, as used for string indices.
+pub fn get_index(x: &Exp) -> Result<usize, Exceptions> {
+    match x {
+        Exp::Atom(Atom::Number(Number::Int(n))) if *n >= 0 => Ok(*n as usize),
+        _ => Err(Exceptions::type_error("an exact non-negative integer", x)),
+    }
+}
+
+/// Conversion of a risp value into a Rust value, failing with a type error
+/// when the value has the wrong type.
+pub trait FromExp: Sized {
+    fn from_exp(exp: &Exp) -> Result<Self, Exceptions>;
+}
+
+/// Conversion of a Rust value into a risp value.
+pub trait IntoExp {
+    fn into_exp(self) -> Exp;
+}
+
+/// The arguments of a call from Rust: a tuple of values that can be
+/// converted, `()` for no arguments, or already converted values.
+pub trait IntoArgs {
+    fn into_args(self) -> Vec<Exp>;
+}
+
+impl FromExp for Exp {
+    fn from_exp(exp: &Exp) -> Result<Self, Exceptions> {
+        Ok(exp.clone())
+    }
+}
+
+impl IntoExp for Exp {
+    fn into_exp(self) -> Exp {
+        self
+    }
+}
+
+impl FromExp for Number {
+    fn from_exp(exp: &Exp) -> Result<Self, Exceptions> {
+        get_number(exp).cloned()
+    }
+}
+
+impl IntoExp for Number {
+    fn into_exp(self) -> Exp {
+        Exp::Atom(Atom::Number(self))
+    }
+}
+
+impl FromExp for i64 {
+    fn from_exp(exp: &Exp) -> Result<Self, Exceptions> {
+        match exp {
+            Exp::Atom(Atom::Number(Number::Int(x))) => Ok(*x),
+            _ => Err(Exceptions::type_error("an integer", exp)),
+        }
+    }
+}
+
+impl IntoExp for i64 {
+    fn into_exp(self) -> Exp {
+        Exp::Atom(Atom::Number(Number::Int(self)))
+    }
+}
+
+impl IntoExp for i32 {
+    fn into_exp(self) -> Exp {
+        i64::from(self).into_exp()
+    }
+}
+
+impl FromExp for BigInt {
+    fn from_exp(exp: &Exp) -> Result<Self, Exceptions> {
+        match exp {
+            Exp::Atom(Atom::Number(n)) => n
+                .to_bigint()
+                .ok_or_else(|| Exceptions::type_error("an integer", exp)),
+            _ => Err(Exceptions::type_error("an integer", exp)),
+        }
+    }
+}
+
+impl IntoExp for BigInt {
+    fn into_exp(self) -> Exp {
+        Number::from_bigint(self).into_exp()
+    }
+}
+
+impl FromExp for f64 {
+    fn from_exp(exp: &Exp) -> Result<Self, Exceptions> {
+        get_float(exp)
+    }
+}
+
+impl IntoExp for f64 {
+    fn into_exp(self) -> Exp {
+        Exp::Atom(Atom::Number(Number::Float(self)))
+    }
+}
+
+impl FromExp for bool {
+    fn from_exp(exp: &Exp) -> Result<Self, Exceptions> {
+        get_bool(exp)
+    }
+}
+
+impl IntoExp for bool {
+    fn into_exp(self) -> Exp {
+        Exp::Atom(Atom::Bool(self))
+    }
+}
+
+impl FromExp for char {
+    fn from_exp(exp: &Exp) -> Result<Self, Exceptions> {
+        get_char(exp)
+    }
+}
+
+impl IntoExp for char {
+    fn into_exp(self) -> Exp {
+        Exp::Atom(Atom::Char(self))
+    }
+}
+
+impl FromExp for String {
+    fn from_exp(exp: &Exp) -> Result<Self, Exceptions> {
+        match exp {
+            Exp::Str(s) => Ok(s.borrow().clone()),
+            _ => Err(Exceptions::type_error("a string", exp)),
+        }
+    }
+}
+
+impl IntoExp for String {
+    fn into_exp(self) -> Exp {
+        Exp::Str(Rc::new(Text::new(self)))
+    }
+}
+
+impl IntoExp for &str {
+    fn into_exp(self) -> Exp {
+        self.to_string().into_exp()
+    }
+}
+
+impl<T: FromExp> FromExp for Vec<T> {
+    fn from_exp(exp: &Exp) -> Result<Self, Exceptions> {
+        match exp {
+            Exp::List(items) => items.iter().map(T::from_exp).collect(),
+            _ => Err(Exceptions::type_error("a list", exp)),
+        }
+    }
+}
+
+impl<T: IntoExp> IntoExp for Vec<T> {
+    fn into_exp(self) -> Exp {
+        let items: Vec<Exp> = self.into_iter().map(IntoExp::into_exp).collect();
+        Exp::List(Rc::new(items.into()))
+    }
+}
+
+/// `#f` is `None`, as in Scheme procedures that return a value or `#f`.
+impl<T: FromExp> FromExp for Option<T> {
+    fn from_exp(exp: &Exp) -> Result<Self, Exceptions> {
+        match exp {
+            Exp::Atom(Atom::Bool(false)) => Ok(None),
+            _ => T::from_exp(exp).map(Some),
+        }
+    }
+}
+
+impl<T: IntoExp> IntoExp for Option<T> {
+    fn into_exp(self) -> Exp {
+        match self {
+            Some(x) => x.into_exp(),
+            None => Exp::Atom(Atom::Bool(false)),
+        }
+    }
+}
+
+impl IntoExp for () {
+    fn into_exp(self) -> Exp {
+        Exp::List(Rc::new(vec![].into()))
+    }
+}
+
+impl IntoArgs for () {
+    fn into_args(self) -> Vec<Exp> {
+        vec![]
+    }
 }
 
+impl IntoArgs for Vec<Exp> {
+    fn into_args(self) -> Vec<Exp> {
+        self
+    }
+}
+
+/// Tuples are lists of a fixed length.
+macro_rules! tuple_conversions {
+    ($len:literal; $($name:ident)+) => {
+        impl<$($name: FromExp),+> FromExp for ($($name,)+) {
+            fn from_exp(exp: &Exp) -> Result<Self, Exceptions> {
+                match exp {
+                    Exp::List(items) if items.len() == $len => {
+                        let mut items = items.iter();
+                        Ok(($(<$name as FromExp>::from_exp(items.next().unwrap())?,)+))
+                    }
+                    _ => Err(Exceptions::type_error(concat!("a list of length ", $len), exp)),
+                }
+            }
+        }
+
+        #[allow(non_snake_case)]
+        impl<$($name: IntoExp),+> IntoExp for ($($name,)+) {
+            fn into_exp(self) -> Exp {
+                Exp::List(Rc::new(self.into_args().into()))
+            }
+        }
+
+        #[allow(non_snake_case)]
+        impl<$($name: IntoExp),+> IntoArgs for ($($name,)+) {
+            fn into_args(self) -> Vec<Exp> {
+                let ($($name,)+) = self;
+                vec![$($name.into_exp()),+]
+            }
+        }
+    };
+}
+
+tuple_conversions!(1; A);
+tuple_conversions!(2; A B);
+tuple_conversions!(3; A B C);
+tuple_conversions!(4; A B C D);
+
+/// A Rust struct exposed to risp as a record, usually implemented with
+/// `#[derive(RispValue)]` from the `derive` feature.
+pub trait RispRecord: FromExp + IntoExp + 'static {
+    /// The name of the record type, e.g. `server-config` for `ServerConfig`.
+    const NAME: &'static str;
+    /// The names of the fields, in declaration order.
+    const FIELDS: &'static [&'static str];
+
+    /// Defines the constructor `make-NAME`, the predicate `NAME?` and an
+    /// accessor `NAME-FIELD` for every field. The constructor checks the
+    /// types of the fields by converting the record to `Self`.
+    fn define_procedures(env: &Environment) {
+        env.define_native(
+            &format!("make-{}", Self::NAME),
+            Self::FIELDS.len(),
+            |args, _| {
+                let fields = Self::FIELDS
+                    .iter()
+                    .map(|field| field.to_string())
+                    .zip(args.iter().cloned())
+                    .collect();
+                let record = Exp::Record(Rc::new(Record::new(Self::NAME, fields)));
+                Ok(Self::from_exp(&record)?.into_exp())
+            },
+        );
+        env.define_native(&format!("{}?", Self::NAME), 1, |args, _| {
+            Ok(Exp::Atom(Atom::Bool(
+                Record::of(&args[0], Self::NAME).is_ok(),
+            )))
+        });
+        for field in Self::FIELDS {
+            env.define_native(&format!("{}-{}", Self::NAME, field), 1, move |args, _| {
+                Ok(Record::of(&args[0], Self::NAME)?.field(field)?.clone())
+            });
+        }
+    }
+}
+
+impl fmt::Display for Number {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Number::Int(x) => write!(f, "{}", x),
+            Number::BigInt(x) => write!(f, "{}", x),
+            Number::Rational(x) => write!(f, "{}", x),
+            Number::Float(x) if x.is_nan() => write!(f, "+nan.0"),
+            Number::Float(x) if x.is_infinite() && *x > 0.0 => write!(f, "+inf.0"),
+            Number::Float(x) if x.is_infinite() => write!(f, "-inf.0"),
+            // Debug always shows a decimal point or an exponent, unlike
+            // Display. The point is added to an exponent form like `1e21`
+            // too, so that floats are never written like integers.
+            Number::Float(x) => {
+                let s = format!("{:?}", x);
+                match s.split_once('e') {
+                    Some((mantissa, exponent)) if !mantissa.contains('.') => {
+                        write!(f, "{}.0e{}", mantissa, exponent)
+                    }
+                    _ => write!(f, "{}", s),
+                }
+            }
+        }
+    }
+}
+
+/// Shows a value the way `display` prints it, with strings as their bare
+/// contents. `Exp::written` shows it the way `write` does.
 impl fmt::Display for Exp {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let s: String = match self {
+        self.fmt_as(f, false)
+    }
+}
+
+/// A value shown the way `write` prints it, as it would be read back in:
+/// strings in double quotes, including those nested in lists and records.
+pub struct Written<'a>(&'a Exp);
+
+impl fmt::Display for Written<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt_as(f, true)
+    }
+}
+
+impl Exp {
+    pub fn written(&self) -> Written<'_> {
+        Written(self)
+    }
+
+    fn fmt_as(&self, f: &mut fmt::Formatter<'_>, write: bool) -> fmt::Result {
+        match self {
             Exp::Atom(x) => match x {
-                Atom::Symbol(y) => y.clone(),
-                Atom::Number(Number::Int(y)) => y.to_string(),
-                Atom::Number(Number::Float(y)) => y.to_string(),
+                Atom::Symbol(y) => write!(f, "{}", y),
+                Atom::Number(y) => write!(f, "{}", y),
                 Atom::Bool(x) => match x {
-                    true => "#t".to_string(),
-                    false => "#f".to_string(),
+                    true => write!(f, "#t"),
+                    false => write!(f, "#f"),
                 },
+                Atom::Char(c) if write => match CHAR_NAMES.iter().find(|(_, named)| named == c) {
+                    Some((name, _)) => write!(f, "#\\{}", name),
+                    None if c.is_control() => write!(f, "#\\x{:x}", *c as u32),
+                    None => write!(f, "#\\{}", c),
+                },
+                Atom::Char(c) => write!(f, "{}", c),
             },
             Exp::List(x) => {
-                let str_form: Vec<String> =
-                    x.iter().map(|a| format!("{}", a).to_string()).collect();
-                "(".to_string() + &str_form.join(" ") + ")"
+                write!(f, "(")?;
+                for (i, a) in x.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    a.fmt_as(f, write)?;
+                }
+                write!(f, ")")
             }
-            Exp::Str(s) => s.clone(),
-            Exp::Func(_) => "Func".to_string(),
-            Exp::Procedure(_) => "Proc".to_string(),
-        };
-        write!(f, "{}", s)
+            Exp::Str(s) if write => {
+                write!(f, "\"")?;
+                for c in s.borrow().chars() {
+                    match c {
+                        '"' | '\\' => write!(f, "\\{}", c)?,
+                        '\n' => write!(f, "\\n")?,
+                        '\t' => write!(f, "\\t")?,
+                        '\r' => write!(f, "\\r")?,
+                        '\u{7}' => write!(f, "\\a")?,
+                        '\u{8}' => write!(f, "\\b")?,
+                        c if c.is_control() => write!(f, "\\x{:x};", c as u32)?,
+                        c => write!(f, "{}", c)?,
+                    }
+                }
+                write!(f, "\"")
+            }
+            Exp::Str(s) => write!(f, "{}", s.borrow()),
+            Exp::Func(_) | Exp::NativeClosure(_) => write!(f, "Func"),
+            Exp::Procedure(_) => write!(f, "Proc"),
+            Exp::Condition(e) => write!(f, "#<error-object {}>", e.root()),
+            Exp::Macro(_) => write!(f, "Macro"),
+            Exp::Record(r) => {
+                write!(f, "#<{}", r.name)?;
+                for (name, value) in r.fields.iter() {
+                    write!(f, " {}=", name)?;
+                    value.fmt_as(f, write)?;
+                }
+                write!(f, ">")
+            }
+            Exp::Port(p) => match **p {
+                Port::Output(_) => write!(f, "#<output-port>"),
+                Port::Input(..) => write!(f, "#<input-port>"),
+            },
+            Exp::Eof => write!(f, "#<eof-object>"),
+        }
+    }
+}
+
+impl Exp {
+    pub fn is_procedure(&self) -> bool {
+        matches!(
+            self,
+            Exp::Func(_) | Exp::NativeClosure(_) | Exp::Procedure(_)
+        )
+    }
+
+    /// Everything except `#f` counts as true in a test.
+    pub fn is_truthy(&self) -> bool {
+        !matches!(self, Exp::Atom(Atom::Bool(false)))
+    }
+
+    /// The name of the type of the value, as used in error messages.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Exp::Atom(Atom::Bool(_)) => "boolean",
+            Exp::Atom(Atom::Symbol(_)) => "symbol",
+            Exp::Atom(Atom::Number(_)) => "number",
+            Exp::Atom(Atom::Char(_)) => "character",
+            Exp::List(_) => "list",
+            Exp::Str(_) => "string",
+            Exp::Func(_) | Exp::NativeClosure(_) | Exp::Procedure(_) => "procedure",
+            Exp::Condition(_) => "error-object",
+            Exp::Macro(_) => "macro",
+            Exp::Record(_) => "record",
+            Exp::Port(_) => "port",
+            Exp::Eof => "eof-object",
+        }
+    }
+}
+
+impl fmt::Debug for Exp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self)
     }
 }